@@ -1,9 +1,16 @@
 #!/bin/sh
 
+# The first serial port is the PL011, the second the mini UART.
+if [ "$UART" = pl011 ]; then
+    SERIAL="-serial pty"
+else
+    SERIAL="-serial null -serial pty"
+fi
+
 TOP=$(git rev-parse --show-toplevel)
 $TOP/bin/qemu-system-aarch64 \
     -nographic \
     -M raspi3 \
-    -serial null -serial pty \
+    $SERIAL \
     -kernel \
     "$@"
@@ -10,6 +10,7 @@ mod init;
 use xmodem::Xmodem;
 use core::time::Duration;
 use pi;
+use pi::uart::Uart;
 use core;
 
 /// Start address of the binary to load and of the bootloader.
@@ -22,6 +23,13 @@ const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 /// Free space between the bootloader and the loaded binary's start address.
 const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
 
+/// The UART the binary is received on. The mini UART is used unless the
+/// bootloader is built with the `pl011` feature.
+#[cfg(not(feature = "pl011"))]
+type BootUart = pi::uart::MiniUart;
+#[cfg(feature = "pl011")]
+type BootUart = pi::uart::Pl011;
+
 /// Branches to the address `addr` unconditionally.
 unsafe fn jump_to(addr: *mut u8) -> ! {
     asm!("br $0" : : "r"(addr as usize));
@@ -34,7 +42,7 @@ fn kmain() -> ! {
     let mut receive_buf = unsafe {
         core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE)
     };
-    let mut uart = pi::uart::MiniUart::new();
+    let mut uart = BootUart::new();
     uart.set_read_timeout(Duration::from_millis(750));
 
     loop {
@@ -1,4 +1,5 @@
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/layout.ld");
     println!("cargo:rerun-if-env-changed=LOG_LEVEL");
+    println!("cargo:rerun-if-env-changed=NET_DEVICE");
 }
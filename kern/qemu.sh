@@ -1,10 +1,17 @@
 #!/bin/sh
 
+# The first serial port is the PL011, the second the mini UART.
+if [ "$UART" = pl011 ]; then
+    SERIAL="-serial mon:stdio"
+else
+    SERIAL="-serial null -serial mon:stdio"
+fi
+
 #TOP=$(git rev-parse --show-toplevel)
 #$TOP/bin/qemu-system-aarch64 \
 qemu-system-aarch64 \
     -nographic \
     -M raspi3 \
-    -serial null -serial mon:stdio \
+    $SERIAL \
     -kernel \
     "$@"
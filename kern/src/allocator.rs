@@ -2,19 +2,34 @@ mod linked_list;
 pub mod util;
 
 mod bin;
+mod buddy;
 mod bump;
+#[cfg(any(test, feature = "heap-check"))]
+pub mod check;
+pub mod frame;
+#[cfg(feature = "leak-tracking")]
+pub mod leaks;
+pub mod slab;
+pub mod stats;
 
-type AllocatorImpl = bin::Allocator;
+type AllocatorImpl = buddy::Allocator;
 
 #[cfg(test)]
 mod tests;
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
+#[cfg(feature = "heap-check")]
+use core::ptr;
 
 use crate::mutex::Mutex;
+use crate::param::{KERN_HEAP_SIZE, PAGE_SIZE};
+use crate::FRAMES;
 use pi::atags::{Atag, Atags};
 
+use self::frame::{FrameAllocator, FrameStats};
+use self::stats::HeapStats;
+
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
 pub trait LocalAlloc {
@@ -23,6 +38,10 @@ pub trait LocalAlloc {
 }
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
+///
+/// Allocations of the layout of one of the `slab::CACHES` are served by that
+/// cache instead. With the `heap-check` feature, every block is put between
+/// red zones and poisoned when freed to catch heap corruption (see `check`).
 pub struct Allocator(Mutex<Option<AllocatorImpl>>);
 
 impl Allocator {
@@ -34,35 +53,157 @@ impl Allocator {
         Allocator(Mutex::new(None))
     }
 
-    /// Initializes the memory allocator.
+    /// Initializes the memory allocator with a heap of `KERN_HEAP_SIZE` bytes,
+    /// or half of the physical memory if that is less, carved out of `FRAMES`.
     /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization.
+    /// kernel initialization, after `FRAMES` is initialized.
     ///
     /// # Panics
     ///
-    /// Panics if the system's memory map could not be retrieved.
+    /// Panics if the frames for the heap could not be allocated.
     pub unsafe fn initialize(&self) {
-        let (start, end) = memory_map().expect("failed to find memory map");
+        let frames = (KERN_HEAP_SIZE / PAGE_SIZE).min(FRAMES.stats().total / 2);
+        let start = FRAMES
+            .alloc_contiguous(frames)
+            .expect("failed to allocate the kernel heap");
+        let end = start + frames * PAGE_SIZE;
         info!("heap beg: {:x}, end: {:x}", start, end);
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
-}
 
-unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// Allocates a block of `layout` from the cache serving it, or else from
+    /// the heap.
+    unsafe fn alloc_block(&self, layout: Layout) -> *mut u8 {
+        match slab::cache_for(&layout) {
+            Some(cache) => cache.alloc(),
+            None => self
+                .0
+                .lock()
+                .as_mut()
+                .expect("allocator uninitialized")
+                .alloc(layout),
+        }
+    }
+
+    /// Frees a block allocated with `alloc_block()`.
+    unsafe fn dealloc_block(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(cache) = slab::cache_for(&layout) {
+            return cache.dealloc(ptr);
+        }
         self.0
             .lock()
             .as_mut()
             .expect("allocator uninitialized")
-            .alloc(layout)
+            .dealloc(ptr, layout);
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Returns the statistics of the heap. Allocations served by the
+    /// `slab::CACHES` are not part of them.
+    pub fn stats(&self) -> HeapStats {
         self.0
             .lock()
-            .as_mut()
+            .as_ref()
             .expect("allocator uninitialized")
-            .dealloc(ptr, layout);
+            .stats()
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(not(feature = "heap-check"))]
+        let ptr = self.alloc_block(layout);
+        #[cfg(feature = "heap-check")]
+        let ptr = check::alloc(layout, |padded| self.alloc_block(padded));
+        #[cfg(feature = "leak-tracking")]
+        leaks::track(ptr, &layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "leak-tracking")]
+        leaks::untrack(ptr);
+        #[cfg(not(feature = "heap-check"))]
+        self.dealloc_block(ptr, layout);
+        #[cfg(feature = "heap-check")]
+        check::dealloc(ptr, layout, |base, padded| self.dealloc_block(base, padded));
+    }
+
+    /// Checks the red zones of the block before it is copied, so an overrun
+    /// is reported at the block it happened in.
+    #[cfg(feature = "heap-check")]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        check::check(ptr, &layout);
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Thread-safe (locking) wrapper around the physical page-frame allocator.
+pub struct GlobalFrameAllocator(Mutex<Option<FrameAllocator>>);
+
+impl GlobalFrameAllocator {
+    /// Returns an uninitialized `GlobalFrameAllocator`.
+    ///
+    /// The allocator must be initialized by calling `initialize()` before the
+    /// first frame allocation. Failure to do will result in panics.
+    pub const fn uninitialized() -> Self {
+        GlobalFrameAllocator(Mutex::new(None))
+    }
+
+    /// Initializes the frame allocator with all of the available memory.
+    /// The caller should assure that the method is invoked only once during the
+    /// kernel initialization.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's memory map could not be retrieved.
+    pub unsafe fn initialize(&self) {
+        let (start, end) = memory_map().expect("failed to find memory map");
+        let frames = FrameAllocator::new(start, end);
+        info!("frames: {:?}", frames);
+        *self.0.lock() = Some(frames);
+    }
+
+    fn critical<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut FrameAllocator) -> R,
+    {
+        f(self.0.lock().as_mut().expect("frame allocator uninitialized"))
+    }
+
+    /// Allocates a frame. See `FrameAllocator::alloc()`.
+    pub fn alloc(&self) -> Option<usize> {
+        self.critical(|frames| frames.alloc())
+    }
+
+    /// Allocates contiguous frames. See `FrameAllocator::alloc_contiguous()`.
+    pub fn alloc_contiguous(&self, count: usize) -> Option<usize> {
+        self.critical(|frames| frames.alloc_contiguous(count))
+    }
+
+    /// Adds a reference to a frame. See `FrameAllocator::retain()`.
+    pub fn retain(&self, addr: usize) -> usize {
+        self.critical(|frames| frames.retain(addr))
+    }
+
+    /// Drops a reference to a frame. See `FrameAllocator::release()`.
+    pub fn release(&self, addr: usize) -> bool {
+        self.critical(|frames| frames.release(addr))
+    }
+
+    /// Returns the reference count of a frame.
+    pub fn refcount(&self, addr: usize) -> usize {
+        self.critical(|frames| frames.refcount(addr))
+    }
+
+    /// Returns the physical memory statistics.
+    pub fn stats(&self) -> FrameStats {
+        self.critical(|frames| frames.stats())
     }
 }
 
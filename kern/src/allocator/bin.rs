@@ -4,6 +4,7 @@ use core::ptr;
 use core::mem;
 
 use crate::allocator::linked_list::LinkedList;
+use crate::allocator::stats::{Counters, HeapStats};
 use crate::allocator::util::*;
 use crate::allocator::LocalAlloc;
 use crate::param::*;
@@ -25,12 +26,14 @@ pub struct Allocator {
     total_mem: usize,
     current: usize,
     end: usize,
+    counters: Counters,
     bins: [LinkedList; BIN_COUNT_MAX],
 }
 
 impl Allocator {
     /// Creates a new bin allocator that will allocate memory from the region
     /// starting at address `start` and ending at address `end`.
+    #[allow(dead_code)]
     pub fn new(start: usize, end: usize) -> Allocator {
         let current = start;
         let total_mem = end - current;
@@ -42,6 +45,7 @@ impl Allocator {
             total_mem,
             current,
             end,
+            counters: Counters::new(),
             bins,
         }
     }
@@ -63,6 +67,21 @@ impl Allocator {
             layout.align()
         );
     }
+
+    /// Returns the heap statistics of the allocator.
+    #[allow(dead_code)]
+    pub fn stats(&self) -> HeapStats {
+        let mut free = self.end - self.current;
+        let mut largest_free = free;
+        for index in 0..self.bins.len() {
+            let count = self.bins[index].iter().count();
+            free += count * Allocator::bin_size(index);
+            if count != 0 {
+                largest_free = largest_free.max(Allocator::bin_size(index));
+            }
+        }
+        self.counters.stats(self.total_mem, free, largest_free)
+    }
 }
 
 impl LocalAlloc for Allocator {
@@ -91,13 +110,16 @@ impl LocalAlloc for Allocator {
         let index =  self.map_to_bin(&layout);
         for node in self.bins[index].iter_mut() {
             if has_alignment(node.value() as usize, layout.align()) {
+                self.counters.alloc(&layout, Allocator::bin_size(index));
                 return node.pop() as *mut u8
             }
         }
         let aligned_addr = align_up(self.current, Allocator::bin_size(index));
         if aligned_addr + Allocator::bin_size(index) > self.end {
+            self.counters.fail();
             core::ptr::null_mut()
         } else {
+            self.counters.alloc(&layout, Allocator::bin_size(index));
             self.fragmentation += aligned_addr - self.current;
             self.current = aligned_addr + Allocator::bin_size(index);
             aligned_addr as *mut u8
@@ -119,6 +141,7 @@ impl LocalAlloc for Allocator {
     /// behavior.
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         let index = self.map_to_bin(&layout);
+        self.counters.dealloc(&layout, Allocator::bin_size(index));
         self.bins[index].push(ptr as *mut usize)
     }
 }
@@ -0,0 +1,322 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use crate::allocator::stats::{Counters, HeapStats};
+use crate::allocator::util::*;
+use crate::allocator::LocalAlloc;
+use crate::param::*;
+
+/// A buddy allocator.
+///
+/// Memory is handed out in blocks of 2^k bytes (order k) that are aligned to
+/// their size, and free blocks are kept on one list per order:
+///   order 4 (2^4 bytes)  : the smallest block, big enough for two list links
+///   order 5 (2^5 bytes)
+///   ...
+///   order 32 (2^32 bytes): the largest block
+///
+/// A request is served from the smallest free block that fits, which is split
+/// in halves (buddies) until it has the requested order. A freed block is
+/// merged with its buddy for as long as the buddy is free too, so memory freed
+/// in one size can be reused in any other.
+///
+/// Whether a block is free is kept in a bitmap at the start of the region,
+/// with one bit per block of each order, and the free lists are doubly linked,
+/// so a buddy is found and taken off its list in constant time.
+pub struct Allocator {
+    start: usize,
+    end: usize,
+    /// Address of the first block, past the bitmap.
+    base: usize,
+    /// Address of the bitmap of free blocks.
+    map: usize,
+    /// Index of the first bit of each order in the bitmap.
+    offsets: [usize; ORDER_COUNT],
+    /// Number of free bytes.
+    free: usize,
+    counters: Counters,
+    free_lists: [FreeList; ORDER_COUNT],
+}
+
+const KERNEL_VMM_ADDRESS_SIZE: usize = mem::size_of::<usize>() * 8 - KERNEL_MASK_BITS;
+const ORDER_COUNT: usize = KERNEL_VMM_ADDRESS_SIZE;
+const MIN_ORDER: usize = 4;
+const MAX_ORDER: usize = ORDER_COUNT - 1;
+
+/// The links of a free block, kept in the block itself.
+#[repr(C)]
+struct Link {
+    next: usize,
+    prev: usize,
+}
+
+/// A doubly linked list of free blocks of one order. The end of the list and
+/// the start of it are marked by links of 0.
+#[derive(Copy, Clone)]
+struct FreeList {
+    head: usize,
+    len: usize,
+}
+
+impl FreeList {
+    const fn new() -> FreeList {
+        FreeList { head: 0, len: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head == 0
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Pushes the block at `addr` to the front of the list.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `addr` is a free block not on any list.
+    unsafe fn push(&mut self, addr: usize) {
+        let link = &mut *(addr as *mut Link);
+        link.next = self.head;
+        link.prev = 0;
+        if self.head != 0 {
+            (*(self.head as *mut Link)).prev = addr;
+        }
+        self.head = addr;
+        self.len += 1;
+    }
+
+    /// Removes the block at `addr` from the list.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `addr` is on the list.
+    unsafe fn remove(&mut self, addr: usize) {
+        let link = &*(addr as *const Link);
+        if link.prev == 0 {
+            self.head = link.next;
+        } else {
+            (*(link.prev as *mut Link)).next = link.next;
+        }
+        if link.next != 0 {
+            (*(link.next as *mut Link)).prev = link.prev;
+        }
+        self.len -= 1;
+    }
+}
+
+impl Allocator {
+    /// Creates a new buddy allocator that will allocate memory from the region
+    /// starting at address `start` and ending at address `end`.
+    pub fn new(start: usize, end: usize) -> Allocator {
+        // The bitmap has a bit for every block of every order that could be
+        // in the region.
+        let mut offsets = [0; ORDER_COUNT];
+        let mut bits = 0;
+        for (order, offset) in offsets.iter_mut().enumerate().skip(MIN_ORDER) {
+            *offset = bits;
+            bits += (end >> order) - (start >> order) + 1;
+        }
+        let map_size = (bits + 7) / 8;
+        let base = align_up(start + map_size, block_size(MIN_ORDER));
+
+        let mut allocator = Allocator {
+            start,
+            end,
+            base,
+            map: start,
+            offsets,
+            free: 0,
+            counters: Counters::new(),
+            free_lists: [FreeList::new(); ORDER_COUNT],
+        };
+        if start + map_size > end {
+            return allocator;
+        }
+        unsafe { ptr::write_bytes(start as *mut u8, 0, map_size) };
+
+        // Cover the rest of the region with the largest blocks that are
+        // aligned to their size and fit.
+        let mut addr = base;
+        while addr + block_size(MIN_ORDER) <= end {
+            let mut order = MAX_ORDER;
+            while !has_alignment(addr, block_size(order)) || addr + block_size(order) > end {
+                order -= 1;
+            }
+            unsafe { allocator.insert(addr, order) };
+            addr += block_size(order);
+        }
+        allocator
+    }
+
+    /// Returns the byte of the bitmap holding the bit of the block at `addr`
+    /// of `order`, and the mask of the bit.
+    fn bit(&self, addr: usize, order: usize) -> (*mut u8, u8) {
+        let index = self.offsets[order] + (addr >> order) - (self.start >> order);
+        ((self.map + index / 8) as *mut u8, 1 << (index % 8))
+    }
+
+    /// Returns `true` if the block at `addr` of `order` is free.
+    fn is_free(&self, addr: usize, order: usize) -> bool {
+        let (byte, mask) = self.bit(addr, order);
+        unsafe { *byte & mask != 0 }
+    }
+
+    /// Adds the block at `addr` to the free blocks of `order`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the block is in the region and unused.
+    unsafe fn insert(&mut self, addr: usize, order: usize) {
+        let (byte, mask) = self.bit(addr, order);
+        *byte |= mask;
+        self.free_lists[order].push(addr);
+        self.free += block_size(order);
+    }
+
+    /// Removes the free block at `addr` from the free blocks of `order`.
+    fn remove(&mut self, addr: usize, order: usize) {
+        let (byte, mask) = self.bit(addr, order);
+        unsafe {
+            *byte &= !mask;
+            self.free_lists[order].remove(addr);
+        }
+        self.free -= block_size(order);
+    }
+
+    /// Returns the order of the smallest block that satisfies `layout`, or
+    /// `None` if no block is large enough.
+    fn order(layout: &Layout) -> Option<usize> {
+        let required_size = layout.size().max(layout.align());
+        (MIN_ORDER..ORDER_COUNT).find(|&order| block_size(order) >= required_size)
+    }
+
+    /// Removes the block at `addr` from the free blocks of `order`. Returns
+    /// `false` if the block is not free.
+    fn take(&mut self, addr: usize, order: usize) -> bool {
+        if !self.is_free(addr, order) {
+            return false;
+        }
+        self.remove(addr, order);
+        true
+    }
+
+    /// Returns the number of free bytes.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Returns the heap statistics of the allocator.
+    pub fn stats(&self) -> HeapStats {
+        let largest_free = (MIN_ORDER..ORDER_COUNT)
+            .rev()
+            .find(|&order| !self.free_lists[order].is_empty())
+            .map_or(0, block_size);
+        self.counters.stats(self.end - self.start, self.free(), largest_free)
+    }
+}
+
+fn block_size(order: usize) -> usize {
+    1 << order
+}
+
+impl LocalAlloc for Allocator {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// pointing to a block of storage suitable for holding an instance of
+    /// `layout`. In particular, the block will be at least `layout.size()`
+    /// bytes large and will be aligned to `layout.align()`. The returned block
+    /// of storage may or may not have its contents initialized or zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that `layout.size() > 0` and that
+    /// `layout.align()` is a power of two. Parameters not meeting these
+    /// conditions may result in undefined behavior.
+    ///
+    /// # Errors
+    ///
+    /// Returning null pointer (`core::ptr::null_mut`)
+    /// indicates that either memory is exhausted
+    /// or `layout` does not meet this allocator's
+    /// size or alignment constraints.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let order = Allocator::order(&layout);
+        let free = order.and_then(|order| {
+            (order..ORDER_COUNT).find(|&o| !self.free_lists[o].is_empty())
+        });
+        let (order, mut current) = match (order, free) {
+            (Some(order), Some(current)) => (order, current),
+            _ => {
+                self.counters.fail();
+                return ptr::null_mut();
+            }
+        };
+
+        let addr = self.free_lists[current].head;
+        self.remove(addr, current);
+        // Split the block, keeping its lower half and freeing the upper one.
+        while current > order {
+            current -= 1;
+            self.insert(addr + block_size(current), current);
+        }
+        self.counters.alloc(&layout, block_size(order));
+        addr as *mut u8
+    }
+
+    /// Deallocates the memory referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure the following:
+    ///
+    ///   * `ptr` must denote a block of memory currently allocated via this
+    ///     allocator
+    ///   * `layout` must properly represent the original layout used in the
+    ///     allocation call that returned `ptr`
+    ///
+    /// Parameters not meeting these conditions may result in undefined
+    /// behavior.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut order = Allocator::order(&layout).expect("dealloc of a block never allocated");
+        let mut addr = ptr as usize;
+        self.counters.dealloc(&layout, block_size(order));
+
+        // A buddy outside of the region is never free; otherwise it is free
+        // only if it is on the free list of its order.
+        while order < MAX_ORDER {
+            let buddy = addr ^ block_size(order);
+            let in_region = buddy >= self.base && buddy + block_size(order) <= self.end;
+            if !in_region || !self.take(buddy, order) {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.insert(addr, order);
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Allocator {{")?;
+        writeln!(f, "  start: {:#x}", self.start)?;
+        writeln!(f, "  end: {:#x}", self.end)?;
+        writeln!(f, "  allocated mem: {}", self.counters.allocated())?;
+        writeln!(f, "  free mem: {}", self.free())?;
+        for order in MIN_ORDER..ORDER_COUNT {
+            let count = self.free_lists[order].len();
+            if count != 0 {
+                writeln!(f, "  order {} size={}: {} free", order, block_size(order), count)?;
+            }
+        }
+        writeln!(f, "}}")?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 use core::alloc::Layout;
 use core::ptr;
 
+use crate::allocator::stats::{Counters, HeapStats};
 use crate::allocator::util::*;
 use crate::allocator::LocalAlloc;
 
 /// A "bump" allocator: allocates memory by bumping a pointer; never frees.
 #[derive(Debug)]
 pub struct Allocator {
+    start: usize,
     current: usize,
     end: usize,
+    counters: Counters,
 }
 
 impl Allocator {
@@ -17,10 +20,20 @@ impl Allocator {
     #[allow(dead_code)]
     pub fn new(start: usize, end: usize) -> Allocator {
         Allocator {
+            start,
             current: start,
-            end
+            end,
+            counters: Counters::new(),
         }
     }
+
+    /// Returns the heap statistics of the allocator. Freed memory is never
+    /// reused, so it stays allocated.
+    #[allow(dead_code)]
+    pub fn stats(&self) -> HeapStats {
+        let free = self.end - self.current;
+        self.counters.stats(self.end - self.start, free, free)
+    }
 }
 
 impl LocalAlloc for Allocator {
@@ -50,8 +63,10 @@ impl LocalAlloc for Allocator {
         let alloc_end = aligned_addr.saturating_add(layout.size());
 
         if alloc_end >= self.end {
+            self.counters.fail();
             core::ptr::null_mut()
         } else {
+            self.counters.alloc(&layout, alloc_end - self.current);
             self.current = alloc_end;
             aligned_addr as *mut u8
         }
@@ -0,0 +1,175 @@
+use core::alloc::Layout;
+use core::ptr;
+
+use crate::allocator::util::align_up;
+use crate::mutex::Mutex;
+
+/// Size of the red zone after a block, and the least size of the one before
+/// it. The one before is rounded up to the block's alignment.
+pub const REDZONE_SIZE: usize = 16;
+
+/// Byte the red zones are filled with.
+pub const REDZONE_BYTE: u8 = 0xbb;
+
+/// Byte freed blocks are filled with.
+pub const POISON_BYTE: u8 = 0x6b;
+
+/// Number of freed blocks held back before they are reused.
+const QUARANTINE_SIZE: usize = 64;
+
+/// Returns the layout of the allocation holding a block of `layout` between
+/// its red zones, and the offset of the block in it.
+pub fn padded(layout: &Layout) -> (Layout, usize) {
+    let front = align_up(REDZONE_SIZE, layout.align());
+    let size = front + layout.size() + REDZONE_SIZE;
+    let padded = Layout::from_size_align(size, layout.align())
+        .expect("layout overflows with red zones");
+    (padded, front)
+}
+
+/// Fills the red zones of the allocation at `base` for a block of `layout`,
+/// and returns the address of the block.
+///
+/// # Safety
+///
+/// `base` must be an allocation of `padded(layout)`.
+pub unsafe fn arm(base: *mut u8, layout: &Layout) -> *mut u8 {
+    let (_, front) = padded(layout);
+    ptr::write_bytes(base, REDZONE_BYTE, front);
+    ptr::write_bytes(base.add(front + layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+    base.add(front)
+}
+
+/// Returns the offset in `len` bytes at `start` of the first byte that is
+/// not `byte`.
+unsafe fn find_mismatch(start: *const u8, len: usize, byte: u8) -> Option<usize> {
+    (0..len).find(|&i| *start.add(i) != byte)
+}
+
+/// Panics if a red zone of the block of `layout` at `ptr` was overwritten,
+/// e.g. by an overrun. Returns the address of the allocation holding it.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `arm()` for `layout`.
+pub unsafe fn check(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    let (_, front) = padded(layout);
+    let base = ptr.sub(front);
+    if let Some(i) = find_mismatch(base, front, REDZONE_BYTE) {
+        panic!(
+            "heap corruption: red zone before block {:#x} ({:?}) overwritten at {:#x}",
+            ptr as usize,
+            layout,
+            base as usize + i
+        );
+    }
+    let back = ptr.add(layout.size());
+    if let Some(i) = find_mismatch(back, REDZONE_SIZE, REDZONE_BYTE) {
+        panic!(
+            "heap corruption: red zone after block {:#x} ({:?}) overwritten at {:#x}",
+            ptr as usize,
+            layout,
+            back as usize + i
+        );
+    }
+    base
+}
+
+/// Fills the freed block of `layout` at `ptr` with `POISON_BYTE`.
+///
+/// # Safety
+///
+/// `ptr` must be a block of `layout` that is not in use anymore.
+pub unsafe fn poison(ptr: *mut u8, layout: &Layout) {
+    ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+}
+
+/// Panics if the poisoned block of `layout` at `ptr` was written to after
+/// it was freed, or if its red zones were overwritten. Returns the address
+/// of the allocation holding it.
+///
+/// # Safety
+///
+/// `ptr` must have been poisoned with `poison()` for `layout`.
+pub unsafe fn check_poison(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    if let Some(i) = find_mismatch(ptr, layout.size(), POISON_BYTE) {
+        panic!(
+            "heap corruption: freed block {:#x} ({:?}) written to at {:#x}",
+            ptr as usize,
+            layout,
+            ptr as usize + i
+        );
+    }
+    check(ptr, layout)
+}
+
+/// Freed blocks that are not reused yet, so writes to them can be caught.
+pub struct Quarantine {
+    blocks: [(usize, usize, usize); QUARANTINE_SIZE],
+    next: usize,
+}
+
+impl Quarantine {
+    pub const fn new() -> Quarantine {
+        Quarantine {
+            blocks: [(0, 0, 0); QUARANTINE_SIZE],
+            next: 0,
+        }
+    }
+
+    /// Holds back the freed block of `layout` at `ptr`, and returns the
+    /// block held back the longest if that had to make room for it.
+    pub fn insert(&mut self, ptr: *mut u8, layout: &Layout) -> Option<(*mut u8, Layout)> {
+        let (old, size, align) = self.blocks[self.next];
+        self.blocks[self.next] = (ptr as usize, layout.size(), layout.align());
+        self.next = (self.next + 1) % QUARANTINE_SIZE;
+        match old {
+            0 => None,
+            old => {
+                let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+                Some((old as *mut u8, layout))
+            }
+        }
+    }
+}
+
+/// The quarantine of the kernel's heap.
+pub static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine::new());
+
+/// Allocates a block of `layout` between red zones with `inner`.
+///
+/// # Safety
+///
+/// `inner` must allocate like `GlobalAlloc::alloc()`.
+pub unsafe fn alloc<F>(layout: Layout, inner: F) -> *mut u8
+where
+    F: FnOnce(Layout) -> *mut u8,
+{
+    let (padded, _) = padded(&layout);
+    let base = inner(padded);
+    if base.is_null() {
+        return base;
+    }
+    arm(base, &layout)
+}
+
+/// Checks the red zones of the block of `layout` at `ptr`, poisons it and
+/// puts it in quarantine. The block leaving the quarantine for it, if any,
+/// is checked for writes after it was freed and freed with `inner`.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated with `alloc()` for `layout`, and `inner`
+/// must free like `GlobalAlloc::dealloc()`.
+pub unsafe fn dealloc<F>(ptr: *mut u8, layout: Layout, inner: F)
+where
+    F: FnOnce(*mut u8, Layout),
+{
+    check(ptr, &layout);
+    poison(ptr, &layout);
+    let evicted = QUARANTINE.lock().insert(ptr, &layout);
+    if let Some((old, old_layout)) = evicted {
+        let base = check_poison(old, &old_layout);
+        inner(base, padded(&old_layout).0);
+    }
+}
@@ -0,0 +1,191 @@
+use core::fmt;
+use core::mem;
+use core::slice;
+
+use crate::allocator::util::*;
+use crate::param::PAGE_SIZE;
+
+/// Largest reference count of a frame.
+const MAX_REFCOUNT: u16 = core::u16::MAX;
+
+/// Physical memory statistics of a `FrameAllocator`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameStats {
+    /// Number of frames managed.
+    pub total: usize,
+    /// Number of free frames.
+    pub free: usize,
+    /// Number of frames referenced more than once.
+    pub shared: usize,
+}
+
+impl FrameStats {
+    /// Returns the number of frames in use.
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+/// An allocator of `PAGE_SIZE` physical page frames.
+///
+/// Every frame has a reference count: a frame is free while its count is 0,
+/// `alloc()` hands it out with a count of 1, and it is freed again once
+/// `release()` drops the count back to 0. The counts are kept in a table at
+/// the start of the managed region.
+pub struct FrameAllocator {
+    /// Address of the first frame.
+    base: usize,
+    /// Reference count of each frame.
+    refcounts: &'static mut [u16],
+    free: usize,
+    /// Index at which the search for a free frame starts. No frame below it
+    /// is free.
+    next: usize,
+}
+
+impl FrameAllocator {
+    /// Creates a new frame allocator managing the frames that fit in the
+    /// region starting at address `start` and ending at address `end`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the region is unused, writeable memory
+    /// that outlives the allocator.
+    pub unsafe fn new(start: usize, end: usize) -> FrameAllocator {
+        let table = align_up(start, mem::align_of::<u16>());
+        let max_frames = end.saturating_sub(table) / PAGE_SIZE;
+        let base = align_up(table + max_frames * mem::size_of::<u16>(), PAGE_SIZE);
+        let frames = end.saturating_sub(base) / PAGE_SIZE;
+
+        let refcounts = slice::from_raw_parts_mut(table as *mut u16, frames);
+        for refcount in refcounts.iter_mut() {
+            *refcount = 0;
+        }
+        FrameAllocator {
+            base,
+            refcounts,
+            free: frames,
+            next: 0,
+        }
+    }
+
+    /// Returns the index of the frame starting at `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is not the address of a frame of this allocator.
+    fn index(&self, addr: usize) -> usize {
+        if !self.contains(addr) || !has_alignment(addr, PAGE_SIZE) {
+            panic!("FrameAllocator: {:#x} is not a page frame", addr);
+        }
+        (addr - self.base) / PAGE_SIZE
+    }
+
+    fn addr(&self, index: usize) -> usize {
+        self.base + index * PAGE_SIZE
+    }
+
+    /// Returns `true` if `addr` lies in one of the frames of this allocator.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr < self.base + self.refcounts.len() * PAGE_SIZE
+    }
+
+    /// Allocates a frame and returns its address, or `None` if every frame is
+    /// in use. The frame's contents are not cleared.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let index = (self.next..self.refcounts.len()).find(|&i| self.refcounts[i] == 0)?;
+        self.refcounts[index] = 1;
+        self.free -= 1;
+        self.next = index + 1;
+        Some(self.addr(index))
+    }
+
+    /// Allocates `count` physically contiguous frames and returns the address
+    /// of the first one, or `None` if there is no such run of free frames.
+    pub fn alloc_contiguous(&mut self, count: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+        let mut run = 0;
+        for index in self.next..self.refcounts.len() {
+            if self.refcounts[index] != 0 {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == count {
+                let first = index + 1 - count;
+                for refcount in self.refcounts[first..=index].iter_mut() {
+                    *refcount = 1;
+                }
+                self.free -= count;
+                if first == self.next {
+                    self.next = index + 1;
+                }
+                return Some(self.addr(first));
+            }
+        }
+        None
+    }
+
+    /// Adds a reference to the allocated frame at `addr`, e.g. because it is
+    /// mapped once more, and returns the new reference count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is not an allocated frame of this allocator, or if its
+    /// count would overflow.
+    pub fn retain(&mut self, addr: usize) -> usize {
+        let index = self.index(addr);
+        let refcount = &mut self.refcounts[index];
+        assert!(*refcount != 0, "FrameAllocator::retain(): {:#x} is free", addr);
+        assert!(*refcount != MAX_REFCOUNT, "FrameAllocator::retain(): too many references");
+        *refcount += 1;
+        *refcount as usize
+    }
+
+    /// Drops a reference to the allocated frame at `addr`. Returns `true` if
+    /// that was the last one and the frame is free now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is not an allocated frame of this allocator.
+    pub fn release(&mut self, addr: usize) -> bool {
+        let index = self.index(addr);
+        let refcount = &mut self.refcounts[index];
+        assert!(*refcount != 0, "FrameAllocator::release(): {:#x} is already free", addr);
+        *refcount -= 1;
+        if *refcount != 0 {
+            return false;
+        }
+        self.free += 1;
+        self.next = self.next.min(index);
+        true
+    }
+
+    /// Returns the reference count of the frame at `addr`, 0 if it is free.
+    pub fn refcount(&self, addr: usize) -> usize {
+        self.refcounts[self.index(addr)] as usize
+    }
+
+    /// Returns the memory statistics of the allocator.
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.refcounts.len(),
+            free: self.free,
+            shared: self.refcounts.iter().filter(|&&count| count > 1).count(),
+        }
+    }
+}
+
+impl fmt::Debug for FrameAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = self.stats();
+        f.debug_struct("FrameAllocator")
+            .field("base", &format_args!("{:#x}", self.base))
+            .field("frames", &stats.total)
+            .field("free", &stats.free)
+            .field("shared", &stats.shared)
+            .finish()
+    }
+}
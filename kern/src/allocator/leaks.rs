@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+use crate::mutex::Mutex;
+use crate::param::{KERN_STACK_BASE, KERN_STACK_STRIDE};
+use crate::percore;
+
+/// Largest number of live allocations that can be tracked at once.
+pub const TRACKED_MAX: usize = 2048;
+
+/// Number of return addresses recorded per allocation.
+pub const DEPTH: usize = 6;
+
+/// Address marking the slot of an allocation that was freed.
+const FREED: usize = 1;
+
+/// A live allocation and where it was made.
+#[derive(Debug, Copy, Clone)]
+pub struct Record {
+    pub ptr: usize,
+    pub size: usize,
+    /// Sequence number of the allocation, counting from 1.
+    pub seq: usize,
+    /// Return addresses of the innermost frames of the allocation, the first
+    /// one in the allocator itself. Unused entries are 0.
+    pub callers: [usize; DEPTH],
+}
+
+impl Record {
+    const EMPTY: Record = Record { ptr: 0, size: 0, seq: 0, callers: [0; DEPTH] };
+}
+
+/// Records the live heap allocations for leak detection.
+///
+/// The records are kept in a fixed table, hashed by address, since the
+/// tracker cannot allocate. Allocations that do not fit are only counted.
+pub struct LeakTracker {
+    records: [Record; TRACKED_MAX],
+    /// Sequence number of the last allocation.
+    seq: usize,
+    /// Sequence number of the last allocation before `mark()`.
+    mark: usize,
+    untracked: usize,
+}
+
+impl LeakTracker {
+    const fn new() -> LeakTracker {
+        LeakTracker {
+            records: [Record::EMPTY; TRACKED_MAX],
+            seq: 0,
+            mark: 0,
+            untracked: 0,
+        }
+    }
+
+    fn slot(ptr: usize, probe: usize) -> usize {
+        ((ptr >> 3) + probe) % TRACKED_MAX
+    }
+
+    fn track(&mut self, ptr: usize, size: usize, callers: [usize; DEPTH]) {
+        self.seq += 1;
+        for probe in 0..TRACKED_MAX {
+            let record = &mut self.records[LeakTracker::slot(ptr, probe)];
+            if record.ptr == 0 || record.ptr == FREED {
+                *record = Record { ptr, size, seq: self.seq, callers };
+                return;
+            }
+        }
+        self.untracked += 1;
+    }
+
+    fn untrack(&mut self, ptr: usize) {
+        for probe in 0..TRACKED_MAX {
+            let record = &mut self.records[LeakTracker::slot(ptr, probe)];
+            if record.ptr == ptr {
+                record.ptr = FREED;
+                return;
+            } else if record.ptr == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Starts a leak check: `leaks()` only reports the allocations made from
+    /// now on.
+    pub fn mark(&mut self) {
+        self.mark = self.seq;
+    }
+
+    /// Appends the live allocations made since `mark()` to `leaks`, as far as
+    /// its capacity allows, so the tracker's lock is never held while the
+    /// heap is used.
+    pub fn leaks(&self, leaks: &mut Vec<Record>) {
+        let live = self.records.iter().filter(|r| r.ptr > FREED && r.seq > self.mark);
+        for record in live {
+            if leaks.len() == leaks.capacity() {
+                break;
+            }
+            leaks.push(*record);
+        }
+    }
+
+    /// Returns the number of allocations that could not be tracked.
+    pub fn untracked(&self) -> usize {
+        self.untracked
+    }
+}
+
+/// Returns the top of the kernel stack holding `sp`: the stack of a core or
+/// of the kernel process running on this core. Returns `sp` itself for any
+/// other stack, such as an overflow stack.
+fn stack_top(sp: usize) -> usize {
+    let (bottom, top) = percore::process_stack();
+    if sp >= bottom && sp < top {
+        top
+    } else if sp <= KERN_STACK_BASE {
+        let core = (KERN_STACK_BASE - sp) / KERN_STACK_STRIDE;
+        KERN_STACK_BASE - core * KERN_STACK_STRIDE
+    } else {
+        sp
+    }
+}
+
+/// Returns the return addresses of the innermost `DEPTH` frames, following
+/// the frame records `make LEAK_TRACKING=1` builds the kernel with
+/// (`-C force-frame-pointers`). The walk stops at the top of the current
+/// stack, as a trap handler's outermost record links to the interrupted one.
+#[inline(always)]
+fn callers() -> [usize; DEPTH] {
+    let mut callers = [0; DEPTH];
+    let (mut fp, sp): (usize, usize);
+    unsafe {
+        asm!("mov $0, x29" : "=r"(fp) ::: "volatile");
+        asm!("mov $0, sp" : "=r"(sp) ::: "volatile");
+    }
+    let top = stack_top(sp);
+    for caller in callers.iter_mut() {
+        // Frame records are 16 byte aligned, and the callers' ones are
+        // further up the stack.
+        if fp < sp || fp + 16 > top || fp % 16 != 0 {
+            break;
+        }
+        let (next, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        *caller = lr;
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    callers
+}
+
+/// Records the allocation of `layout` at `ptr`, unless it failed.
+#[inline(always)]
+pub fn track(ptr: *mut u8, layout: &Layout) {
+    if !ptr.is_null() {
+        let callers = callers();
+        LEAKS.lock().track(ptr as usize, layout.size(), callers);
+    }
+}
+
+/// Forgets the allocation at `ptr`.
+pub fn untrack(ptr: *mut u8) {
+    LEAKS.lock().untrack(ptr as usize);
+}
+
+/// The tracker of the kernel's heap allocations.
+pub static LEAKS: Mutex<LeakTracker> = Mutex::new(LeakTracker::new());
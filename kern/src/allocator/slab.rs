@@ -0,0 +1,390 @@
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use aarch64::{affinity, disable_fiq_interrupt, disable_irq_interrupt};
+use aarch64::{get_interrupt_mask, set_interrupt_mask};
+
+use crate::allocator::linked_list::LinkedList;
+use crate::allocator::util::*;
+use crate::mutex::Mutex;
+use crate::net::FrameBuf;
+use crate::param::{NCORES, PAGE_SIZE};
+use crate::traps::TrapFrame;
+use crate::FRAMES;
+
+/// Largest number of objects a magazine can hold.
+const MAGAZINE_CAPACITY: usize = 16;
+
+/// The slabs of an object cache and the objects in them that are free.
+///
+/// A slab is a run of `PAGE_SIZE` frames carved into objects of one size.
+/// Free objects are kept on an intrusive list, so objects must be at least
+/// `usize` in size.
+pub struct Depot {
+    /// Size of an object, rounded up to its alignment.
+    size: usize,
+    align: usize,
+    free: LinkedList,
+    free_count: usize,
+    objects: usize,
+    slabs: usize,
+}
+
+impl Depot {
+    /// Returns an empty depot of objects of `size` bytes aligned to `align`.
+    pub const fn new(size: usize, align: usize) -> Depot {
+        Depot {
+            size: (size + align - 1) & !(align - 1),
+            align,
+            free: LinkedList::new(),
+            free_count: 0,
+            objects: 0,
+            slabs: 0,
+        }
+    }
+
+    /// Returns the size of a slab: the smallest run of frames an object fits.
+    pub fn slab_size(&self) -> usize {
+        align_up(self.size, PAGE_SIZE)
+    }
+
+    /// Carves the slab starting at address `start` and ending at address
+    /// `end` into objects and adds them to the free ones.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the slab is unused, writeable memory that
+    /// is never handed out by anything else.
+    pub unsafe fn add_slab(&mut self, start: usize, end: usize) {
+        assert!(self.size >= mem::size_of::<usize>(), "Depot: objects are too small");
+        let mut addr = align_up(start, self.align);
+        while addr + self.size <= end {
+            self.free.push(addr as *mut usize);
+            self.free_count += 1;
+            self.objects += 1;
+            addr += self.size;
+        }
+        self.slabs += 1;
+    }
+
+    /// Takes a free object, if any.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let addr = self.free.pop()? as usize;
+        self.free_count -= 1;
+        Some(addr)
+    }
+
+    /// Returns the object at `addr` to the free ones.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `addr` is an object of this depot that is
+    /// not in use anymore.
+    pub unsafe fn free(&mut self, addr: usize) {
+        self.free.push(addr as *mut usize);
+        self.free_count += 1;
+    }
+
+    /// Returns the number of objects carved from the slabs.
+    pub fn objects(&self) -> usize {
+        self.objects
+    }
+
+    /// Returns the number of free objects.
+    pub fn free_count(&self) -> usize {
+        self.free_count
+    }
+
+    /// Returns the number of slabs.
+    pub fn slabs(&self) -> usize {
+        self.slabs
+    }
+}
+
+/// A per-core stack of free objects, taken and refilled without the depot
+/// lock, and the core's counts of allocations from it.
+///
+/// Only its core writes it, with interrupts masked, so it needs no atomics
+/// and can be used before the MMU is on.
+struct Magazine(UnsafeCell<PerCore>);
+
+struct PerCore {
+    magazine: MagazineInner,
+    hits: usize,
+    misses: usize,
+}
+
+impl Magazine {
+    const fn new() -> Magazine {
+        Magazine(UnsafeCell::new(PerCore { magazine: MagazineInner::new(), hits: 0, misses: 0 }))
+    }
+}
+
+/// The objects of a magazine.
+pub(super) struct MagazineInner {
+    objects: [usize; MAGAZINE_CAPACITY],
+    len: usize,
+}
+
+impl MagazineInner {
+    pub(super) const fn new() -> MagazineInner {
+        MagazineInner {
+            objects: [0; MAGAZINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of objects in the magazine.
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Takes the object freed last, if any.
+    pub(super) fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.objects[self.len])
+    }
+
+    /// Adds the object at `addr`. The magazine must not be full.
+    pub(super) fn push(&mut self, addr: usize) {
+        self.objects[self.len] = addr;
+        self.len += 1;
+    }
+
+    /// Takes objects from `depot` until the magazine holds `target` of them.
+    /// When the depot runs dry, it grows by the slab `grow` returns for the
+    /// slab size, if any. Returns the number of objects taken.
+    pub(super) fn refill<G>(&mut self, depot: &mut Depot, target: usize, mut grow: G) -> usize
+    where
+        G: FnMut(usize) -> Option<usize>,
+    {
+        let mut taken = 0;
+        while self.len < target {
+            let addr = match depot.alloc() {
+                Some(addr) => addr,
+                None => {
+                    let slab_size = depot.slab_size();
+                    match grow(slab_size) {
+                        Some(start) => unsafe { depot.add_slab(start, start + slab_size) },
+                        None => break,
+                    }
+                    continue;
+                }
+            };
+            self.push(addr);
+            taken += 1;
+        }
+        taken
+    }
+
+    /// Returns the older half of the objects to `depot`. Returns the number
+    /// of objects returned.
+    pub(super) fn flush(&mut self, depot: &mut Depot) -> usize {
+        let count = self.len / 2;
+        for &addr in self.objects[..count].iter() {
+            unsafe { depot.free(addr) };
+        }
+        for i in count..self.len {
+            self.objects[i - count] = self.objects[i];
+        }
+        self.len -= count;
+        count
+    }
+}
+
+/// Returns the number of objects of `depot` handed out, leaving out the
+/// `cached` ones held in magazines.
+pub(super) fn in_use(depot: &Depot, cached: usize) -> usize {
+    (depot.objects() - depot.free_count()).saturating_sub(cached)
+}
+
+/// Statistics of an `ObjectCache`.
+#[derive(Debug, Copy, Clone)]
+pub struct CacheStats {
+    pub name: &'static str,
+    /// Size of an object in bytes.
+    pub size: usize,
+    pub slabs: usize,
+    pub objects: usize,
+    /// Number of objects allocated from the cache.
+    pub in_use: usize,
+    /// Number of free objects held in magazines.
+    pub cached: usize,
+    /// Number of allocations served from a magazine.
+    pub hits: usize,
+    /// Number of allocations that had to go to the depot.
+    pub misses: usize,
+}
+
+/// A cache of kernel objects of one layout.
+///
+/// Each core allocates from and frees to its own magazine, which only needs
+/// interrupts to be masked on that core. The shared depot is locked only to
+/// refill an empty magazine or to take back half of a full one, and grows by
+/// a slab from `FRAMES` whenever it runs dry. Slabs are never given back.
+pub struct ObjectCache {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    /// Number of objects a magazine holds at most, up to
+    /// `MAGAZINE_CAPACITY`.
+    magazine_size: usize,
+    depot: Mutex<Depot>,
+    magazines: [Magazine; NCORES],
+}
+
+unsafe impl Sync for ObjectCache {}
+
+impl ObjectCache {
+    /// Returns an empty cache named `name` of objects of `size` bytes aligned
+    /// to `align`, with magazines of `magazine_size` objects.
+    pub const fn new(
+        name: &'static str,
+        size: usize,
+        align: usize,
+        magazine_size: usize,
+    ) -> ObjectCache {
+        ObjectCache {
+            name,
+            size,
+            align,
+            magazine_size,
+            depot: Mutex::new(Depot::new(size, align)),
+            magazines: [Magazine::new(), Magazine::new(), Magazine::new(), Magazine::new()],
+        }
+    }
+
+    /// Returns `true` if the cache serves allocations of `layout`.
+    pub fn serves(&self, layout: &Layout) -> bool {
+        layout.size() == self.size && layout.align() == self.align
+    }
+
+    /// Calls `f` with the magazine of the current core, masking interrupts
+    /// so nothing else on the core can use it meanwhile.
+    fn with_magazine<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut PerCore) -> R,
+    {
+        let mask = get_interrupt_mask();
+        disable_irq_interrupt();
+        disable_fiq_interrupt();
+        let result = f(unsafe { &mut *self.magazines[affinity()].0.get() });
+        set_interrupt_mask(mask);
+        result
+    }
+
+    /// Fills `magazine` halfway from the depot, growing the depot by a slab
+    /// from `FRAMES` if needed.
+    fn refill(&self, magazine: &mut MagazineInner) {
+        let mut depot = self.depot.lock();
+        let target = (self.magazine_size + 1) / 2;
+        magazine.refill(&mut depot, target, |slab_size| {
+            FRAMES.alloc_contiguous(slab_size / PAGE_SIZE)
+        });
+    }
+
+    /// Returns the older half of the objects in `magazine` to the depot.
+    fn flush(&self, magazine: &mut MagazineInner) {
+        magazine.flush(&mut self.depot.lock());
+    }
+
+    /// Allocates an object. Returns a null pointer if the depot could not
+    /// grow.
+    pub fn alloc(&self) -> *mut u8 {
+        self.with_magazine(|core| {
+            if core.magazine.len() == 0 {
+                core.misses += 1;
+                self.refill(&mut core.magazine);
+            } else {
+                core.hits += 1;
+            }
+            match core.magazine.pop() {
+                Some(addr) => addr as *mut u8,
+                None => ptr::null_mut(),
+            }
+        })
+    }
+
+    /// Frees the object at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` was allocated from this cache and is
+    /// not in use anymore.
+    pub unsafe fn dealloc(&self, ptr: *mut u8) {
+        self.with_magazine(|core| {
+            if core.magazine.len() == self.magazine_size {
+                self.flush(&mut core.magazine);
+            }
+            core.magazine.push(ptr as usize);
+        })
+    }
+
+    /// Returns the statistics of the cache. The counts of the other cores
+    /// are read while they may change, so they are only a snapshot.
+    pub fn stats(&self) -> CacheStats {
+        let depot = self.depot.lock();
+        let (mut cached, mut hits, mut misses) = (0, 0, 0);
+        for magazine in self.magazines.iter() {
+            let core = magazine.0.get();
+            unsafe {
+                cached += ptr::read_volatile(&(*core).magazine.len);
+                hits += ptr::read_volatile(&(*core).hits);
+                misses += ptr::read_volatile(&(*core).misses);
+            }
+        }
+        CacheStats {
+            name: self.name,
+            size: self.size,
+            slabs: depot.slabs(),
+            objects: depot.objects(),
+            in_use: in_use(&depot, cached),
+            cached,
+            hits,
+            misses,
+        }
+    }
+}
+
+impl fmt::Debug for ObjectCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjectCache")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("align", &self.align)
+            .finish()
+    }
+}
+
+/// The caches of the kernel objects allocated over and over at a fixed size.
+/// Allocations of exactly their layout are served by them instead of the
+/// heap.
+///
+/// Page tables have no cache, as each one is a whole frame taken from
+/// `FRAMES`, and neither do processes, which the scheduler keeps in its queue
+/// rather than allocating one by one.
+pub static CACHES: [ObjectCache; 2] = [
+    ObjectCache::new(
+        "TrapFrame",
+        mem::size_of::<TrapFrame>(),
+        mem::align_of::<TrapFrame>(),
+        MAGAZINE_CAPACITY,
+    ),
+    ObjectCache::new(
+        "FrameBuf",
+        mem::size_of::<FrameBuf>(),
+        mem::align_of::<FrameBuf>(),
+        MAGAZINE_CAPACITY,
+    ),
+];
+
+/// Returns the cache serving allocations of `layout`, if any.
+pub fn cache_for(layout: &Layout) -> Option<&'static ObjectCache> {
+    CACHES.iter().find(|cache| cache.serves(layout))
+}
@@ -0,0 +1,120 @@
+use core::alloc::Layout;
+use core::mem;
+
+use crate::param::KERNEL_MASK_BITS;
+
+/// Number of size classes: class k counts the blocks of up to 2^(k + 3)
+/// bytes, so the classes cover 8 bytes to 4 GiB like the bins of the
+/// allocators.
+pub const CLASS_COUNT: usize = mem::size_of::<usize>() * 8 - KERNEL_MASK_BITS - 3;
+
+/// Returns the largest size of a block of size class `class`.
+pub fn class_size(class: usize) -> usize {
+    1 << (class + 3)
+}
+
+/// Returns the size class of a block of `size` bytes.
+pub fn class_of(size: usize) -> usize {
+    (0..CLASS_COUNT)
+        .find(|&class| class_size(class) >= size)
+        .unwrap_or(CLASS_COUNT - 1)
+}
+
+/// Heap statistics of an allocator.
+#[derive(Debug, Copy, Clone)]
+pub struct HeapStats {
+    /// Number of bytes managed.
+    pub total: usize,
+    /// Number of bytes that can still be allocated.
+    pub free: usize,
+    /// Size of the largest block that can be allocated.
+    pub largest_free: usize,
+    /// Number of bytes requested by the live allocations.
+    pub requested: usize,
+    /// Number of bytes in the blocks handed out for them.
+    pub allocated: usize,
+    /// Largest `allocated` so far.
+    pub high_water: usize,
+    /// Number of live allocations.
+    pub allocations: usize,
+    /// Number of allocations that failed.
+    pub failed: usize,
+    /// Number of live blocks of each size class.
+    pub classes: [usize; CLASS_COUNT],
+}
+
+impl HeapStats {
+    /// Returns the number of bytes lost to internal fragmentation: handed
+    /// out in blocks, but not requested.
+    pub fn fragmentation(&self) -> usize {
+        self.allocated - self.requested
+    }
+}
+
+/// Allocation counters kept by an allocator to report its `HeapStats`.
+#[derive(Debug)]
+pub struct Counters {
+    requested: usize,
+    allocated: usize,
+    high_water: usize,
+    allocations: usize,
+    failed: usize,
+    classes: [usize; CLASS_COUNT],
+}
+
+impl Counters {
+    pub const fn new() -> Counters {
+        Counters {
+            requested: 0,
+            allocated: 0,
+            high_water: 0,
+            allocations: 0,
+            failed: 0,
+            classes: [0; CLASS_COUNT],
+        }
+    }
+
+    /// Records that `layout` was allocated in a block of `block` bytes.
+    pub fn alloc(&mut self, layout: &Layout, block: usize) {
+        self.requested += layout.size();
+        self.allocated += block;
+        self.high_water = self.high_water.max(self.allocated);
+        self.allocations += 1;
+        self.classes[class_of(block)] += 1;
+    }
+
+    /// Records that the block of `block` bytes allocated for `layout` was
+    /// freed.
+    pub fn dealloc(&mut self, layout: &Layout, block: usize) {
+        self.requested -= layout.size();
+        self.allocated -= block;
+        self.allocations -= 1;
+        self.classes[class_of(block)] -= 1;
+    }
+
+    /// Records that an allocation failed.
+    pub fn fail(&mut self) {
+        self.failed += 1;
+    }
+
+    /// Returns the number of bytes in allocated blocks.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the statistics of an allocator of `total` bytes of which
+    /// `free` are free, the largest free block being `largest_free` bytes.
+    pub fn stats(&self, total: usize, free: usize, largest_free: usize) -> HeapStats {
+        HeapStats {
+            total,
+            free,
+            largest_free,
+            requested: self.requested,
+            allocated: self.allocated,
+            high_water: self.high_water,
+            allocations: self.allocations,
+            failed: self.failed,
+            classes: self.classes,
+        }
+    }
+}
@@ -1,3 +1,34 @@
+extern crate alloc;
+use alloc::raw_vec::RawVec;
+
+use crate::allocator::util::align_up;
+
+/// Heap memory standing in for a region of memory handed to an allocator.
+/// The memory is freed when the region is dropped.
+struct Region {
+    _mem: RawVec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl Region {
+    /// Returns a region of `size` bytes starting at a multiple of `align`.
+    fn new(size: usize, align: usize) -> Region {
+        let mem: RawVec<u8> = RawVec::with_capacity(size + align - 1);
+        let start = align_up(mem.ptr() as usize, align);
+        Region { _mem: mem, start, end: start + size }
+    }
+
+    /// Returns a region of `size` bytes starting at a multiple of `align`,
+    /// along with what `init` makes of its start and end addresses. The
+    /// region must be kept as long as that is used.
+    fn with<T, F: FnOnce(usize, usize) -> T>(size: usize, align: usize, init: F) -> (Region, T) {
+        let region = Region::new(size, align);
+        let value = init(region.start, region.end);
+        (region, value)
+    }
+}
+
 mod align_util {
     use crate::allocator::util::{align_down, align_up};
 
@@ -71,20 +102,24 @@ mod align_util {
 }
 
 mod allocator {
-    extern crate alloc;
-    use alloc::raw_vec::RawVec;
-
     use core::alloc::Layout;
 
-    use crate::allocator::{bin, bump, LocalAlloc};
+    use super::Region;
+
+    use crate::allocator::stats::class_of;
+    use crate::allocator::{bin, buddy, bump, LocalAlloc};
 
     macro_rules! test_allocators {
+        (@freeing, $bin:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
+            test_allocators!(@bin, $bin, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
+        );
+
         (@$kind:ident, $name:ident, $mem:expr, |$info:pat| $block:expr) => {
             #[test]
             fn $name() {
-                let mem: RawVec<u8> = RawVec::with_capacity($mem);
-                let start = mem.ptr() as usize;
-                let end = start + $mem;
+                let region = Region::new($mem, 1);
+                let (start, end) = (region.start, region.end);
 
                 let allocator = $kind::Allocator::new(start, end);
                 let $info = (start, end, allocator);
@@ -96,9 +131,10 @@ mod allocator {
             }
         };
 
-        ($bin:ident, $bump:ident, $mem:expr, |$info:pat| $block:expr) => (
+        ($bin:ident, $bump:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
             test_allocators!(@bin, $bin, $mem, |$info| $block);
             test_allocators!(@bump, $bump, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
         );
     }
 
@@ -157,12 +193,12 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_exhausted, bump_exhausted, 128, |(_, _, mut a)| {
+    test_allocators!(bin_exhausted, bump_exhausted, buddy_exhausted, 128, |(_, _, mut a)| {
         let result = a.alloc(layout!(1024, 128));
         assert!(result.is_null());
     });
 
-    test_allocators!(bin_alloc, bump_alloc, 8 * (1 << 20), |(start, end, a)| {
+    test_allocators!(bin_alloc, bump_alloc, buddy_alloc, 8 * (1 << 20), |(start, end, a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 128),
@@ -188,7 +224,7 @@ mod allocator {
         test_layouts!(layouts, start, end, a);
     });
 
-    test_allocators!(bin_alloc_2, bump_alloc_2, 16 * (1 << 20), |(
+    test_allocators!(bin_alloc_2, bump_alloc_2, buddy_alloc_2, 16 * (1 << 20), |(
         start,
         end,
         a,
@@ -208,7 +244,7 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_dealloc_s, bump_dealloc_s, 4096, |(_, _, mut a)| {
+    test_allocators!(bin_dealloc_s, bump_dealloc_s, buddy_dealloc_s, 4096, |(_, _, mut a)| {
         let layouts = [layout!(16, 16), layout!(16, 128), layout!(16, 256)];
 
         let mut pointers: Vec<(usize, Layout)> = vec![];
@@ -226,7 +262,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_1, 65536, |(_, _, mut a)| {
+    test_allocators!(@freeing, bin_dealloc_1, buddy_dealloc_1, 65536, |(_, _, mut a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 256),
@@ -266,7 +302,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_2, 8192, |(_, _, mut a)| {
+    test_allocators!(@freeing, bin_dealloc_2, buddy_dealloc_2, 8192, |(_, _, mut a)| {
         let layouts = [
             layout!(3072, 16),
             layout!(512, 32),
@@ -289,6 +325,169 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@freeing, bin_stats, buddy_stats, 65536, |(_, _, mut a)| {
+        let empty = a.stats();
+        assert_eq!(empty.allocations, 0);
+        assert_eq!(empty.requested, 0);
+        assert!(empty.free <= empty.total);
+
+        let small = layout!(20, 8);
+        let large = layout!(1000, 16);
+        let p1 = a.alloc(small.clone());
+        let p2 = a.alloc(small.clone());
+        let p3 = a.alloc(large.clone());
+        assert!(a.alloc(layout!(1 << 20, 8)).is_null());
+
+        let stats = a.stats();
+        assert_eq!(stats.allocations, 3);
+        assert_eq!(stats.requested, 1040);
+        assert_eq!(stats.allocated, 32 + 32 + 1024);
+        assert_eq!(stats.fragmentation(), 48);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.classes[class_of(32)], 2);
+        assert_eq!(stats.classes[class_of(1024)], 1);
+        assert_eq!(stats.classes.iter().sum::<usize>(), 3);
+
+        a.dealloc(p1, small.clone());
+        a.dealloc(p3, large.clone());
+        let stats = a.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.requested, 20);
+        assert_eq!(stats.allocated, 32);
+        assert_eq!(stats.high_water, 32 + 32 + 1024);
+
+        a.dealloc(p2, small.clone());
+        assert_eq!(a.stats().allocated, 0);
+    });
+
+    /// Returns the size of the largest block `a` can allocate.
+    fn largest_block(a: &mut buddy::Allocator) -> usize {
+        let mut size = 1 << 30;
+        while size >= 8 {
+            let ptr = unsafe { a.alloc(layout!(size, 8)) };
+            if !ptr.is_null() {
+                unsafe { a.dealloc(ptr, layout!(size, 8)) };
+                return size;
+            }
+            size /= 2;
+        }
+        0
+    }
+
+    /// Allocates blocks of `layout` until memory is exhausted.
+    fn alloc_all(a: &mut buddy::Allocator, layout: Layout) -> Vec<*mut u8> {
+        let mut ptrs = vec![];
+        loop {
+            let ptr = unsafe { a.alloc(layout.clone()) };
+            if ptr.is_null() {
+                return ptrs;
+            }
+            assert!(ptr as usize % layout.align() == 0,
+                "{:x} is not aligned to {}", ptr as usize, layout.align());
+            ptrs.push(ptr);
+        }
+    }
+
+    test_allocators!(@buddy, buddy_split_freed, 8 * (1 << 20), |(_, _, mut a)| {
+        let largest = largest_block(&mut a);
+        let big = layout!(1 << 20, 8);
+        let small = layout!(64, 8);
+
+        // memory freed as large blocks must be reusable for small ones
+        let ptrs = alloc_all(&mut a, big.clone());
+        assert!(ptrs.len() >= 6, "only {} 1 MiB blocks", ptrs.len());
+        for &ptr in &ptrs {
+            a.dealloc(ptr, big.clone());
+        }
+
+        let smalls = alloc_all(&mut a, small.clone());
+        assert!(smalls.len() >= ptrs.len() * ((1 << 20) / 64),
+            "only {} small blocks after freeing {} large ones", smalls.len(), ptrs.len());
+        for &ptr in &smalls {
+            scribble(ptr, small.size());
+        }
+
+        // and the small blocks must merge back into large ones
+        for ptr in smalls {
+            a.dealloc(ptr, small.clone());
+        }
+        assert_eq!(largest_block(&mut a), largest);
+        assert_eq!(alloc_all(&mut a, big.clone()).len(), ptrs.len());
+    });
+
+    test_allocators!(@buddy, buddy_checkerboard, 4 * (1 << 20), |(_, _, mut a)| {
+        let largest = largest_block(&mut a);
+        let free = a.free();
+        let page = layout!(4096, 4096);
+
+        // free every other block, leaving holes that cannot merge
+        let ptrs = alloc_all(&mut a, page.clone());
+        assert!(!ptrs.is_empty());
+        let (kept, freed): (Vec<_>, Vec<_>) =
+            ptrs.iter().enumerate().partition(|&(i, _)| i % 2 == 0);
+        for &(_, &ptr) in &freed {
+            a.dealloc(ptr, page.clone());
+        }
+
+        // the holes are reused before memory runs out again
+        let refilled = alloc_all(&mut a, page.clone());
+        assert_eq!(refilled.len(), freed.len());
+
+        for ptr in refilled {
+            a.dealloc(ptr, page.clone());
+        }
+        for (_, &ptr) in kept {
+            a.dealloc(ptr, page.clone());
+        }
+        assert_eq!(a.free(), free);
+        assert_eq!(largest_block(&mut a), largest);
+    });
+
+    test_allocators!(@buddy, buddy_random, 16 * (1 << 20), |(start, end, mut a)| {
+        let largest = largest_block(&mut a);
+        let free = a.free();
+
+        // a simple linear congruential generator keeps the test reproducible
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        let mut live: Vec<(*mut u8, Layout, u8)> = vec![];
+        for round in 0..20_000 {
+            if live.is_empty() || random() % 3 != 0 {
+                let size = 1 + random() % (1 << (random() % 16));
+                let layout = layout!(size, 1 << (random() % 13));
+                let ptr = a.alloc(layout.clone());
+                if ptr.is_null() {
+                    continue;
+                }
+                let (addr, size) = (ptr as usize, layout.size());
+                assert!(addr >= start && addr + size <= end,
+                    "{:x} + {:x} is out of bounds", addr, size);
+                assert!(addr % layout.align() == 0,
+                    "{:x} is not aligned to {}", addr, layout.align());
+                let tag = round as u8;
+                ::core::ptr::write_bytes(ptr, tag, size);
+                live.push((ptr, layout, tag));
+            } else {
+                let (ptr, layout, tag) = live.swap_remove(random() % live.len());
+                // overlapping blocks would have overwritten each other's tags
+                for i in 0..layout.size() {
+                    assert_eq!(*ptr.add(i), tag, "block at {:x} was overwritten", ptr as usize);
+                }
+                a.dealloc(ptr, layout);
+            }
+        }
+
+        for (ptr, layout, _) in live {
+            a.dealloc(ptr, layout);
+        }
+        assert_eq!(a.free(), free);
+        assert_eq!(largest_block(&mut a), largest);
+    });
 }
 
 mod linked_list {
@@ -383,3 +582,378 @@ mod linked_list {
         assert_eq!(iter.next(), None);
     }
 }
+
+mod frame {
+    use crate::allocator::frame::{FrameAllocator, FrameStats};
+    use crate::param::PAGE_SIZE;
+
+    use super::Region;
+
+    /// Returns a frame allocator of at least `count` frames.
+    fn frames(count: usize) -> (Region, FrameAllocator) {
+        // Room for the frames, the reference count table and the alignment
+        // of the first frame.
+        Region::with((count + 2) * PAGE_SIZE, 1, |start, end| unsafe {
+            FrameAllocator::new(start, end)
+        })
+    }
+
+    #[test]
+    fn frames_in_region() {
+        let (region, mut a) = frames(16);
+        let (start, end) = (region.start, region.end);
+        let total = a.stats().total;
+        assert!(total >= 16, "only {} frames", total);
+        for _ in 0..total {
+            let frame = a.alloc().expect("frame");
+            assert!(frame >= start && frame + PAGE_SIZE <= end);
+            assert_eq!(frame % PAGE_SIZE, 0, "{:x} is not page aligned", frame);
+            assert!(a.contains(frame));
+        }
+        assert_eq!(a.alloc(), None);
+        assert_eq!(a.stats().free, 0);
+    }
+
+    #[test]
+    fn frames_distinct() {
+        let (_region, mut a) = frames(16);
+        let mut frames = vec![];
+        while let Some(frame) = a.alloc() {
+            frames.push(frame);
+        }
+        frames.sort();
+        for window in frames.windows(2) {
+            assert!(window[1] - window[0] >= PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn frames_reuse() {
+        let (_region, mut a) = frames(4);
+        let total = a.stats().total;
+        for _ in 0..1000 {
+            let frame = a.alloc().expect("frame");
+            assert_eq!(a.refcount(frame), 1);
+            assert!(a.release(frame));
+            assert_eq!(a.refcount(frame), 0);
+        }
+        assert_eq!(a.stats().free, total);
+    }
+
+    #[test]
+    fn frames_refcount() {
+        let (_region, mut a) = frames(4);
+        let frame = a.alloc().expect("frame");
+        assert_eq!(a.retain(frame), 2);
+        assert_eq!(a.retain(frame), 3);
+        assert_eq!(a.stats().shared, 1);
+
+        assert!(!a.release(frame));
+        assert!(!a.release(frame));
+        assert_eq!(a.stats().shared, 0);
+        assert_eq!(a.stats().used(), 1);
+        assert!(a.release(frame));
+        assert_eq!(a.stats().used(), 0);
+    }
+
+    #[test]
+    fn frames_stats() {
+        let (_region, mut a) = frames(8);
+        let total = a.stats().total;
+        let first = a.alloc().expect("frame");
+        let second = a.alloc().expect("frame");
+        a.retain(second);
+        assert_eq!(a.stats(), FrameStats { total, free: total - 2, shared: 1 });
+        a.release(first);
+        assert_eq!(a.stats(), FrameStats { total, free: total - 1, shared: 1 });
+    }
+
+    #[test]
+    fn frames_contiguous() {
+        let (_region, mut a) = frames(16);
+        let single = a.alloc().expect("frame");
+        let run = a.alloc_contiguous(4).expect("contiguous frames");
+        assert!(run >= single + PAGE_SIZE || run + 4 * PAGE_SIZE <= single);
+        for i in 0..4 {
+            assert_eq!(a.refcount(run + i * PAGE_SIZE), 1);
+        }
+
+        // A run is found past the frames in use.
+        a.release(single);
+        let total = a.stats().total;
+        assert_eq!(a.alloc_contiguous(total), None);
+        assert!(a.alloc_contiguous(total - 5).is_some());
+        assert_eq!(a.stats().free, 1);
+    }
+
+    #[test]
+    fn frames_empty() {
+        let (_region, mut a) =
+            Region::with(PAGE_SIZE, 1, |start, end| unsafe { FrameAllocator::new(start, end) });
+        assert_eq!(a.stats().total, 0);
+        assert_eq!(a.alloc(), None);
+        assert_eq!(a.alloc_contiguous(1), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn frames_double_release() {
+        let (_region, mut a) = frames(2);
+        let frame = a.alloc().expect("frame");
+        a.release(frame);
+        a.release(frame);
+    }
+}
+
+mod slab {
+    use crate::allocator::slab::{in_use, Depot, MagazineInner};
+
+    use super::Region;
+
+    /// Returns a depot of `size` byte objects aligned to `align`, with one
+    /// slab of `mem` bytes.
+    fn depot(size: usize, align: usize, mem: usize) -> (Region, Depot) {
+        Region::with(mem, 1, |start, end| {
+            let mut depot = Depot::new(size, align);
+            unsafe { depot.add_slab(start, end) };
+            depot
+        })
+    }
+
+    #[test]
+    fn depot_carve() {
+        let (region, mut depot) = depot(48, 16, 4096);
+        let (start, end) = (region.start, region.end);
+        assert_eq!(depot.slabs(), 1);
+        assert!(depot.objects() >= 4096 / 48 - 1);
+        assert_eq!(depot.free_count(), depot.objects());
+
+        let mut objects = vec![];
+        while let Some(addr) = depot.alloc() {
+            assert!(addr >= start && addr + 48 <= end, "{:x} is out of bounds", addr);
+            assert!(addr % 16 == 0, "{:x} is not aligned to 16", addr);
+            objects.push(addr);
+        }
+        assert_eq!(objects.len(), depot.objects());
+        assert_eq!(depot.free_count(), 0);
+
+        objects.sort();
+        for window in objects.windows(2) {
+            assert!(window[1] - window[0] >= 48, "{:x} overlaps {:x}", window[0], window[1]);
+        }
+    }
+
+    #[test]
+    fn depot_rounds_size() {
+        let (_region, mut depot) = depot(20, 16, 1024);
+        let a = depot.alloc().expect("object");
+        let b = depot.alloc().expect("object");
+        assert_eq!((a as isize - b as isize).abs(), 32);
+    }
+
+    #[test]
+    fn depot_reuse() {
+        let (_region, mut depot) = depot(64, 8, 1024);
+        let objects = depot.objects();
+        let a = depot.alloc().expect("object");
+        unsafe { depot.free(a) };
+        assert_eq!(depot.free_count(), objects);
+        assert_eq!(depot.alloc(), Some(a));
+    }
+
+    #[test]
+    fn depot_grows() {
+        let (_region, mut depot) = depot(64, 8, 512);
+        let objects = depot.objects();
+        let more = Region::new(512, 1);
+        unsafe { depot.add_slab(more.start, more.end) };
+        assert_eq!(depot.slabs(), 2);
+        assert!(depot.objects() > objects);
+        assert_eq!(depot.free_count(), depot.objects());
+    }
+
+    #[test]
+    #[should_panic]
+    fn depot_objects_too_small() {
+        depot(4, 4, 1024);
+    }
+
+    #[test]
+    fn magazine_refill_from_depot() {
+        let (region, mut depot) = depot(64, 8, 1024);
+        let (start, end) = (region.start, region.end);
+        let objects = depot.objects();
+        let mut magazine = MagazineInner::new();
+        let taken = magazine.refill(&mut depot, 8, |_| panic!("depot grown"));
+        assert_eq!(taken, 8);
+        assert_eq!(magazine.len(), 8);
+        assert_eq!(depot.free_count(), objects - 8);
+
+        // Already full enough.
+        assert_eq!(magazine.refill(&mut depot, 4, |_| None), 0);
+
+        while let Some(addr) = magazine.pop() {
+            assert!(addr >= start && addr + 64 <= end);
+        }
+        assert_eq!(magazine.pop(), None);
+    }
+
+    #[test]
+    fn magazine_refill_grows_depot() {
+        let (_region, mut depot) = depot(64, 8, 256);
+        let objects = depot.objects();
+        let mut slabs = vec![];
+        let mut magazine = MagazineInner::new();
+        let taken = magazine.refill(&mut depot, objects + 2, |size| {
+            let slab = Region::new(size, 1);
+            let start = slab.start;
+            slabs.push(slab);
+            Some(start)
+        });
+        assert_eq!(taken, objects + 2);
+        assert_eq!(slabs.len(), 1);
+        assert_eq!(depot.slabs(), 2);
+    }
+
+    #[test]
+    fn magazine_refill_stops_without_slab() {
+        let (_region, mut depot) = depot(64, 8, 256);
+        let objects = depot.objects();
+        let mut magazine = MagazineInner::new();
+        assert_eq!(magazine.refill(&mut depot, objects + 2, |_| None), objects);
+        assert_eq!(magazine.len(), objects);
+        assert_eq!(depot.free_count(), 0);
+        assert_eq!(depot.slabs(), 1);
+    }
+
+    #[test]
+    fn magazine_flush_when_full() {
+        let (_region, mut depot) = depot(64, 8, 2048);
+        let objects = depot.objects();
+        let mut magazine = MagazineInner::new();
+        magazine.refill(&mut depot, 16, |_| None);
+        let newest = magazine.pop().unwrap();
+        magazine.push(newest);
+
+        assert_eq!(magazine.flush(&mut depot), 8);
+        assert_eq!(magazine.len(), 8);
+        assert_eq!(depot.free_count(), objects - 8);
+        // The older half goes back, the newest objects stay.
+        assert_eq!(magazine.pop(), Some(newest));
+
+        let mut magazine = MagazineInner::new();
+        assert_eq!(magazine.flush(&mut depot), 0);
+    }
+
+    #[test]
+    fn magazine_stats() {
+        let (_region, mut depot) = depot(64, 8, 1024);
+        let mut magazine = MagazineInner::new();
+        let mut cached = magazine.refill(&mut depot, 8, |_| None);
+        assert_eq!(in_use(&depot, cached), 0);
+
+        let a = magazine.pop().unwrap();
+        let b = magazine.pop().unwrap();
+        cached -= 2;
+        assert_eq!(in_use(&depot, cached), 2);
+
+        magazine.push(a);
+        cached += 1;
+        assert_eq!(in_use(&depot, cached), 1);
+
+        cached -= magazine.flush(&mut depot);
+        assert_eq!(in_use(&depot, cached), 1);
+        unsafe { depot.free(b) };
+        assert_eq!(in_use(&depot, cached), 0);
+    }
+}
+
+mod check {
+    use core::alloc::Layout;
+
+    use crate::allocator::check::*;
+
+    use super::Region;
+
+    /// Returns a block of `size` bytes aligned to `align` armed with red
+    /// zones, and its layout.
+    fn block(size: usize, align: usize) -> (Region, *mut u8, Layout) {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let (padded, _) = padded(&layout);
+        let (region, ptr) = Region::with(padded.size(), padded.align(), |start, _| unsafe {
+            arm(start as *mut u8, &layout)
+        });
+        (region, ptr, layout)
+    }
+
+    #[test]
+    fn check_padded() {
+        for &(size, align) in [(1, 1), (24, 8), (100, 64), (4096, 4096)].iter() {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let (padded, front) = padded(&layout);
+            assert!(front >= REDZONE_SIZE && front % align == 0);
+            assert!(padded.size() >= front + size + REDZONE_SIZE);
+            assert_eq!(padded.align(), align);
+        }
+    }
+
+    #[test]
+    fn check_intact() {
+        let (region, ptr, layout) = block(40, 8);
+        let base = region.start;
+        unsafe {
+            assert_eq!(ptr as usize % 8, 0);
+            ::core::ptr::write_bytes(ptr, 0xAF, layout.size());
+            assert_eq!(check(ptr, &layout) as usize, base);
+            poison(ptr, &layout);
+            assert_eq!(check_poison(ptr, &layout) as usize, base);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "red zone after block")]
+    fn check_overrun() {
+        let (_region, ptr, layout) = block(40, 8);
+        unsafe {
+            ::core::ptr::write_bytes(ptr, 0xAF, layout.size() + 1);
+            check(ptr, &layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "red zone before block")]
+    fn check_underrun() {
+        let (_region, ptr, layout) = block(40, 16);
+        unsafe {
+            *ptr.sub(1) = 0;
+            check(ptr, &layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "written to")]
+    fn check_use_after_free() {
+        let (_region, ptr, layout) = block(64, 8);
+        unsafe {
+            poison(ptr, &layout);
+            *ptr.add(10) = 0;
+            check_poison(ptr, &layout);
+        }
+    }
+
+    #[test]
+    fn check_quarantine() {
+        let mut quarantine = Quarantine::new();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let mut evicted = 0;
+        for addr in 1..=100usize {
+            if let Some((ptr, old)) = quarantine.insert((addr * 64) as *mut u8, &layout) {
+                evicted += 1;
+                // blocks leave in the order they came in
+                assert_eq!(ptr as usize, evicted * 64);
+                assert_eq!(old, layout);
+            }
+        }
+        assert!(evicted > 0 && evicted < 100);
+    }
+}
@@ -1,32 +1,146 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt;
-use pi::uart::MiniUart;
+use pi::framebuffer::{FrameBuffer, TextConsole};
+use pi::interrupt::Controller;
+use pi::uart::Uart;
 use shim::io;
 
 use crate::mutex::Mutex;
+use crate::param::{FB_HEIGHT, FB_WIDTH, NCORES};
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::watchdog::WATCHDOG;
+use crate::GLOABAL_IRQ;
+
+/// The UART the console talks to. The mini UART is used unless the kernel is
+/// built with the `pl011` feature.
+#[cfg(not(feature = "pl011"))]
+pub type ConsoleUart = pi::uart::MiniUart;
+#[cfg(feature = "pl011")]
+pub type ConsoleUart = pi::uart::Pl011;
+
+/// Capacity of each of the console's receive and transmit buffers.
+const RING_BUF_SIZE: usize = 1024;
+/// Longest line that can be edited in canonical mode, including the newline.
+const MAX_LINE_LEN: usize = 256;
+
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+const BELL: u8 = 7;
+const BACK: u8 = 8;
+const DEL: u8 = 127;
+
+/// A fixed-size FIFO queue of bytes.
+struct RingBuffer {
+    buf: [u8; RING_BUF_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer {
+            buf: [0; RING_BUF_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == RING_BUF_SIZE
+    }
+
+    /// Appends `byte`. Returns `false` if the buffer is full.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % RING_BUF_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the oldest byte.
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % RING_BUF_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
 
 /// A global singleton allowing read/write access to the console.
+///
+/// Until `enable_interrupts()` is called the console talks to the UART
+/// directly. Afterwards received bytes are collected into a ring buffer by
+/// the UART interrupt, so no input is lost while nobody is reading, and
+/// output is queued in another ring buffer that the interrupt drains.
+///
+/// Once a framebuffer console is set with `set_mirror()`, everything written
+/// to the UART is drawn on it too.
 pub struct Console {
-    inner: Option<MiniUart>,
+    inner: Option<ConsoleUart>,
+    rx: RingBuffer,
+    tx: RingBuffer,
+    interrupts: bool,
+    /// Number of received bytes dropped because `rx` was full.
+    overruns: usize,
+    /// The process the user-space console input belongs to, if any.
+    owner: Option<u64>,
+    /// In canonical mode input is edited a line at a time and only complete
+    /// lines can be read. In raw mode every byte can be read as it arrives.
+    canonical: bool,
+    /// Whether input handed to user space is echoed back.
+    echo: bool,
+    /// The line being edited in canonical mode.
+    line: Vec<u8>,
+    /// Input ready to be read by the owner.
+    input: RingBuffer,
+    /// An end-of-file (`Ctrl-D` on an empty line) is pending.
+    eof: bool,
+    /// The framebuffer console output is mirrored to, if any.
+    mirror: Option<TextConsole>,
 }
 
 impl Console {
     /// Creates a new instance of `Console`.
     const fn new() -> Console {
-        Console { inner: None }
+        Console {
+            inner: None,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            interrupts: false,
+            overruns: 0,
+            owner: None,
+            canonical: true,
+            echo: true,
+            line: Vec::new(),
+            input: RingBuffer::new(),
+            eof: false,
+            mirror: None,
+        }
     }
 
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
         match self.inner {
-            None => self.inner = Some(MiniUart::new()),
+            None => self.inner = Some(ConsoleUart::new()),
             _ => (),
         }
     }
 
-    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
+    /// Returns a mutable borrow to the inner `ConsoleUart`, initializing it as
     /// needed.
-    fn inner(&mut self) -> &mut MiniUart {
+    fn inner(&mut self) -> &mut ConsoleUart {
         match self.inner {
             Some(ref mut uart) => uart,
             _ => {
@@ -36,48 +150,452 @@ impl Console {
         }
     }
 
-    /// Reads a byte from the UART device, blocking until a byte is available.
+    /// Switches the console to interrupt-driven mode. The caller is
+    /// responsible for routing `ConsoleUart::INTERRUPT` to `handle_irq()`.
+    pub fn enable_interrupts(&mut self) {
+        self.interrupts = true;
+        self.inner().set_rx_interrupt(true);
+        self.pump_tx();
+    }
+
+    /// Switches the console back to polled mode, writing out everything still
+    /// queued for transmission first. Used when interrupts may never be
+    /// serviced again, e.g. on panic.
+    pub fn disable_interrupts(&mut self) {
+        let uart = self.inner.get_or_insert_with(ConsoleUart::new);
+        uart.set_rx_interrupt(false);
+        uart.set_tx_interrupt(false);
+        while let Some(byte) = self.tx.pop() {
+            uart.write_byte(byte);
+        }
+        self.interrupts = false;
+    }
+
+    /// Services the UART interrupt: moves received bytes into the receive
+    /// buffer and refills the transmit FIFO.
+    pub fn handle_irq(&mut self) {
+        self.poll_rx();
+        self.pump_tx();
+    }
+
+    /// Returns the number of received bytes dropped because the receive
+    /// buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    /// Moves every byte waiting in the UART's input FIFO to `rx`.
+    fn poll_rx(&mut self) {
+        while self.inner().has_byte() {
+            let byte = self.inner().read_byte();
+            if !self.rx.push(byte) {
+                self.overruns += 1;
+            }
+        }
+    }
+
+    /// Moves bytes from `tx` to the UART's output FIFO while it has room, and
+    /// asks for an interrupt once it drains if `tx` is not empty yet.
+    fn pump_tx(&mut self) {
+        while !self.tx.is_empty() && self.inner().can_write() {
+            let byte = self.tx.pop().unwrap();
+            self.inner().write_byte(byte);
+        }
+        let pending = !self.tx.is_empty();
+        if self.interrupts {
+            self.inner().set_tx_interrupt(pending);
+        }
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read. In
+    /// interrupt-driven mode only the receive buffer is looked at.
+    pub fn has_byte(&mut self) -> bool {
+        if !self.interrupts {
+            return self.inner().has_byte();
+        }
+        !self.rx.is_empty()
+    }
+
+    /// Reads a byte if one is available. Never blocks. In interrupt-driven
+    /// mode the byte is taken from the receive buffer.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if !self.interrupts {
+            return match self.inner().has_byte() {
+                true => Some(self.inner().read_byte()),
+                false => None,
+            };
+        }
+        self.rx.pop()
+    }
+
+    /// Reads a byte, blocking until a byte is available.
+    ///
+    /// The UART interrupt cannot take the console while it is held here, so
+    /// the core sleeps with `wfi` and then services the interrupt itself. The
+    /// interrupt is only taken on core 0; other cores wake up on their next
+    /// timer tick. Prefer `console::read_byte()`, which releases the lock
+    /// while waiting.
     pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+            // The caller may wait for long with interrupts masked.
+            WATCHDOG.pet();
+            if self.interrupts {
+                aarch64::wfi();
+                self.poll_rx();
+            }
+        }
     }
 
-    /// Writes the byte `byte` to the UART device.
+    /// Mirrors the console output to `mirror` from now on, or stops
+    /// mirroring if `mirror` is `None`.
+    pub fn set_mirror(&mut self, mirror: Option<TextConsole>) {
+        self.mirror = mirror;
+    }
+
+    /// Returns the framebuffer console output is mirrored to, if any.
+    pub fn mirror(&mut self) -> Option<&mut TextConsole> {
+        self.mirror.as_mut()
+    }
+
+    fn mirror_bytes(&mut self, bytes: &[u8]) {
+        if let Some(mirror) = self.mirror.as_mut() {
+            for &byte in bytes {
+                mirror.write_byte(byte);
+            }
+        }
+    }
+
+    /// Writes the byte `byte` to the UART device and the mirror.
+    ///
+    /// In interrupt-driven mode the byte is queued. If the queue is full, this
+    /// waits for the UART to take the oldest queued bytes.
     pub fn write_byte(&mut self, byte: u8) {
-        self.inner().write_byte(byte)
+        self.mirror_bytes(&[byte]);
+        self.queue_byte(byte);
+    }
+
+    /// Writes the byte `byte` to the UART device only.
+    fn queue_byte(&mut self, byte: u8) {
+        if !self.interrupts {
+            return self.inner().write_byte(byte);
+        }
+        while self.tx.is_full() {
+            let oldest = self.tx.pop().unwrap();
+            self.inner().write_byte(oldest);
+        }
+        self.tx.push(byte);
+        self.pump_tx();
+    }
+}
+
+/// Line discipline of the console input handed to user processes.
+///
+/// Only one process, the owner, reads the console input at a time. A process
+/// becomes the owner by reading or setting the input mode while nobody owns
+/// it, and stays the owner until it releases the console or exits.
+impl Console {
+    /// Returns `true` if process `pid` may read the console input, i.e. it owns
+    /// the input or nobody does.
+    pub fn may_read(&self, pid: u64) -> bool {
+        match self.owner {
+            Some(owner) => owner == pid,
+            None => true,
+        }
+    }
+
+    /// Makes `pid` the owner of the console input if nobody owns it. Returns
+    /// `true` if `pid` is the owner afterwards.
+    pub fn claim(&mut self, pid: u64) -> bool {
+        if self.owner.is_none() {
+            self.owner = Some(pid);
+        }
+        self.owner == Some(pid)
+    }
+
+    /// Returns `true` if process `pid` owns the console input.
+    pub fn is_owner(&self, pid: u64) -> bool {
+        self.owner == Some(pid)
+    }
+
+    /// Gives up the console input if `pid` owns it. The input mode is reset
+    /// to canonical with echo and a partially edited line is discarded.
+    pub fn release(&mut self, pid: u64) {
+        if self.owner != Some(pid) {
+            return;
+        }
+        self.owner = None;
+        self.canonical = true;
+        self.echo = true;
+        self.line.clear();
+        self.eof = false;
+    }
+
+    /// Sets the input mode. Switching to raw mode makes a partially edited
+    /// line readable right away.
+    pub fn set_mode(&mut self, canonical: bool, echo: bool) {
+        if !canonical {
+            for i in 0..self.line.len() {
+                let byte = self.line[i];
+                self.input.push(byte);
+            }
+            self.line.clear();
+        }
+        self.canonical = canonical;
+        self.echo = echo;
+    }
+
+    /// Runs buffered input through the line discipline and returns `true`
+    /// if `read_input()` would return immediately.
+    pub fn input_ready(&mut self) -> bool {
+        self.discipline();
+        !self.input.is_empty() || self.eof
+    }
+
+    /// Reads processed input into `buf` and returns the number of bytes read.
+    /// In canonical mode at most one line is returned. Returns `Some(0)` once
+    /// for a pending end-of-file and `None` if nothing is ready.
+    pub fn read_input(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if buf.is_empty() {
+            return Some(0);
+        }
+        self.discipline();
+        if self.input.is_empty() {
+            if !self.eof {
+                return None;
+            }
+            self.eof = false;
+            return Some(0);
+        }
+        let mut bytes = 0;
+        while bytes < buf.len() {
+            match self.input.pop() {
+                Some(byte) => {
+                    buf[bytes] = byte;
+                    bytes += 1;
+                    if self.canonical && byte == b'\n' {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Some(bytes)
+    }
+
+    fn echo_bytes(&mut self, bytes: &[u8]) {
+        if self.echo {
+            for &byte in bytes {
+                self.write_byte(byte);
+            }
+        }
+    }
+
+    /// Moves received bytes into `input`, editing them first in canonical
+    /// mode. Stops while `input` cannot take a whole line, so nothing typed is
+    /// dropped.
+    fn discipline(&mut self) {
+        while self.input.len < RING_BUF_SIZE - MAX_LINE_LEN {
+            let byte = match self.try_read_byte() {
+                Some(byte) => byte,
+                None => return,
+            };
+            if !self.canonical {
+                self.input.push(byte);
+                self.echo_bytes(&[byte]);
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.line.push(b'\n');
+                    self.echo_bytes(b"\r\n");
+                    self.flush_line();
+                }
+                BACK | DEL => {
+                    if self.line.pop().is_some() {
+                        self.echo_bytes(&[BACK, b' ', BACK]);
+                    }
+                }
+                CTRL_C => {
+                    self.line.clear();
+                    self.echo_bytes(b"^C\r\n");
+                }
+                CTRL_D => {
+                    if self.line.is_empty() {
+                        self.eof = true;
+                        return;
+                    }
+                    self.flush_line();
+                }
+                byte if byte < 32 || byte > 127 => (),
+                byte => {
+                    if self.line.len() < MAX_LINE_LEN - 1 {
+                        self.line.push(byte);
+                        self.echo_bytes(&[byte]);
+                    } else {
+                        self.echo_bytes(&[BELL]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes the edited line readable.
+    fn flush_line(&mut self) {
+        for i in 0..self.line.len() {
+            let byte = self.line[i];
+            self.input.push(byte);
+        }
+        self.line.clear();
     }
 }
 
 impl io::Read for Console {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
+        if !self.interrupts {
+            return self.inner().read(buf);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.read_byte();
+        let mut byte_count = 1;
+        while byte_count < buf.len() {
+            match self.try_read_byte() {
+                Some(byte) => buf[byte_count] = byte,
+                None => break,
+            }
+            byte_count += 1;
+        }
+        Ok(byte_count)
     }
 }
 
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        self.mirror_bytes(buf);
+        if !self.interrupts {
+            return self.inner().write(buf);
+        }
+        for &byte in buf {
+            self.queue_byte(byte);
+        }
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        while !self.tx.is_empty() {
+            let byte = self.tx.pop().unwrap();
+            self.inner().write_byte(byte);
+        }
         Ok(())
     }
 }
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        self.mirror_bytes(s.as_bytes());
+        if !self.interrupts {
+            return self.inner().write_str(s);
+        }
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                self.queue_byte(b'\r');
+            }
+            self.queue_byte(byte);
+        }
+        Ok(())
     }
 }
 
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Routes the UART interrupt to the console and switches the console to
+/// interrupt-driven mode. The interrupt is only taken on core 0.
+pub fn initialize_interrupts() {
+    let int = ConsoleUart::INTERRUPT;
+    GLOABAL_IRQ.register(int, Box::new(|_tf| CONSOLE.lock().handle_irq()));
+    Controller::new().enable(int);
+    CONSOLE.lock().enable_interrupts();
+}
+
+/// Allocates a `FB_WIDTH` x `FB_HEIGHT` framebuffer and mirrors the console
+/// output to it. Without a display attached the console stays serial only.
+/// Must be called with the MMU enabled.
+pub fn initialize_framebuffer() {
+    match FrameBuffer::new(FB_WIDTH, FB_HEIGHT) {
+        Ok(fb) => {
+            info!("framebuffer: {:?}", fb);
+            CONSOLE.lock().set_mirror(Some(TextConsole::new(fb)));
+        }
+        Err(e) => warn!("framebuffer: unavailable: {:?}", e),
+    }
+}
+
+/// Reads a byte from the console, blocking until the UART interrupt puts one
+/// in the receive buffer. Unlike `Console::read_byte()`, the console is not
+/// held while waiting, so output from other cores and the UART interrupt are
+/// not held up.
+///
+/// The core sleeps with `wfi` until an interrupt arrives. The UART interrupt
+/// is serviced here as well, since the caller may have IRQs masked, as the
+/// `brk` shell does.
+pub fn read_byte() -> u8 {
+    loop {
+        let interrupts = {
+            let mut console = CONSOLE.lock();
+            if let Some(byte) = console.try_read_byte() {
+                return byte;
+            }
+            console.interrupts
+        };
+        // The `brk` shell waits here with interrupts masked.
+        WATCHDOG.pet();
+        if interrupts {
+            aarch64::wfi();
+            CONSOLE.lock().handle_irq();
+        }
+    }
+}
+
+/// Per-core buffers collecting `kprint!` output instead of the console. See
+/// `capture_output()`.
+static REDIRECT: [Mutex<Option<String>>; NCORES] = [
+    Mutex::new(None),
+    Mutex::new(None),
+    Mutex::new(None),
+    Mutex::new(None),
+];
+
+/// Executes `f` with the `kprint[ln]!` output of the current core collected
+/// into a `String` rather than written to the console. Returns the result of
+/// `f` and the collected output.
+///
+/// The caller must not be rescheduled on another core while `f` runs.
+pub fn capture_output<F, R>(f: F) -> (R, String)
+where
+    F: FnOnce() -> R,
+{
+    let core = aarch64::affinity();
+    *REDIRECT[core].lock() = Some(String::new());
+    let result = f();
+    let output = REDIRECT[core].lock().take().unwrap_or_default();
+    (result, output)
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     #[cfg(not(test))]
     {
         use core::fmt::Write;
+        if let Some(output) = REDIRECT[aarch64::affinity()].lock().as_mut() {
+            output.write_fmt(args).unwrap();
+            return;
+        }
         let mut console = CONSOLE.lock();
         console.write_fmt(args).unwrap();
     }
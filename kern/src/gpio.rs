@@ -0,0 +1,307 @@
+///! Dispatch of GPIO event interrupts to per-pin handlers and ownership of
+///! the pins used by user processes
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kernel_api::*;
+use pi::gpio::{self, Event, Function, Gpio, Pull};
+use pi::interrupt::{Controller, Interrupt};
+use pi::spi;
+
+use crate::i2c::I2C_BUS;
+use crate::mutex::Mutex;
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::traps::TrapFrame;
+use crate::GLOABAL_IRQ;
+
+/// A handler for the events of one pin. It is called with the pin number in
+/// the context of the GPIO interrupt on core 0, and must not register or
+/// unregister GPIO handlers itself.
+pub type GpioHandler = Box<dyn FnMut(u8, &mut TrapFrame) + Send>;
+
+/// The interrupts raised for detected GPIO events: one per bank of pins
+/// (0-27, 28-45 and 46-53). `Interrupt::Gpio3` is raised for every pin too
+/// and is left disabled so each event is dispatched once.
+const GPIO_BANK_INTERRUPTS: [Interrupt; 3] = [Interrupt::Gpio0, Interrupt::Gpio1, Interrupt::Gpio2];
+
+/// A thread-safe registry of per-pin GPIO event handlers.
+pub struct GpioIrq(Mutex<Option<BTreeMap<u8, GpioHandler>>>);
+
+impl GpioIrq {
+    pub const fn uninitialized() -> GpioIrq {
+        GpioIrq(Mutex::new(None))
+    }
+
+    /// Routes the GPIO bank interrupts to the registered pin handlers. Stale
+    /// events are cleared first.
+    pub fn initialize(&self) {
+        *self.0.lock() = Some(BTreeMap::new());
+        gpio::clear_events(gpio::pending_events());
+        for &int in GPIO_BANK_INTERRUPTS.iter() {
+            GLOABAL_IRQ.register(int, Box::new(|tf| crate::GPIO_IRQ.dispatch(tf)));
+            Controller::new().enable(int);
+        }
+    }
+
+    /// Switches `pin` to an input, enables detection of each of `events` on
+    /// it and calls `handler` whenever one of them is detected. Replaces any
+    /// handler already registered for `pin`.
+    ///
+    /// Level events are detected again as soon as the handler returns while
+    /// the level holds, so their handler should disable them with
+    /// `Gpio::disable_event()` or change the level.
+    pub fn register(&self, pin: u8, events: &[Event], handler: GpioHandler) {
+        let mut handlers = self.0.lock();
+        let handlers = handlers.as_mut().expect("Uninitialized GpioIrq");
+        let mut gpio = Gpio::new(pin).into_input();
+        gpio.disable_events();
+        gpio.clear_event();
+        handlers.insert(pin, handler);
+        for &event in events {
+            gpio.enable_event(event);
+        }
+    }
+
+    /// Disables event detection on `pin` and removes its handler. Returns
+    /// `false` if no handler was registered.
+    pub fn unregister(&self, pin: u8) -> bool {
+        let mut handlers = self.0.lock();
+        let handlers = handlers.as_mut().expect("Uninitialized GpioIrq");
+        let removed = handlers.remove(&pin).is_some();
+        if removed {
+            gpio::disable_events(1 << pin);
+            gpio::clear_events(1 << pin);
+        }
+        removed
+    }
+
+    /// Clears every pending event and calls the handler of its pin. Events on
+    /// pins without a handler are disabled.
+    fn dispatch(&self, tf: &mut TrapFrame) {
+        let pending = gpio::pending_events();
+        gpio::clear_events(pending);
+
+        let mut handlers = self.0.lock();
+        let handlers = match handlers.as_mut() {
+            Some(handlers) => handlers,
+            None => return,
+        };
+        for pin in (0..54u8).filter(|pin| pending & (1 << pin) != 0) {
+            match handlers.get_mut(&pin) {
+                Some(handler) => handler(pin, tf),
+                None => {
+                    trace!("GpioIrq: no handler for pin {}", pin);
+                    gpio::disable_events(1 << pin);
+                }
+            }
+        }
+    }
+}
+
+/// Number of GPIO pins user processes may claim: the pins on the header,
+/// 0 to 27.
+const USER_PIN_COUNT: usize = 28;
+
+/// Returns `true` if user processes may claim `pin`. The pins of the console
+/// UART and of the kernel's I2C and SPI buses stay with the kernel.
+fn is_user_pin(pin: u64) -> bool {
+    let (sda, scl) = I2C_BUS.pins();
+    let kernel_pins = [14, 15, sda as u64, scl as u64];
+    (pin as usize) < USER_PIN_COUNT
+        && !kernel_pins.contains(&pin)
+        && !spi::PINS.contains(&(pin as u8))
+}
+
+/// A pin claimed by a user process.
+#[derive(Debug, Copy, Clone)]
+struct UserPin {
+    owner: u64,
+    /// The pin's function, one of the `GPIO_*` function codes.
+    function: u64,
+    /// The `GPIO_RISING`/`GPIO_FALLING` edges detected on the pin.
+    edges: u64,
+}
+
+/// Tracks which process owns each user-accessible GPIO pin.
+///
+/// A process claims a free pin before configuring and using it, and the pin
+/// is reset to a plain input and freed again once the process releases it or
+/// exits.
+pub struct UserGpio {
+    pins: Mutex<[Option<UserPin>; USER_PIN_COUNT]>,
+    /// Bit mask of pins with an edge detected since their owner last waited.
+    /// Set from the GPIO interrupt, so it is not behind `pins`.
+    events: AtomicU64,
+}
+
+impl UserGpio {
+    pub const fn new() -> UserGpio {
+        UserGpio {
+            pins: Mutex::new([None; USER_PIN_COUNT]),
+            events: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `f` with the pin `pin` owned by `pid`.
+    fn with_pin<F, R>(&self, pid: u64, pin: u64, f: F) -> OsResult<R>
+    where
+        F: FnOnce(&mut UserPin) -> OsResult<R>,
+    {
+        if !is_user_pin(pin) {
+            return Err(OsError::InvalidArgument);
+        }
+        match self.pins.lock()[pin as usize].as_mut() {
+            Some(user_pin) if user_pin.owner == pid => f(user_pin),
+            _ => Err(OsError::NoAccess),
+        }
+    }
+
+    /// Makes `pid` the owner of `pin` and switches it to an input. Claiming a
+    /// pin `pid` already owns does nothing.
+    pub fn claim(&self, pid: u64, pin: u64) -> OsResult<()> {
+        if !is_user_pin(pin) {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut pins = self.pins.lock();
+        match pins[pin as usize] {
+            Some(user_pin) if user_pin.owner == pid => Ok(()),
+            Some(_) => Err(OsError::NoAccess),
+            None => {
+                Gpio::new(pin as u8).into_input();
+                pins[pin as usize] = Some(UserPin {
+                    owner: pid,
+                    function: GPIO_INPUT,
+                    edges: 0,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Frees `pin` if `pid` owns it.
+    pub fn release(&self, pid: u64, pin: u64) -> OsResult<()> {
+        if !is_user_pin(pin) {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut pins = self.pins.lock();
+        match pins[pin as usize] {
+            Some(user_pin) if user_pin.owner == pid => {
+                reset(pin as u8);
+                pins[pin as usize] = None;
+                Ok(())
+            }
+            _ => Err(OsError::NoAccess),
+        }
+    }
+
+    /// Frees every pin `pid` owns.
+    pub fn release_all(&self, pid: u64) {
+        let mut pins = self.pins.lock();
+        for pin in 0..USER_PIN_COUNT {
+            if pins[pin].map_or(false, |user_pin| user_pin.owner == pid) {
+                reset(pin as u8);
+                pins[pin] = None;
+            }
+        }
+    }
+
+    /// Sets the function and the pull-up/pull-down resistor of `pin`. Edge
+    /// detection is turned off.
+    pub fn configure(&self, pid: u64, pin: u64, function: u64, pull: u64) -> OsResult<()> {
+        let alt = match function {
+            GPIO_INPUT => Function::Input,
+            GPIO_OUTPUT => Function::Output,
+            GPIO_ALT0 => Function::Alt0,
+            GPIO_ALT1 => Function::Alt1,
+            GPIO_ALT2 => Function::Alt2,
+            GPIO_ALT3 => Function::Alt3,
+            GPIO_ALT4 => Function::Alt4,
+            GPIO_ALT5 => Function::Alt5,
+            _ => return Err(OsError::InvalidArgument),
+        };
+        let pull = match pull {
+            GPIO_PULL_OFF => Pull::Off,
+            GPIO_PULL_DOWN => Pull::Down,
+            GPIO_PULL_UP => Pull::Up,
+            _ => return Err(OsError::InvalidArgument),
+        };
+        self.with_pin(pid, pin, |user_pin| {
+            if user_pin.edges != 0 {
+                crate::GPIO_IRQ.unregister(pin as u8);
+                user_pin.edges = 0;
+            }
+            let mut gpio = Gpio::new(pin as u8).into_alt(alt);
+            gpio.set_pull(pull);
+            user_pin.function = function;
+            Ok(())
+        })
+    }
+
+    /// Drives the output `pin` high if `level` is `true` and low otherwise.
+    pub fn write(&self, pid: u64, pin: u64, level: bool) -> OsResult<()> {
+        self.with_pin(pid, pin, |user_pin| {
+            if user_pin.function != GPIO_OUTPUT {
+                return Err(OsError::InvalidArgument);
+            }
+            let mut gpio = Gpio::new(pin as u8).into_output();
+            match level {
+                true => gpio.set(),
+                false => gpio.clear(),
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the level of `pin`.
+    pub fn read(&self, pid: u64, pin: u64) -> OsResult<bool> {
+        self.with_pin(pid, pin, |_| Ok(Gpio::new(pin as u8).level()))
+    }
+
+    /// Enables detection of `edges` on the input `pin` if it is not enabled
+    /// yet. Returns `true` if an edge was detected since the last call, and
+    /// forgets about it.
+    pub fn watch(&self, pid: u64, pin: u64, edges: u64) -> OsResult<bool> {
+        if edges == 0 || edges & !(GPIO_RISING | GPIO_FALLING) != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        self.with_pin(pid, pin, |user_pin| {
+            if user_pin.function != GPIO_INPUT {
+                return Err(OsError::InvalidArgument);
+            }
+            if user_pin.edges != edges {
+                self.events.fetch_and(!(1 << pin), Ordering::SeqCst);
+                let mut events = Vec::new();
+                if edges & GPIO_RISING != 0 {
+                    events.push(Event::RisingEdge);
+                }
+                if edges & GPIO_FALLING != 0 {
+                    events.push(Event::FallingEdge);
+                }
+                let handler = Box::new(|pin: u8, _tf: &mut TrapFrame| {
+                    USER_GPIO.events.fetch_or(1 << pin, Ordering::SeqCst);
+                });
+                crate::GPIO_IRQ.register(pin as u8, &events, handler);
+                user_pin.edges = edges;
+            }
+            Ok(self.take_event(pin))
+        })
+    }
+
+    /// Returns `true` if an edge was detected on `pin` since the last call,
+    /// and forgets about it. Does not take any lock.
+    pub fn take_event(&self, pin: u64) -> bool {
+        self.events.fetch_and(!(1 << pin), Ordering::SeqCst) & (1 << pin) != 0
+    }
+}
+
+/// Turns off edge detection on `pin` and makes it a plain input again.
+fn reset(pin: u8) {
+    crate::GPIO_IRQ.unregister(pin);
+    let mut gpio = Gpio::new(pin).into_input();
+    gpio.set_pull(Pull::Off);
+}
+
+/// The GPIO pins of user processes.
+pub static USER_GPIO: UserGpio = UserGpio::new();
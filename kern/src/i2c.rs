@@ -0,0 +1,50 @@
+///! The I2C bus of the GPIO header, shared by the kernel and user processes
+use kernel_api::{OsError, OsResult};
+use pi::i2c::{self, Bus, I2c};
+
+use crate::mutex::Mutex;
+use crate::param::I2C_CLOCK;
+
+/// The bus the kernel drives: BSC1 on GPIO pins 2 and 3.
+pub const I2C_BUS: Bus = Bus::Bsc1;
+
+/// A thread-safe I2C master. Each transfer runs to completion while the bus
+/// is held, so transfers of different processes never interleave.
+pub struct I2cDevice(Mutex<Option<I2c>>);
+
+impl I2cDevice {
+    pub const fn uninitialized() -> I2cDevice {
+        I2cDevice(Mutex::new(None))
+    }
+
+    /// Sets up `I2C_BUS` to run at `I2C_CLOCK`.
+    pub fn initialize(&self) {
+        let mut i2c = I2c::new(I2C_BUS);
+        i2c.set_clock(I2C_CLOCK);
+        info!("I2C: {:?} at {} Hz", I2C_BUS, i2c.clock());
+        *self.0.lock() = Some(i2c);
+    }
+
+    /// Writes `data` to the device at the 7-bit address `addr`, then reads
+    /// `buf.len()` bytes from it into `buf` after a repeated start. Either
+    /// part may be empty, but not both.
+    pub fn transfer(&self, addr: u64, data: &[u8], buf: &mut [u8]) -> OsResult<()> {
+        if addr > 0x7f {
+            return Err(OsError::InvalidArgument);
+        }
+        let addr = addr as u8;
+        let mut i2c = self.0.lock();
+        let i2c = i2c.as_mut().expect("Uninitialized I2cDevice");
+        let result = match (data.is_empty(), buf.is_empty()) {
+            (true, true) => return Err(OsError::InvalidArgument),
+            (false, true) => i2c.write(addr, data),
+            (true, false) => i2c.read(addr, buf),
+            (false, false) => i2c.write_read(addr, data, buf),
+        };
+        result.map_err(|e| match e {
+            i2c::Error::Nack => OsError::NoEntry,
+            i2c::Error::ClockStretchTimeout | i2c::Error::Timeout => OsError::IoErrorTimedOut,
+            i2c::Error::InvalidLength => OsError::InvalidArgument,
+        })
+    }
+}
@@ -118,7 +118,7 @@ unsafe fn kinit() -> ! {
 /// Kernel entrypoint for core 1, 2, and 3
 #[no_mangle]
 pub unsafe extern "C" fn start2() -> ! {
-    SP.set(KERN_STACK_BASE - (KERN_STACK_SIZE * MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize));
+    SP.set(KERN_STACK_BASE - (KERN_STACK_STRIDE * MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize));
     kinit2()
 }
 
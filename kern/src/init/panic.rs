@@ -1,8 +1,21 @@
 use core::panic::PanicInfo;
-use crate::console::kprintln;
+use pi::framebuffer::Color;
+
+use crate::console::{kprintln, CONSOLE};
+use crate::param::PANIC_REBOOT_DELAY;
+use crate::watchdog::WATCHDOG;
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
+    // Done first, so the board is still reset if printing hangs.
+    WATCHDOG.panic();
+    // Nothing guarantees the UART interrupt is serviced from now on.
+    let mut console = CONSOLE.lock();
+    console.disable_interrupts();
+    if let Some(mirror) = console.mirror() {
+        mirror.set_colors(Color::WHITE, Color::RED);
+    }
+    drop(console);
     kprintln!("");
     kprintln!(r#"                             __"#);
     kprintln!(r#"                   _ ,___,-'",-=-."#);
@@ -46,5 +59,9 @@ fn panic(_info: &PanicInfo) -> ! {
         Some(message) => kprintln!("{}", message),
         None => kprintln!("Panic message cannot be determined"),
     }
+    if let Some(delay) = PANIC_REBOOT_DELAY {
+        kprintln!("");
+        kprintln!("Rebooting in {} seconds...", delay.min(pi::pm::MAX_TIMEOUT).as_secs());
+    }
     loop {}
 }
@@ -52,8 +52,6 @@ context_restore:
     msr TTBR0_EL1, x9
     msr TTBR1_EL1, x10
     dsb ishst
-    tlbi vmalle1
-    dsb ish
     isb
     ldp x9, x10, [SP], #16
     msr ELR_EL1, x9
@@ -107,6 +105,32 @@ context_restore:
     eret
 .endm
 
+// Like HANDLER, but first checks that the kernel stack is not (nearly) used
+// up, as pushing the trap frame would fault in the guard page below it again
+// and again. The stacks and guard pages fill the memory below KERN_STACK_BASE
+// (0x80000) in turns of 64 KiB, the guard pages having bit 16 clear.
+.macro KERNEL_HANDLER source, kind
+    .align 7
+    msr     TPIDR_EL1, x0
+    mov     x0, SP
+    sub     x0, x0, #1, LSL #12 // leave 4 KiB for the handler
+    cmp     x0, #0x80, LSL #12
+    b.hs    1f
+    tbz     x0, #16, stack_overflow
+1:
+    mrs     x0, TPIDR_EL1
+    stp     lr, xzr, [SP, #-16]!
+    stp     x28, x29, [SP, #-16]!
+
+    mov     x29, \source
+    movk    x29, \kind, LSL #16
+    bl      context_save
+
+    ldp     x28, x29, [SP], #16
+    ldp     lr, xzr, [SP], #16
+    eret
+.endm
+
 .align 11
 .global vectors
 vectors:
@@ -114,7 +138,7 @@ vectors:
     HANDLER 0, 1
     HANDLER 0, 2
     HANDLER 0, 3
-    HANDLER 1, 0
+    KERNEL_HANDLER 1, 0
     HANDLER 1, 1
     HANDLER 1, 2
     HANDLER 1, 3
@@ -126,3 +150,16 @@ vectors:
     HANDLER 3, 1
     HANDLER 3, 2
     HANDLER 3, 3
+
+// Reports a kernel stack overflow on the core's overflow stack (8 KiB each,
+// see `OVERFLOW_STACKS`). Does not return.
+stack_overflow:
+    mov     x0, SP
+    mrs     x1, MPIDR_EL1
+    and     x1, x1, #0xff
+    add     x1, x1, #1
+    adrp    x2, OVERFLOW_STACKS
+    add     x2, x2, :lo12:OVERFLOW_STACKS
+    add     x2, x2, x1, LSL #13
+    mov     SP, x2
+    bl      kernel_stack_overflow
@@ -20,6 +20,8 @@ extern crate log;
 pub mod allocator;
 pub mod console;
 pub mod fs;
+pub mod gpio;
+pub mod i2c;
 pub mod logger;
 pub mod mutex;
 pub mod net;
@@ -27,15 +29,21 @@ pub mod param;
 pub mod percore;
 pub mod process;
 pub mod shell;
+pub mod spi;
 pub mod traps;
 pub mod vm;
+pub mod watchdog;
 pub mod rng;
 
-use allocator::Allocator;
+use allocator::{Allocator, GlobalFrameAllocator};
 use fs::FileSystem;
+use gpio::GpioIrq;
+use i2c::I2cDevice;
 use net::uspi::Usb;
-use net::GlobalEthernetDriver;
+use net::{GlobalEthernetDriver, NetDevice};
 use process::GlobalScheduler;
+use shell::remote::RemoteShell;
+use spi::SpiDevice;
 use traps::irq::{Fiq, GlobalIrq};
 use vm::VMManager;
 use console::kprintln;
@@ -44,6 +52,7 @@ use aarch64::affinity;
 
 use core::time::Duration;
 
+pub static FRAMES: GlobalFrameAllocator = GlobalFrameAllocator::uninitialized();
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 pub static FILESYSTEM: FileSystem = FileSystem::uninitialized();
@@ -52,7 +61,11 @@ pub static VMM: VMManager = VMManager::uninitialized();
 pub static USB: Usb = Usb::uninitialized();
 pub static GLOABAL_IRQ: GlobalIrq = GlobalIrq::new();
 pub static FIQ: Fiq = Fiq::new();
+pub static GPIO_IRQ: GpioIrq = GpioIrq::uninitialized();
+pub static I2C: I2cDevice = I2cDevice::uninitialized();
+pub static SPI: SpiDevice = SpiDevice::uninitialized();
 pub static ETHERNET: GlobalEthernetDriver = GlobalEthernetDriver::uninitialized();
+pub static RSHELL: RemoteShell = RemoteShell::uninitialized();
 
 extern "C" {
     static __text_beg: u64;
@@ -72,22 +85,27 @@ unsafe fn kmain() -> ! {
         "bss  beg: {:016x}, end: {:016x}",
         &__bss_beg as *const _ as u64, &__bss_end as *const _ as u64
     );
+    FRAMES.initialize();
     ALLOCATOR.initialize();
     FILESYSTEM.initialize();
     VMM.initialize();
     SCHEDULER.initialize();
     aarch64::enable_fiq_interrupt();
-    info!("USB init");
-    USB.initialize();
+    let net_device = NetDevice::probe();
     info!("ETHERNET init");
-    ETHERNET.initialize();
-    assert!(USB.is_eth_available());
-    while !USB.is_eth_link_up() {
-        // spin
-    }
+    ETHERNET.initialize(net_device);
+    RSHELL.initialize();
+    console::initialize_interrupts();
+    GPIO_IRQ.initialize();
     aarch64::disable_fiq_interrupt();
     init::initialize_app_cores();
 
     VMM.wait();
+    // Mailbox calls take a lock made of exclusive accesses, which need the
+    // MMU on.
+    I2C.initialize();
+    SPI.initialize();
+    console::initialize_framebuffer();
+    watchdog::WATCHDOG.start();
     SCHEDULER.start()
 }
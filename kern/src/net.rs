@@ -1,8 +1,14 @@
 ///! Network device that wraps USPi in smoltcp abstraction
+pub mod icmp;
+pub mod loopback;
+pub mod pcap;
 pub mod uspi;
 
+#[cfg(test)]
+mod tests;
+
 use alloc::boxed::Box;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::convert::TryInto;
@@ -11,22 +17,26 @@ use core::time::Duration;
 
 use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
 use smoltcp::phy::{self, Device, DeviceCapabilities};
-use smoltcp::socket::{SocketHandle, SocketRef, TcpSocketBuffer};
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketHandle, SocketRef, TcpSocketBuffer, TcpState};
 use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpProtocol, IpVersion};
 
 use crate::mutex::Mutex;
 use crate::param::{MTU, IP_ADDR, SUBNET_MASK};
 use crate::USB;
 
+use self::loopback::{Loopback, LOOPBACK_ETH_ADDR};
+use self::pcap::CAPTURE;
+
 // We always use owned buffer as internal storage
 pub type SocketSet = smoltcp::socket::SocketSet<'static, 'static, 'static>;
 pub type TcpSocket = smoltcp::socket::TcpSocket<'static>;
+pub type RawSocket = smoltcp::socket::RawSocket<'static, 'static>;
 pub type EthernetInterface<T> = smoltcp::iface::EthernetInterface<'static, 'static, 'static, T>;
 
 /// 8-byte aligned `u8` slice.
 #[repr(align(8))]
-struct FrameBuf([u8; MTU as usize]);
+pub struct FrameBuf([u8; MTU as usize]);
 
 /// A fixed size buffer with length tracking functionality.
 pub struct Frame {
@@ -82,7 +92,7 @@ pub struct UsbEthernet;
 
 impl<'a> Device<'a> for UsbEthernet {
     type RxToken = RxToken;
-    type TxToken = TxToken;
+    type TxToken = TxToken<'a>;
 
     fn capabilities(&self) -> DeviceCapabilities {
         let mut capability = DeviceCapabilities::default();
@@ -95,7 +105,7 @@ impl<'a> Device<'a> for UsbEthernet {
         match USB.recv_frame(&mut frame) {
             Some(_) => {
                 let rx = RxToken { frame };
-                let tx = TxToken;
+                let tx = TxToken::Usb;
                 Some((rx, tx))
             }
             _ => None,
@@ -103,7 +113,83 @@ impl<'a> Device<'a> for UsbEthernet {
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(TxToken)
+        Some(TxToken::Usb)
+    }
+}
+
+/// The network device backing the kernel's ethernet interface, selected at
+/// boot by `NetDevice::probe()`.
+#[derive(Debug)]
+pub enum NetDevice {
+    /// The RPi's USB ethernet adapter driven by USPi.
+    Usb(UsbEthernet),
+    /// An in-memory device that receives every frame it transmits.
+    Loopback(Loopback),
+}
+
+impl NetDevice {
+    /// Picks the network device to boot with.
+    ///
+    /// Setting `NET_DEVICE=loopback` at build time always selects the loopback
+    /// device. Otherwise USB is initialized and, if an ethernet adapter is
+    /// present, this waits for its link to come up and selects it. When USPi
+    /// fails to initialize or finds no adapter, the loopback device is used so
+    /// the kernel still boots (e.g. in QEMU).
+    ///
+    /// FIQs must be enabled while calling this since USPi relies on them.
+    pub fn probe() -> NetDevice {
+        if option_env!("NET_DEVICE") == Some("loopback") {
+            info!("NetDevice: loopback selected by NET_DEVICE");
+            return NetDevice::Loopback(Loopback::new());
+        }
+
+        info!("USB init");
+        if !USB.initialize() {
+            warn!("NetDevice: USPi initialization failed, falling back to loopback");
+            return NetDevice::Loopback(Loopback::new());
+        }
+        if !USB.is_eth_available() {
+            warn!("NetDevice: no USB ethernet adapter, falling back to loopback");
+            return NetDevice::Loopback(Loopback::new());
+        }
+        while !USB.is_eth_link_up() {
+            // spin
+        }
+        NetDevice::Usb(UsbEthernet)
+    }
+
+    /// Returns the MAC address of the device.
+    pub fn ethernet_addr(&self) -> EthernetAddress {
+        match self {
+            NetDevice::Usb(_) => USB.get_eth_addr(),
+            NetDevice::Loopback(_) => LOOPBACK_ETH_ADDR,
+        }
+    }
+}
+
+impl<'a> Device<'a> for NetDevice {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self {
+            NetDevice::Usb(device) => device.capabilities(),
+            NetDevice::Loopback(device) => device.capabilities(),
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        match self {
+            NetDevice::Usb(device) => device.receive(),
+            NetDevice::Loopback(device) => device.receive(),
+        }
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        match self {
+            NetDevice::Usb(device) => device.transmit(),
+            NetDevice::Loopback(device) => device.transmit(),
+        }
     }
 }
 
@@ -116,13 +202,20 @@ impl phy::RxToken for RxToken {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
+        CAPTURE.record(self.frame.as_slice());
         f(self.frame.as_mut_slice())
     }
 }
 
-pub struct TxToken;
+/// Transmit token of every `NetDevice`, sending the frame to where it belongs.
+pub enum TxToken<'a> {
+    /// Sends the frame with USPi.
+    Usb,
+    /// Queues the frame on a loopback device's receive queue.
+    Loopback(&'a mut VecDeque<Frame>),
+}
 
-impl phy::TxToken for TxToken {
+impl<'a> phy::TxToken for TxToken<'a> {
     fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
@@ -130,15 +223,20 @@ impl phy::TxToken for TxToken {
         let mut frame = Frame::new();
         frame.set_len(len.try_into().unwrap());
         let result = f(frame.as_mut_slice());
-        USB.send_frame(&frame);
+        CAPTURE.record(frame.as_slice());
+        match self {
+            TxToken::Usb => {
+                USB.send_frame(&frame);
+            }
+            TxToken::Loopback(queue) => loopback::enqueue(queue, frame),
+        }
         result
     }
 }
 
-/// Creates and returns a new ethernet interface using `UsbEthernet` struct.
-pub fn create_interface() -> EthernetInterface<UsbEthernet> {
-    let device = UsbEthernet;
-    let hw_addr = USB.get_eth_addr();
+/// Creates and returns a new ethernet interface on top of `device`.
+pub fn create_interface(device: NetDevice) -> EthernetInterface<NetDevice> {
+    let hw_addr = device.ethernet_addr();
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
     let private_cidr = IpCidr::new(
         IpAddress::v4(
@@ -157,45 +255,103 @@ pub fn create_interface() -> EthernetInterface<UsbEthernet> {
         .finalize()
 }
 
+/// Returns `true` if receiving on `socket` has to wait: no data is buffered
+/// yet but the socket is listening, connecting or connected with the receive
+/// half still open. A closed socket never blocks so the caller sees the error.
+pub fn recv_would_block(socket: &TcpSocket) -> bool {
+    !socket.can_recv() && match socket.state() {
+        TcpState::Listen
+        | TcpState::SynSent
+        | TcpState::SynReceived
+        | TcpState::Established
+        | TcpState::FinWait1
+        | TcpState::FinWait2 => true,
+        _ => false,
+    }
+}
+
+/// Returns `true` if sending on `socket` has to wait: the transmit buffer is
+/// full or the connection is not established yet.
+pub fn send_would_block(socket: &TcpSocket) -> bool {
+    !socket.can_send() && match socket.state() {
+        TcpState::Listen
+        | TcpState::SynSent
+        | TcpState::SynReceived
+        | TcpState::Established
+        | TcpState::CloseWait => true,
+        _ => false,
+    }
+}
+
 const PORT_MAP_SIZE: usize = 65536 / 64;
 
+/// Maximum number of interface polls per `EthernetDriver::poll()` call while
+/// looped back frames are pending.
+const LOOPBACK_POLL_LIMIT: usize = 8;
+
 pub struct EthernetDriver {
     /// A set of sockets
     socket_set: SocketSet,
     /// Bitmap to track the port usage
     port_map: [u64; PORT_MAP_SIZE],
     /// Internal ethernet interface
-    ethernet: EthernetInterface<UsbEthernet>,
+    ethernet: EthernetInterface<NetDevice>,
 }
 
 impl EthernetDriver {
-    /// Creates a fresh ethernet driver.
-    fn new() -> EthernetDriver {
+    /// Creates a fresh ethernet driver on top of `device`.
+    fn new(device: NetDevice) -> EthernetDriver {
         EthernetDriver {
             socket_set: SocketSet::new(Vec::new()),
             port_map: [0; PORT_MAP_SIZE],
-            ethernet: create_interface(),
+            ethernet: create_interface(device),
+        }
+    }
+
+    /// Returns `true` if the interface runs on the loopback device.
+    pub fn is_loopback(&self) -> bool {
+        match self.ethernet.device() {
+            NetDevice::Loopback(_) => true,
+            NetDevice::Usb(_) => false,
+        }
+    }
+
+    /// Returns the number of looped back frames that have not been received
+    /// by the interface yet. Always 0 on real hardware.
+    fn pending_frames(&self) -> usize {
+        match self.ethernet.device() {
+            NetDevice::Loopback(device) => device.pending(),
+            NetDevice::Usb(_) => 0,
         }
     }
 
     /// Polls the ethernet interface.
     /// See also `smoltcp::iface::EthernetInterface::poll()`.
+    ///
+    /// On the loopback device the frames sent while polling are received again
+    /// right away, up to `LOOPBACK_POLL_LIMIT` rounds, so a request and its
+    /// reply are handled by a single call.
     fn poll(&mut self, timestamp: Instant) {
         trace!("EthernetDriver::poll() timestamp: {:?}", timestamp);
-        match self.ethernet.poll(&mut self.socket_set, timestamp) {
-            Ok(packets_processed) => {
-                if packets_processed {
-                    trace!("EthernetDriver::poll() packets processed");
-                } else {
-                    trace!("EthernetDriver::poll() no packets processed");
+        for _ in 0..LOOPBACK_POLL_LIMIT {
+            match self.ethernet.poll(&mut self.socket_set, timestamp) {
+                Ok(packets_processed) => {
+                    if packets_processed {
+                        trace!("EthernetDriver::poll() packets processed");
+                    } else {
+                        trace!("EthernetDriver::poll() no packets processed");
+                    }
                 }
-            }
-            Err(e) => {
-                match e {
-                    smoltcp::Error::Unrecognized => (),
-                    e => debug!("EthernetDriver::poll() error: {:?}", e),
+                Err(e) => {
+                    match e {
+                        smoltcp::Error::Unrecognized => (),
+                        e => debug!("EthernetDriver::poll() error: {:?}", e),
+                    }
                 }
             }
+            if self.pending_frames() == 0 {
+                break;
+            }
         }
     }
 
@@ -271,6 +427,21 @@ impl EthernetDriver {
         self.socket_set.add(tcp_socket)
     }
 
+    /// Finds a raw socket with a `SocketHandle`.
+    pub fn get_raw_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, RawSocket> {
+        self.socket_set.get::<RawSocket>(handle)
+    }
+
+    /// This function creates a new raw IPv4 socket bound to the ICMP protocol,
+    /// adds it to the internal socket set, and returns the `SocketHandle` of
+    /// the new socket.
+    pub fn add_icmp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 8], vec![0; 4096]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 8], vec![0; 4096]);
+        let raw_socket = RawSocket::new(IpVersion::Ipv4, IpProtocol::Icmp, rx_buffer, tx_buffer);
+        self.socket_set.add(raw_socket)
+    }
+
     /// Releases a socket from the internal socket set.
     pub fn release(&mut self, handle: SocketHandle) {
         self.socket_set.release(handle);
@@ -290,9 +461,19 @@ impl GlobalEthernetDriver {
         GlobalEthernetDriver(Mutex::new(None))
     }
 
-    pub fn initialize(&self) {
+    /// Initializes the ethernet driver on top of `device`.
+    pub fn initialize(&self, device: NetDevice) {
         let mut lock = self.0.lock();
-        *lock = Some(EthernetDriver::new());
+        *lock = Some(EthernetDriver::new(device));
+    }
+
+    /// Returns `true` if the ethernet driver runs on the loopback device.
+    pub fn is_loopback(&self) -> bool {
+        self.0
+            .lock()
+            .as_ref()
+            .expect("Uninitialized EthernetDriver")
+            .is_loopback()
     }
 
     pub fn poll(&self, timestamp: Instant) {
@@ -339,6 +520,14 @@ impl GlobalEthernetDriver {
             .add_socket()
     }
 
+    pub fn add_icmp_socket(&self) -> SocketHandle {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .add_icmp_socket()
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the socket.
     pub fn with_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
@@ -354,6 +543,21 @@ impl GlobalEthernetDriver {
         f(&mut socket)
     }
 
+    /// Enters a critical region and execute the provided closure with a mutable
+    /// reference to the raw socket.
+    pub fn with_raw_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
+    where
+        F: FnOnce(&mut SocketRef<'_, RawSocket>) -> R,
+    {
+        let mut guard = self.0.lock();
+        let mut socket = guard
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .get_raw_socket(handle);
+
+        f(&mut socket)
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the inner ethernet driver.
     pub fn critical<F, R>(&self, f: F) -> R
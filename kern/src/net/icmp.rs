@@ -0,0 +1,106 @@
+///! ICMP echo request/reply support on top of a smoltcp raw IPv4 socket
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{Icmpv4Packet, Icmpv4Repr, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr};
+
+use crate::net::RawSocket;
+use crate::param::IP_ADDR;
+
+/// Hop limit set on outgoing echo requests.
+pub const ECHO_HOP_LIMIT: u8 = 64;
+
+/// Payload carried by every echo request sent by the kernel.
+pub const ECHO_PAYLOAD: &[u8] = b"RustOS ping payload 0123456789ab";
+
+/// An echo reply received on a raw ICMP socket.
+#[derive(Debug, Copy, Clone)]
+pub struct EchoReply {
+    /// The address the reply came from.
+    pub src_addr: Ipv4Address,
+    /// Sequence number echoed back by the remote host.
+    pub seq_no: u16,
+    /// Time-to-live field of the IPv4 header carrying the reply.
+    pub ttl: u8,
+    /// Length of the echoed payload in bytes.
+    pub len: usize,
+}
+
+/// Returns the IPv4 address of this board.
+pub fn local_addr() -> Ipv4Address {
+    Ipv4Address::from_bytes(&IP_ADDR)
+}
+
+/// Queues an ICMP echo request for `dst_addr` on `socket`.
+///
+/// The IPv4 header is built here since raw sockets hand the whole packet to
+/// the interface as-is. The interface fills in the header checksum.
+///
+/// # Errors
+///
+/// Returns `smoltcp::Error::Exhausted` if the socket's transmit buffer is full.
+pub fn send_echo_request(
+    socket: &mut RawSocket,
+    dst_addr: Ipv4Address,
+    ident: u16,
+    seq_no: u16,
+) -> smoltcp::Result<()> {
+    let checksum = ChecksumCapabilities::default();
+    let icmp_repr = Icmpv4Repr::EchoRequest {
+        ident,
+        seq_no,
+        data: ECHO_PAYLOAD,
+    };
+    let ipv4_repr = Ipv4Repr {
+        src_addr: local_addr(),
+        dst_addr,
+        protocol: IpProtocol::Icmp,
+        payload_len: icmp_repr.buffer_len(),
+        hop_limit: ECHO_HOP_LIMIT,
+    };
+
+    let buf = socket.send(ipv4_repr.buffer_len() + icmp_repr.buffer_len())?;
+    let mut ipv4_packet = Ipv4Packet::new_unchecked(buf);
+    ipv4_repr.emit(&mut ipv4_packet, &checksum);
+    let mut icmp_packet = Icmpv4Packet::new_unchecked(ipv4_packet.payload_mut());
+    icmp_repr.emit(&mut icmp_packet, &checksum);
+    trace!("icmp::send_echo_request() {} ident: {} seq: {}", dst_addr, ident, seq_no);
+    Ok(())
+}
+
+/// Drains `socket` looking for an echo reply matching `ident` and `seq_no`.
+///
+/// Packets that are not the reply we are waiting for (other ICMP traffic,
+/// replies to another process, late replies) are discarded.
+///
+/// Returns `Some(EchoReply)` if a matching reply was received and `None`
+/// otherwise.
+pub fn recv_echo_reply(socket: &mut RawSocket, ident: u16, seq_no: u16) -> Option<EchoReply> {
+    let checksum = ChecksumCapabilities::default();
+    while socket.can_recv() {
+        let buf = match socket.recv() {
+            Ok(buf) => buf,
+            Err(_) => return None,
+        };
+        let ipv4_packet = match Ipv4Packet::new_checked(buf) {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+        let icmp_packet = match Icmpv4Packet::new_checked(ipv4_packet.payload()) {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+        match Icmpv4Repr::parse(&icmp_packet, &checksum) {
+            Ok(Icmpv4Repr::EchoReply { ident: reply_ident, seq_no: reply_seq_no, data })
+                if reply_ident == ident && reply_seq_no == seq_no =>
+            {
+                return Some(EchoReply {
+                    src_addr: ipv4_packet.src_addr(),
+                    seq_no: reply_seq_no,
+                    ttl: ipv4_packet.hop_limit(),
+                    len: data.len(),
+                });
+            }
+            _ => trace!("icmp::recv_echo_reply() dropped unrelated ICMP packet"),
+        }
+    }
+    None
+}
@@ -0,0 +1,71 @@
+///! In-memory network device that hands every transmitted frame back to the
+///! receive path
+use alloc::collections::VecDeque;
+
+use smoltcp::phy::{Device, DeviceCapabilities};
+use smoltcp::wire::EthernetAddress;
+
+use crate::net::{Frame, RxToken, TxToken};
+use crate::param::MTU;
+
+/// Locally administered MAC address reported by the loopback device.
+pub const LOOPBACK_ETH_ADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+/// Maximum number of frames queued on the device. Frames transmitted while
+/// the queue is full are dropped, like a real NIC with a full ring would do.
+const QUEUE_LEN: usize = 64;
+
+/// A network device that loops transmitted frames back to the receive side.
+///
+/// Every frame the interface sends is received by the same interface on the
+/// next poll, so sockets bound to the board's own addresses can talk to each
+/// other without any hardware.
+#[derive(Debug)]
+pub struct Loopback {
+    queue: VecDeque<Frame>,
+}
+
+impl Loopback {
+    /// Creates a loopback device with an empty frame queue.
+    pub fn new() -> Loopback {
+        Loopback {
+            queue: VecDeque::with_capacity(QUEUE_LEN),
+        }
+    }
+
+    /// Returns the number of frames waiting to be received.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl<'a> Device<'a> for Loopback {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capability = DeviceCapabilities::default();
+        capability.max_transmission_unit = MTU as usize;
+        capability
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = self.queue.pop_front()?;
+        let rx = RxToken { frame };
+        let tx = TxToken::Loopback(&mut self.queue);
+        Some((rx, tx))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken::Loopback(&mut self.queue))
+    }
+}
+
+/// Queues `frame` on the loopback device, dropping it if the queue is full.
+pub(super) fn enqueue(queue: &mut VecDeque<Frame>, frame: Frame) {
+    if queue.len() < QUEUE_LEN {
+        queue.push_back(frame);
+    } else {
+        debug!("Loopback: queue full, dropping {:?}", frame);
+    }
+}
@@ -0,0 +1,263 @@
+///! Capture of the frames passing through the network device in pcap format
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use shim::io;
+
+use crate::mutex::Mutex;
+use crate::param::MTU;
+
+/// Default capacity of the capture buffer in bytes of pcap records.
+pub const DEFAULT_CAPTURE_SIZE: usize = 256 * 1024;
+
+/// Magic number of a little-endian pcap file with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+/// `LINKTYPE_ETHERNET`
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+/// Size of the pcap global header in bytes.
+const PCAP_HEADER_LEN: usize = 24;
+/// Size of a pcap record header in bytes.
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// A ring buffer of captured frames, stored as pcap records. The buffer is
+/// allocated up front so that recording a frame never allocates. When a frame
+/// does not fit, the oldest records are overwritten.
+#[derive(Debug)]
+pub struct Capture {
+    ring: Box<[u8]>,
+    /// Offset of the oldest record in `ring`.
+    head: usize,
+    /// Bytes of records currently stored in `ring`.
+    size: usize,
+    /// Number of records in `ring`.
+    frames: usize,
+    /// Number of frames evicted or not recorded since the last `clear()`.
+    dropped: usize,
+}
+
+impl Capture {
+    fn new(capacity: usize) -> Capture {
+        let mut ring = Vec::with_capacity(capacity);
+        ring.resize(capacity, 0);
+        Capture {
+            ring: ring.into_boxed_slice(),
+            head: 0,
+            size: 0,
+            frames: 0,
+            dropped: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.ring.len()
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.size = 0;
+        self.frames = 0;
+        self.dropped = 0;
+    }
+
+    /// Copies `data` into the ring at `offset`, wrapping around its end.
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        let offset = offset % self.capacity();
+        let first = data.len().min(self.capacity() - offset);
+        self.ring[offset..offset + first].copy_from_slice(&data[..first]);
+        self.ring[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    /// Copies the bytes of the ring at `offset` into `buf`, wrapping around
+    /// its end.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        let offset = offset % self.capacity();
+        let first = buf.len().min(self.capacity() - offset);
+        buf[..first].copy_from_slice(&self.ring[offset..offset + first]);
+        let rest = buf.len() - first;
+        buf[first..].copy_from_slice(&self.ring[..rest]);
+    }
+
+    /// Drops the oldest record.
+    fn evict(&mut self) {
+        // The captured length is the third word of the record header.
+        let mut len = [0; 4];
+        self.read_at(self.head + 8, &mut len);
+        let record_len = PCAP_RECORD_HEADER_LEN + u32::from_le_bytes(len) as usize;
+        self.head = (self.head + record_len) % self.capacity();
+        self.size -= record_len;
+        self.frames -= 1;
+        self.dropped += 1;
+    }
+
+    fn push(&mut self, timestamp: Duration, frame: &[u8]) {
+        let len = frame.len().min(MTU as usize);
+        let record_len = PCAP_RECORD_HEADER_LEN + len;
+        if record_len > self.capacity() {
+            self.dropped += 1;
+            return;
+        }
+        while self.size + record_len > self.capacity() {
+            self.evict();
+        }
+
+        let mut header = [0; PCAP_RECORD_HEADER_LEN];
+        header[0..4].copy_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        header[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(len as u32).to_le_bytes());
+        let tail = self.head + self.size;
+        self.write_at(tail, &header);
+        self.write_at(tail + PCAP_RECORD_HEADER_LEN, &frame[..len]);
+        self.size += record_len;
+        self.frames += 1;
+    }
+
+    /// Returns the stored records in order, as the part up to the end of the
+    /// ring and the part wrapped around to its start.
+    fn records(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.size;
+        if end <= self.capacity() {
+            (&self.ring[self.head..end], &[])
+        } else {
+            (&self.ring[self.head..], &self.ring[..end - self.capacity()])
+        }
+    }
+
+    /// Returns the length in bytes of the pcap file `write_pcap()` produces.
+    pub fn pcap_len(&self) -> usize {
+        PCAP_HEADER_LEN + self.size
+    }
+
+    /// Writes the captured frames to `w` as a pcap file.
+    pub fn write_pcap<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        w.write_all(&PCAP_VERSION_MAJOR.to_le_bytes())?;
+        w.write_all(&PCAP_VERSION_MINOR.to_le_bytes())?;
+        // thiszone and sigfigs
+        w.write_all(&0i32.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&MTU.to_le_bytes())?;
+        w.write_all(&PCAP_LINKTYPE_ETHERNET.to_le_bytes())?;
+
+        let (first, second) = self.records();
+        w.write_all(first)?;
+        w.write_all(second)?;
+        Ok(())
+    }
+}
+
+/// Summary of the capture state, as shown by the `pcap` shell command.
+#[derive(Debug, Copy, Clone)]
+pub struct CaptureStats {
+    pub enabled: bool,
+    pub frames: usize,
+    pub size: usize,
+    pub capacity: usize,
+    pub dropped: usize,
+}
+
+/// A thread-safe, optional frame capture. Nothing is recorded until `start()`
+/// is called.
+pub struct GlobalCapture {
+    enabled: AtomicBool,
+    capture: Mutex<Option<Capture>>,
+    /// Number of frames not recorded because the capture was locked.
+    missed: AtomicUsize,
+}
+
+impl GlobalCapture {
+    pub const fn new() -> GlobalCapture {
+        GlobalCapture {
+            enabled: AtomicBool::new(false),
+            capture: Mutex::new(None),
+            missed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts capturing. A new buffer of `capacity` bytes replaces the current
+    /// one if the capacity differs, otherwise frames are appended.
+    pub fn start(&self, capacity: usize) {
+        let replace = match *self.capture.lock() {
+            Some(ref inner) => inner.capacity() != capacity,
+            None => true,
+        };
+        if replace {
+            // Allocated and freed outside of the lock `record()` takes.
+            let capture = Capture::new(capacity);
+            let old = mem::replace(&mut *self.capture.lock(), Some(capture));
+            drop(old);
+            self.missed.store(0, Ordering::SeqCst);
+        }
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops capturing. Captured frames are kept until `clear()`.
+    pub fn stop(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+    }
+
+    /// Drops all captured frames.
+    pub fn clear(&self) {
+        if let Some(capture) = self.capture.lock().as_mut() {
+            capture.clear();
+        }
+        self.missed.store(0, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Records `frame` if capturing is enabled.
+    ///
+    /// This is called while the network is polled from the timer interrupt,
+    /// so it neither allocates nor waits for the lock: the frame is dropped if
+    /// the shell is reading the capture meanwhile.
+    pub fn record(&self, frame: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let timestamp = pi::timer::current_time();
+        match self.capture.try_lock() {
+            Some(mut capture) => {
+                if let Some(capture) = capture.as_mut() {
+                    capture.push(timestamp, frame);
+                }
+            }
+            None => {
+                self.missed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        let capture = self.capture.lock();
+        let missed = self.missed.load(Ordering::Relaxed);
+        CaptureStats {
+            enabled: self.is_enabled(),
+            frames: capture.as_ref().map(|c| c.frames).unwrap_or(0),
+            size: capture.as_ref().map(|c| c.size).unwrap_or(0),
+            capacity: capture.as_ref().map(|c| c.capacity()).unwrap_or(0),
+            dropped: capture.as_ref().map(|c| c.dropped).unwrap_or(0) + missed,
+        }
+    }
+
+    /// Returns the captured frames as a pcap file.
+    pub fn to_pcap(&self) -> Vec<u8> {
+        let capture = self.capture.lock();
+        let empty = Capture::new(0);
+        let capture = capture.as_ref().unwrap_or(&empty);
+        let mut pcap = Vec::with_capacity(capture.pcap_len());
+        capture.write_pcap(&mut pcap).expect("failed to write to vector");
+        pcap
+    }
+}
+
+/// Global frame capture fed by the network device tokens.
+pub static CAPTURE: GlobalCapture = GlobalCapture::new();
@@ -0,0 +1,62 @@
+mod loopback {
+    use smoltcp::phy::{self, Device};
+    use smoltcp::socket::TcpState;
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{IpAddress, IpEndpoint};
+
+    use crate::net::loopback::Loopback;
+    use crate::net::{EthernetDriver, NetDevice};
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut device = Loopback::new();
+        assert!(device.receive().is_none());
+
+        let tx = device.transmit().expect("loopback transmit");
+        phy::TxToken::consume(tx, Instant::from_millis(0), 4, |buf| {
+            buf.copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(device.pending(), 1);
+
+        let (rx, _tx) = device.receive().expect("looped back frame");
+        phy::RxToken::consume(rx, Instant::from_millis(0), |buf| {
+            assert_eq!(buf, &[0xde, 0xad, 0xbe, 0xef]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(device.pending(), 0);
+        assert!(device.receive().is_none());
+    }
+
+    #[test]
+    fn test_tcp_connect_and_transfer() {
+        let mut driver = EthernetDriver::new(NetDevice::Loopback(Loopback::new()));
+        assert!(driver.is_loopback());
+
+        let server = driver.add_socket();
+        let client = driver.add_socket();
+        let local = IpAddress::v4(127, 0, 0, 1);
+        driver.get_socket(server).listen(80).unwrap();
+        driver
+            .get_socket(client)
+            .connect(IpEndpoint::new(local, 80), IpEndpoint::new(local, 49152))
+            .unwrap();
+
+        let mut now = 0;
+        while driver.get_socket(client).state() != TcpState::Established && now < 100 {
+            driver.poll(Instant::from_millis(now));
+            now += 1;
+        }
+        assert_eq!(driver.get_socket(client).state(), TcpState::Established);
+        assert_eq!(driver.get_socket(server).state(), TcpState::Established);
+
+        assert_eq!(driver.get_socket(client).send_slice(b"hello").unwrap(), 5);
+        driver.poll(Instant::from_millis(now));
+
+        let mut buf = [0u8; 16];
+        let len = driver.get_socket(server).recv_slice(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+    }
+}
@@ -74,9 +74,13 @@ mod inner {
     impl USPi {
         /// The caller should assure that this function is called only once
         /// during the lifetime of the kernel.
-        pub unsafe fn initialize() -> Self {
-            assert!(USPiInitialize() != 0);
-            USPi(())
+        ///
+        /// Returns `None` if USPi failed to initialize the USB host controller.
+        pub unsafe fn initialize() -> Option<Self> {
+            match USPiInitialize() {
+                0 => None,
+                _ => Some(USPi(())),
+            }
         }
 
         /// Returns whether ethernet is available on RPi
@@ -266,11 +270,14 @@ impl Usb {
         Usb(Mutex::new(None))
     }
 
-    pub fn initialize(&self) {
+    /// Initializes USPi if it is not initialized yet. Returns `true` if USPi
+    /// is ready to use.
+    pub fn initialize(&self) -> bool {
         let mut inner = self.0.lock();
         if let None = *inner {
-            *inner = Some(unsafe { USPi::initialize() });
+            *inner = unsafe { USPi::initialize() };
         }
+        inner.is_some()
     }
 
     pub fn is_eth_available(&self) -> bool {
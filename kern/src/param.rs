@@ -10,24 +10,43 @@ pub const PAGE_ALIGN: usize = 16;
 pub const PAGE_SIZE: usize = 64 * 1024;
 pub const PAGE_MASK: usize = !(PAGE_SIZE - 1);
 
-pub const USER_MASK_BITS: usize = 34;
+/// Shared with user programs through `kernel_api`.
+pub const USER_MASK_BITS: usize = kernel_api::USER_MASK_BITS;
 pub const KERNEL_MASK_BITS: usize = 31;
 
+/// Lowest address of the user address space, which TTBR1 translates.
+pub const USER_VA_BASE: usize = ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS);
+/// Size of the user address space (256 TiB).
+pub const USER_MAX_VM_SIZE: usize = 1 << (64 - USER_MASK_BITS);
+const_assert_eq!(USER_VA_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+/// Address user programs are linked and loaded at, in the last GiB of the
+/// user address space.
 pub const USER_IMG_BASE: usize = 0xffff_ffff_c000_0000;
-const_assert_eq!(
-    USER_IMG_BASE,
-    ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS)
-);
+/// Range `mmap` places mappings in: the user address space below the image.
+pub const USER_MMAP_BASE: usize = USER_VA_BASE;
+pub const USER_MMAP_END: usize = USER_IMG_BASE;
 pub const USER_STACK_PAGE_COUNT: usize = 4;
 pub const USER_STACK_SIZE: usize = PAGE_SIZE * USER_STACK_PAGE_COUNT;
 pub const USER_STACK_MASK: usize = !(USER_STACK_SIZE - 1);
 pub const USER_STACK_BASE: usize = core::usize::MAX & USER_STACK_MASK; //0xffff_ffff_fffc_0000
-pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
-const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+/// Descriptor of the first file a process opens. The console and sockets
+/// take the descriptors below it.
+pub const FILE_FD_BASE: usize = 1024;
 
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
 pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
+/// Size of the unmapped guard page below each core's kernel stack, so an
+/// overflow faults instead of running into the next core's stack.
+pub const KERN_STACK_GUARD_SIZE: usize = PAGE_SIZE;
+/// Distance between the tops of the kernel stacks of two cores.
+pub const KERN_STACK_STRIDE: usize = KERN_STACK_SIZE + KERN_STACK_GUARD_SIZE;
+// The stacks and their guard pages fill the memory below the kernel, which
+// `vectors.s` relies on to tell a guard page from a stack.
+const_assert_eq!(KERN_STACK_STRIDE * NCORES, KERN_STACK_BASE);
+/// Size of the kernel heap carved out of the physical page frames. The rest
+/// of the memory is left to the frame allocator, e.g. for user pages.
+pub const KERN_HEAP_SIZE: usize = 128 * 1024 * 1024;
 
 /// The `tick` time.
 // FIXME: When you're ready, change this to something more reasonable.
@@ -44,3 +63,27 @@ pub const IP_ADDR: [u8; 4] = [192, 168, 254, 11];
 pub const SUBNET_MASK: u8 = 24;
 // pub const IP_ADDR: [u8; 4] = [169, 254, 32, 10];
 // pub const SUBNET_MASK: u8 = 16;
+
+/// The board is reset when a busy core stops petting the watchdog, on its
+/// scheduler ticks and in long waits, for about this long (at most
+/// `pi::pm::MAX_TIMEOUT`). `None` disables the kernel watchdog.
+pub const WATCHDOG_TIMEOUT: Option<Duration> = Some(Duration::from_secs(10));
+/// The board is reset this long after a kernel panic (at most
+/// `pi::pm::MAX_TIMEOUT`). With `None` the panic message stays up until the
+/// board is reset by hand.
+pub const PANIC_REBOOT_DELAY: Option<Duration> = None;
+
+/// Requested width of the framebuffer the console is mirrored to, in pixels.
+pub const FB_WIDTH: u32 = 1024;
+/// Requested height of the framebuffer the console is mirrored to, in pixels.
+pub const FB_HEIGHT: u32 = 768;
+
+/// Frequency of the kernel's I2C bus in Hz.
+pub const I2C_CLOCK: u32 = 100_000;
+
+/// TCP port the remote shell listens on.
+pub const RSHELL_PORT: u16 = 23;
+/// Maximum number of concurrent remote shell sessions.
+pub const RSHELL_SESSIONS: usize = 2;
+/// Size of the stack of the kernel process running remote shell commands.
+pub const RSHELL_STACK_SIZE: usize = 64 * 1024;
@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
 
 use crate::param::NCORES;
 use crate::traps::irq::LocalIrq;
@@ -12,6 +12,9 @@ pub struct PerCore {
     mmu_ready: AtomicBool,
     /// Local IRQ handler registry
     irq: LocalIrq,
+    /// Bottom and top of the stack of the kernel process running on this
+    /// core, or 0 if the process is a user one.
+    process_stack: [AtomicUsize; 2],
 }
 
 static PER_CORE_DATA: [PerCore; NCORES] = [
@@ -19,21 +22,25 @@ static PER_CORE_DATA: [PerCore; NCORES] = [
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        process_stack: [AtomicUsize::new(0), AtomicUsize::new(0)],
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        process_stack: [AtomicUsize::new(0), AtomicUsize::new(0)],
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        process_stack: [AtomicUsize::new(0), AtomicUsize::new(0)],
     },
     PerCore {
         preemption: AtomicI64::new(0),
         mmu_ready: AtomicBool::new(false),
         irq: LocalIrq::new(),
+        process_stack: [AtomicUsize::new(0), AtomicUsize::new(0)],
     },
 ];
 
@@ -74,6 +81,22 @@ pub unsafe fn set_mmu_ready() {
     PER_CORE_DATA[cpu].mmu_ready.store(true, Ordering::Relaxed);
 }
 
+/// Records the `bottom` and `top` of the stack of the process switched to on
+/// the current core: 0 for a user process, which has none in the kernel.
+pub fn set_process_stack(bottom: usize, top: usize) {
+    let cpu = aarch64::affinity();
+    PER_CORE_DATA[cpu].process_stack[0].store(bottom, Ordering::Relaxed);
+    PER_CORE_DATA[cpu].process_stack[1].store(top, Ordering::Relaxed);
+}
+
+/// Returns the bottom and top of the stack of the kernel process running on
+/// the current core, or 0 if it is a user process.
+pub fn process_stack() -> (usize, usize) {
+    let cpu = aarch64::affinity();
+    let stack = &PER_CORE_DATA[cpu].process_stack;
+    (stack[0].load(Ordering::Relaxed), stack[1].load(Ordering::Relaxed))
+}
+
 /// Returns a reference to the local IRQ handler registry of the current core.
 pub fn local_irq() -> &'static LocalIrq {
     let cpu = aarch64::affinity();
@@ -1,8 +1,13 @@
 mod process;
 mod scheduler;
 mod state;
+mod vma;
 
 pub use self::process::{Id, Process};
 pub use self::scheduler::GlobalScheduler;
 pub use self::state::State;
+pub use self::vma::{Backing, Vma};
 pub use crate::param::TICK;
+
+#[cfg(test)]
+mod tests;
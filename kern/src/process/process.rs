@@ -1,17 +1,22 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
 use alloc::vec::Vec;
 use shim::io;
 use shim::path::Path;
+use core::fmt;
 use core::mem;
 
 use fat32::traits::FileSystem;
 use fat32::traits::Entry;
+use fat32::vfat::File;
 
 use aarch64;
 use smoltcp::socket::SocketHandle;
 
+use crate::fs::PiVFatHandle;
 use crate::param::*;
-use crate::process::State;
+use crate::process::vma::remove_range;
+use crate::process::{Backing, State, Vma};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use crate::FILESYSTEM;
@@ -21,6 +26,36 @@ use kernel_api::{OsError, OsResult};
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
+/// The stack of a kernel process, allocated on the kernel heap.
+pub struct KernelStack(Box<[u128]>);
+
+impl KernelStack {
+    fn new(size: usize) -> KernelStack {
+        let mut stack = Vec::with_capacity(size / 16);
+        stack.resize(size / 16, 0);
+        KernelStack(stack.into_boxed_slice())
+    }
+
+    /// Returns the address of the top of the stack, aligned to 16 bytes.
+    fn top(&self) -> u64 {
+        self.0.as_ptr() as u64 + (self.0.len() * 16) as u64
+    }
+
+    /// Returns the addresses of the bottom and the top of the stack.
+    pub fn bounds(&self) -> (usize, usize) {
+        (self.0.as_ptr() as usize, self.top() as usize)
+    }
+}
+
+impl fmt::Debug for KernelStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KernelStack")
+            .field("top", &format_args!("{:#x}", self.top()))
+            .field("size", &(self.0.len() * 16))
+            .finish()
+    }
+}
+
 /// A structure that represents the complete state of a process.
 #[derive(Debug)]
 pub struct Process {
@@ -31,11 +66,26 @@ pub struct Process {
     /// The scheduling state of the process.
     pub state: State,
     pub stack_base: VirtualAddr,
+    /// The lowest address of the heap; `sbrk` never shrinks it below.
+    pub heap_base: VirtualAddr,
     pub heap_ptr: VirtualAddr,
     pub heap_page: VirtualAddr,
     // Lab 5 2.C
     /// Socket handles held by the current process
     pub sockets: Vec<SocketHandle>,
+    /// Raw sockets the kernel holds for the process without a descriptor,
+    /// such as the one of a `ping` in progress.
+    pub raw_sockets: Vec<SocketHandle>,
+    /// Descriptors switched to non-blocking mode. Every descriptor starts in
+    /// blocking mode.
+    pub nonblocking: BTreeSet<usize>,
+    /// Files opened by the process. The file at index `i` has descriptor
+    /// `FILE_FD_BASE + i`; closed files leave `None` behind.
+    pub files: Vec<Option<File<PiVFatHandle>>>,
+    /// Mappings made with `mmap`, sorted by address.
+    pub vmas: Vec<Vma>,
+    /// The stack of a kernel process, `None` for user processes.
+    pub kernel_stack: Option<KernelStack>,
 }
 
 impl Process {
@@ -47,23 +97,55 @@ impl Process {
     pub fn new() -> OsResult<Process> {
         let vmap = Box::new(UserPageTable::new());
         let sockets: Vec<SocketHandle> = Vec::new();
+        let nonblocking = BTreeSet::new();
         Ok(Process {
             context: Box::new(TrapFrame::default()),
             vmap,
             state: State::Ready,
             stack_base: Process::get_stack_base(),
+            heap_base: VirtualAddr::from(0),
             heap_ptr: VirtualAddr::from(0),
             heap_page: VirtualAddr::from(0),
             sockets,
+            raw_sockets: Vec::new(),
+            nonblocking,
+            files: Vec::new(),
+            vmas: Vec::new(),
+            kernel_stack: None,
         })
     }
 
+    /// Creates a kernel process running `entry` at EL1 on a stack of
+    /// `stack_size` bytes of its own.
+    ///
+    /// The process runs with interrupts masked, like a system call, so it
+    /// never interrupts a lock holder and is never preempted while it holds a
+    /// lock itself. It only gives up its core through system calls such as
+    /// `sleep`, which it makes with `svc` as user processes do.
+    pub fn kernel(entry: extern "C" fn() -> !, stack_size: usize) -> OsResult<Process> {
+        use crate::VMM;
+
+        let mut p = Process::new()?;
+        let stack = KernelStack::new(stack_size);
+        p.context.sp = stack.top();
+        p.context.elr = entry as u64;
+        p.context.ttbr0 = VMM.get_baddr().as_u64();
+        p.context.ttbr1 = p.vmap.get_ttbr();
+        p.context.spsr = (aarch64::SPSR_EL1::M & 0b0100) // EL1t
+            | aarch64::SPSR_EL1::D
+            | aarch64::SPSR_EL1::A
+            | aarch64::SPSR_EL1::I
+            | aarch64::SPSR_EL1::F;
+        p.kernel_stack = Some(stack);
+        Ok(p)
+    }
+
     /// Loads a program stored in the given path by calling `do_load()` method.
     /// Sets trapframe `context` corresponding to its page table.
     /// `sp` - the address of stack top
     /// `elr` - the address of image base.
     /// `ttbr0` - the base address of kernel page table
-    /// `ttbr1` - the base address of user page table
+    /// `ttbr1` - the base address of user page table, tagged with its ASID
     /// `spsr` - `F`, `A`, `D` bit should be set.
     ///
     /// Returns Os Error if do_load fails.
@@ -74,7 +156,7 @@ impl Process {
         p.context.sp = Process::get_stack_top().as_u64();
         p.context.elr = Process::get_image_base().as_u64();
         p.context.ttbr0 = VMM.get_baddr().as_u64();
-        p.context.ttbr1 = p.vmap.get_baddr().as_u64();
+        p.context.ttbr1 = p.vmap.get_ttbr();
         p.context.spsr = p.context.spsr |
             aarch64::SPSR_EL1::D |
             aarch64::SPSR_EL1::A |
@@ -112,6 +194,7 @@ impl Process {
             }
         }
         let _heap_page = p.vmap.alloc(current_address, PagePerm::RW);
+        p.heap_base = current_address;
         p.heap_ptr = current_address;
         p.heap_page = current_address;
         Ok(p)
@@ -119,7 +202,7 @@ impl Process {
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
-        VirtualAddr::from(USER_IMG_BASE + (USER_MAX_VM_SIZE - 16))
+        VirtualAddr::from(USER_VA_BASE + (USER_MAX_VM_SIZE - 16))
     }
 
     /// Returns the `VirtualAddr` represents the base address of the user
@@ -140,6 +223,180 @@ impl Process {
         VirtualAddr::from(USER_STACK_BASE + (USER_STACK_SIZE - 16))
     }
 
+    /// Returns the socket handle for descriptor `fd`, if any. Descriptors 0, 1
+    /// and 2 are the console; sockets start at descriptor 3.
+    pub fn socket(&self, fd: usize) -> Option<SocketHandle> {
+        fd.checked_sub(3)
+            .and_then(|index| self.sockets.get(index))
+            .map(|handle| *handle)
+    }
+
+    /// Returns the file for descriptor `fd`, if any. Files start at
+    /// descriptor `FILE_FD_BASE`.
+    pub fn file(&self, fd: usize) -> Option<&File<PiVFatHandle>> {
+        fd.checked_sub(FILE_FD_BASE)
+            .and_then(|index| self.files.get(index))
+            .and_then(|file| file.as_ref())
+    }
+
+    /// Adds `file` to the open files and returns its descriptor, reusing the
+    /// lowest closed one.
+    pub fn add_file(&mut self, file: File<PiVFatHandle>) -> usize {
+        match self.files.iter().position(|file| file.is_none()) {
+            Some(index) => {
+                self.files[index] = Some(file);
+                FILE_FD_BASE + index
+            }
+            None => {
+                self.files.push(Some(file));
+                FILE_FD_BASE + self.files.len() - 1
+            }
+        }
+    }
+
+    /// Closes the file for descriptor `fd`. Returns `false` if there is none.
+    pub fn close_file(&mut self, fd: usize) -> bool {
+        match fd.checked_sub(FILE_FD_BASE).and_then(|index| self.files.get_mut(index)) {
+            Some(file) => file.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Adds a mapping of `len` bytes with `perm` and `backing` and returns its
+    /// address. `len` is rounded up to whole pages.
+    ///
+    /// If `fixed` is set, the mapping is placed at `addr`, replacing the
+    /// mappings there. Otherwise `addr` is a hint: the mapping is placed there
+    /// if the range is free, and at the lowest free range that fits if not.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::InvalidArgument`: `len` is 0, or `fixed` is set and `addr` is not page aligned.
+    /// - `OsError::NoVmSpace`: The range at `addr` is outside of the mmap area, or no free range fits.
+    pub fn map(
+        &mut self,
+        addr: usize,
+        len: usize,
+        perm: PagePerm,
+        backing: Backing,
+        fixed: bool,
+    ) -> OsResult<usize> {
+        let len = match len.checked_add(PAGE_SIZE - 1) {
+            Some(len) if len >= PAGE_SIZE => len & PAGE_MASK,
+            _ => return Err(OsError::InvalidArgument),
+        };
+        let fits = |start: usize| {
+            start >= USER_MMAP_BASE && start <= USER_MMAP_END && USER_MMAP_END - start >= len
+        };
+
+        let start = if fixed {
+            if addr & !PAGE_MASK != 0 {
+                return Err(OsError::InvalidArgument);
+            }
+            if !fits(addr) {
+                return Err(OsError::NoVmSpace);
+            }
+            self.unmap(addr, len)?;
+            addr
+        } else if addr & !PAGE_MASK == 0 && fits(addr)
+            && !self.vmas.iter().any(|vma| vma.overlaps(addr, addr + len))
+        {
+            addr
+        } else {
+            let mut start = USER_MMAP_BASE;
+            for vma in &self.vmas {
+                if vma.start - start >= len {
+                    break;
+                }
+                start = vma.end;
+            }
+            if !fits(start) {
+                return Err(OsError::NoVmSpace);
+            }
+            start
+        };
+
+        let index = self.vmas.iter().position(|vma| vma.start > start).unwrap_or(self.vmas.len());
+        self.vmas.insert(index, Vma { start, end: start + len, perm, backing });
+        Ok(start)
+    }
+
+    /// Removes the mappings of the `len` bytes at `addr`, rounded up to whole
+    /// pages, and releases the pages that were faulted in. The mappings that
+    /// straddle the range keep their pages outside of it.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::InvalidArgument`: `addr` is not page aligned, `len` is 0, or
+    ///   the range is outside of the mmap area.
+    pub fn unmap(&mut self, addr: usize, len: usize) -> OsResult<()> {
+        let end = match len.checked_add(PAGE_SIZE - 1).and_then(|len| addr.checked_add(len & PAGE_MASK)) {
+            Some(end) if len != 0 && addr & !PAGE_MASK == 0 => end,
+            _ => return Err(OsError::InvalidArgument),
+        };
+        if addr < USER_MMAP_BASE || end > USER_MMAP_END {
+            return Err(OsError::InvalidArgument);
+        }
+
+        for vma in remove_range(&mut self.vmas, addr, end) {
+            for page in (vma.start..vma.end).step_by(PAGE_SIZE) {
+                if self.vmap.is_valid(VirtualAddr::from(page)) {
+                    self.vmap.dealloc(VirtualAddr::from(page));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up how to map the page at `va` when it is accessed, for writing
+    /// if `write` is set.
+    ///
+    /// Returns `Ok(None)` if the page is mapped already, or `Ok(Some)` of a
+    /// copy of the mapping made with `mmap` it lies in, to fill the page from
+    /// with `Vma::fill()`. The copy lets the page be filled without holding
+    /// the scheduler lock, and installed with `install()` afterwards.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::BadAddress`: The page is neither mapped nor part of a mapping.
+    /// - `OsError::NoAccess`: `write` is set and the page is part of a read-only mapping.
+    pub fn lookup_fault(&self, va: usize, write: bool) -> OsResult<Option<Vma>> {
+        let page = va & PAGE_MASK;
+        if page < USER_VA_BASE {
+            return Err(OsError::BadAddress);
+        }
+        let vma = self.vmas.iter().find(|vma| vma.contains(page));
+        let read_only = |vma: &Vma| vma.perm == PagePerm::RO || vma.perm == PagePerm::RX;
+        if write && vma.map_or(false, read_only) {
+            return Err(OsError::NoAccess);
+        }
+        if self.vmap.is_valid(VirtualAddr::from(page)) {
+            return Ok(None);
+        }
+        vma.cloned().map(Some).ok_or(OsError::BadAddress)
+    }
+
+    /// Maps the page at `va` to `frame`, filled as `lookup_fault()` said, with
+    /// `perm`.
+    ///
+    /// Returns `false`, leaving `frame` to the caller, if the page was mapped
+    /// meanwhile or is not part of a mapping with `perm` anymore.
+    pub fn install(&mut self, va: usize, frame: usize, perm: PagePerm) -> bool {
+        let page = va & PAGE_MASK;
+        if self.vmap.is_valid(VirtualAddr::from(page))
+            || !self.vmas.iter().any(|vma| vma.contains(page) && vma.perm == perm)
+        {
+            return false;
+        }
+        self.vmap.map(VirtualAddr::from(page), frame, perm);
+        true
+    }
+
+    /// Returns `true` if descriptor `fd` is in blocking mode.
+    pub fn is_blocking(&self, fd: usize) -> bool {
+        !self.nonblocking.contains(&fd)
+    }
+
     /// Returns `true` if this process is ready to be scheduled.
     ///
     /// This functions returns `true` only if one of the following holds:
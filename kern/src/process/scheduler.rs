@@ -12,16 +12,22 @@ use pi::local_interrupt::{LocalInterrupt, LocalController, local_tick_in};
 use smoltcp::time::Instant;
 use pi::{interrupt, timer};
 
+use crate::console::CONSOLE;
+use crate::gpio::USER_GPIO;
 use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::param::*;
-use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq};
+use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq, set_process_stack};
 use crate::process::{Id, Process, State};
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::{TrapFrame, irq};
-use crate::{VMM, GLOABAL_IRQ, SCHEDULER, ETHERNET, USB};
+use crate::vm::{handle_shootdown, sync_tlb, SHOOTDOWN_MAILBOX};
+use crate::watchdog::WATCHDOG;
+use crate::{VMM, FRAMES, GLOABAL_IRQ, SCHEDULER, ETHERNET, RSHELL, SPI, USB};
 use crate::rng::RNG;
 
+use kernel_api::{OsError, OsResult};
+
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
 pub struct GlobalScheduler(Mutex<Option<Box<Scheduler>>>);
@@ -77,8 +83,10 @@ impl GlobalScheduler {
                     tf.x[28],
                     tf.x[27]
                 );
+                WATCHDOG.unpark();
                 return id;
             }
+            WATCHDOG.park();
             aarch64::wfe();
         }
     }
@@ -90,6 +98,60 @@ impl GlobalScheduler {
         self.critical(|scheduler| scheduler.kill(tf))
     }
 
+    /// Maps the page at `va` of the process of `tf` for an access, a write if
+    /// `write` is set, if it is part of a mapping made with `mmap` and not
+    /// mapped yet. For more details, see the documentation on
+    /// `Process::lookup_fault()`.
+    ///
+    /// The page is filled without holding the scheduler lock, as filling it
+    /// from a file reads the disk.
+    pub fn fault(&self, tf: &TrapFrame, va: usize, write: bool) -> OsResult<()> {
+        loop {
+            let lookup = self.critical(|scheduler| scheduler.find_process(tf).lookup_fault(va, write));
+            let mut vma = match lookup? {
+                Some(vma) => vma,
+                None => return Ok(()),
+            };
+            let page = va & PAGE_MASK;
+            let frame = FRAMES.alloc().ok_or(OsError::NoMemory)?;
+            // `fill()` zeroes what it does not read, so nothing of the last
+            // owner of the frame is left.
+            let buf = unsafe { core::slice::from_raw_parts_mut(frame as *mut u8, PAGE_SIZE) };
+            if let Err(e) = vma.fill(page, buf) {
+                error!("GlobalScheduler::fault() failed to fill page {:#x}: {:?}", page, e);
+                FRAMES.release(frame);
+                return Err(OsError::from(e));
+            }
+            // Look the page up again if it changed meanwhile.
+            if !self.critical(|scheduler| scheduler.find_process(tf).install(page, frame, vma.perm)) {
+                FRAMES.release(frame);
+            }
+        }
+    }
+
+    /// Makes sure the pages of the `len` bytes at `va` of the process of `tf`
+    /// are mapped, so the kernel can access them on behalf of the process,
+    /// faulting in the pages of mappings made with `mmap`.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::BadAddress`: A page is neither mapped nor part of a mapping.
+    /// - `OsError::NoAccess`: `write` is set and a page is part of a read-only mapping.
+    pub fn fault_in(&self, tf: &TrapFrame, va: usize, len: usize, write: bool) -> OsResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let last = va.checked_add(len - 1).ok_or(OsError::BadAddress)? & PAGE_MASK;
+        let mut page = va & PAGE_MASK;
+        loop {
+            self.fault(tf, page, write)?;
+            if page == last {
+                return Ok(());
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal
     /// conditions.
@@ -100,6 +162,7 @@ impl GlobalScheduler {
         }
 
         self.initialize_local_timer_interrupt();
+        self.initialize_tlb_shootdown_interrupt();
         let mut tf = TrapFrame::default();
         enable_fiq_interrupt();
         let proc_id = self.switch_to(&mut tf);
@@ -111,10 +174,10 @@ impl GlobalScheduler {
                 "mrs x0, MPIDR_EL1 // calcluate core stack: store register containing core affinity in x0
                 and x0, x0, #0xff // mask to get core_n
                 mov x1, $3 // move KERNEL_STACK_BASE into x1: 0x80_000
-                mov x2, $4 // move KERNEL_STACK_SIZE into x2: 0x10_000
-                msub x0, x0, x2, x1 // calculate stack for core and store in x0 (KERNEL_STACK_BASE - (core_n * KERNEL_STACK_SIZE)
-                str $1, [x0] // store address of tf.x into address at x0 (the calulated stack pointer)
-                str $2, [x0, #-8] // store address of tf.q into the address at x0-8
+                mov x2, $4 // move KERNEL_STACK_STRIDE into x2: 0x20_000
+                msub x0, x0, x2, x1 // calculate stack for core and store in x0 (KERNEL_STACK_BASE - (core_n * KERNEL_STACK_STRIDE)
+                str $1, [x0, #-8] // store address of tf.x into address at x0-8, the top of the core's stack
+                str $2, [x0, #-16] // store address of tf.q into the address at x0-16
                 mov SP, $0 // move address of tf into the stack pointer
                 bl context_restore // restore tf to prepare it to run
                 mrs x0, MPIDR_EL1 // repeat steps to calculate core stack pointer
@@ -123,8 +186,8 @@ impl GlobalScheduler {
                 mov x2, $4
                 msub x0, x0, x2, x1
                 mov SP, x0 // set the stack pointer to the new calculated core stack pointer
-                ldr x1, [x0] // load the value in the address at x0 into x1, this contains the address tf.x
-                ldr x2, [x0, #-8] // load the value in the address at x0 - 8 into x2, this contains the address of tf.q
+                ldr x1, [x0, #-8] // load the value in the address at x0 - 8 into x1, this contains the address tf.x
+                ldr x2, [x0, #-16] // load the value in the address at x0 - 16 into x2, this contains the address of tf.q
                 ldr q0, [x2] // restore q registers from the address of tf.q
                 ldr q1, [x2, #16]
                 ldr q2, [x2, #32]
@@ -191,7 +254,7 @@ impl GlobalScheduler {
                 eret"
                 :
                 : "r"(&tf as *const TrapFrame),  "r"(x_regs_ptr), "r"(q_regs_ptr),
-                    "i"(KERN_STACK_BASE), "i"(KERN_STACK_SIZE)
+                    "i"(KERN_STACK_BASE), "i"(KERN_STACK_STRIDE)
                 : "x0", "x1", "x2"
                 : "volatile"
             );
@@ -207,8 +270,17 @@ impl GlobalScheduler {
     /// # Lab 5
     /// Registers a timer handler with `Usb::start_kernel_timer` which will
     /// invoke `poll_ethernet` after 1 second.
+    ///
+    /// USPi's kernel timer is not available when the ethernet driver runs on
+    /// the loopback device, so `Timer1` drives `poll_loopback` instead.
     pub fn initialize_global_timer_interrupt(&self) {
-        USB.start_kernel_timer(Duration::from_millis(1000), Some(poll_ethernet));
+        if ETHERNET.is_loopback() {
+            interrupt::Controller::new().enable(interrupt::Interrupt::Timer1);
+            GLOABAL_IRQ.register(interrupt::Interrupt::Timer1, Box::new(|_tf| poll_loopback()));
+            timer::tick_in(Duration::from_millis(1000));
+        } else {
+            USB.start_kernel_timer(Duration::from_millis(1000), Some(poll_ethernet));
+        }
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
@@ -222,10 +294,22 @@ impl GlobalScheduler {
         local_irq().register(LocalInterrupt::CntpnsIrq, Box::new(|tf|{
             let core = affinity();
             local_tick_in(core, TICK);
+            WATCHDOG.tick();
             SCHEDULER.switch(State::Ready, tf);
         }));
     }
 
+    /// Initializes the per-core TLB shootdown interrupt. Other cores set the
+    /// `SHOOTDOWN_MAILBOX` of this core, raising `Mailbox0`, once they have
+    /// queued an address space whose translations this core must drop.
+    pub fn initialize_tlb_shootdown_interrupt(&self) {
+        let core = affinity();
+        LocalController::new(core).enable_mailbox(SHOOTDOWN_MAILBOX);
+        local_irq().register(LocalInterrupt::Mailbox0, Box::new(|_tf| {
+            handle_shootdown();
+        }));
+    }
+
     /// Initializes the scheduler and add userspace processes to the Scheduler.
     pub unsafe fn initialize(&self) {
         *self.0.lock() = Some(Box::new(Scheduler::new()));
@@ -269,11 +353,27 @@ impl GlobalScheduler {
 /// `Usb::start_kernel_timer`.
 extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_void) {
     trace!("poll_ethernet()");
+    let delay = poll_network();
+    USB.start_kernel_timer(delay, Some(poll_ethernet));
+}
+
+/// Polls the ethernet driver, serves the remote shell sessions and returns the
+/// advisory delay until the next poll.
+fn poll_network() -> Duration {
     ETHERNET.poll(Instant::from_millis(timer::current_time().as_millis() as i64));
-    let delay = ETHERNET.poll_delay(
+    RSHELL.poll();
+    ETHERNET.poll_delay(
         Instant::from_millis(timer::current_time().as_millis() as i64)
-    );
-    USB.start_kernel_timer(delay, Some(poll_ethernet));
+    )
+}
+
+/// `Timer1` handler polling the ethernet driver when it runs on the loopback
+/// device. The timer is re-armed after the advisory delay, at most `TICK`.
+fn poll_loopback() {
+    trace!("poll_loopback()");
+    let delay = poll_network();
+    let delay = if delay == Duration::from_millis(0) { TICK } else { delay.min(TICK) };
+    timer::tick_in(delay);
 }
 
 /// Internal scheduler struct which is not thread-safe.
@@ -362,6 +462,13 @@ impl Scheduler {
                 let mut next_process = self.processes.remove(index)
                     .expect("Scheduler::switch_to(): Unexpected invalid index in Schedule.processes");
                 next_process.state = State::Running;
+                // Translations are tagged with ASIDs and survive the switch,
+                // so drop the ones other cores have invalidated first.
+                sync_tlb();
+                next_process.context.ttbr1 = next_process.vmap.activate(affinity());
+                let (bottom, top) =
+                    next_process.kernel_stack.as_ref().map_or((0, 0), |stack| stack.bounds());
+                set_process_stack(bottom, top);
 
                 *tf = *next_process.context;
                 self.processes.push_front(next_process);
@@ -388,9 +495,13 @@ impl Scheduler {
         }
     }
 
-    /// Releases all process resources held by the current process such as sockets.
+    /// Releases all process resources held by the current process such as
+    /// sockets, raw sockets, the console input, GPIO pins and SPI transfers.
     fn release_process_resources(&mut self, tf: &mut TrapFrame) {
         let mut process = self.find_process(tf);
+        CONSOLE.lock().release(process.context.tpidr);
+        USER_GPIO.release_all(process.context.tpidr);
+        SPI.release(process.context.tpidr);
         for handle in &process.sockets {
             let port = ETHERNET.with_socket(*handle, |socket| socket.local_endpoint().port);
             ETHERNET.critical(|ethernet|{
@@ -410,6 +521,12 @@ impl Scheduler {
                 ethernet.prune();
             });
         }
+        for handle in &process.raw_sockets {
+            ETHERNET.critical(|ethernet| {
+                ethernet.release(*handle);
+                ethernet.prune();
+            });
+        }
     }
 
     /// Finds a process corresponding with tpidr saved in a trap frame.
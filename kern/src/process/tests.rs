@@ -0,0 +1,141 @@
+mod vma {
+    use shim::io::Cursor;
+
+    use crate::param::{PAGE_SIZE, USER_MMAP_BASE};
+    use crate::process::vma::{fill_from, remove_range};
+    use crate::process::{Backing, Vma};
+    use crate::vm::PagePerm;
+
+    /// Returns the address of page `n` of the mmap area.
+    fn page(n: usize) -> usize {
+        USER_MMAP_BASE + n * PAGE_SIZE
+    }
+
+    /// Returns anonymous mappings of the pages from `start` up to `end` of
+    /// each range.
+    fn mappings(ranges: &[(usize, usize)]) -> Vec<Vma> {
+        ranges
+            .iter()
+            .map(|&(start, end)| Vma {
+                start: page(start),
+                end: page(end),
+                perm: PagePerm::RW,
+                backing: Backing::Anonymous,
+            })
+            .collect()
+    }
+
+    /// Returns the ranges of pages of `vmas`.
+    fn ranges(vmas: &[Vma]) -> Vec<(usize, usize)> {
+        vmas.iter()
+            .map(|vma| ((vma.start - USER_MMAP_BASE) / PAGE_SIZE, (vma.end - USER_MMAP_BASE) / PAGE_SIZE))
+            .collect()
+    }
+
+    #[test]
+    fn unmap_front() {
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(2), page(4));
+        assert_eq!(ranges(&vmas), vec![(4, 6)]);
+        assert_eq!(ranges(&removed), vec![(2, 4)]);
+
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(0), page(3));
+        assert_eq!(ranges(&vmas), vec![(3, 6)]);
+        assert_eq!(ranges(&removed), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn unmap_middle() {
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(3), page(5));
+        assert_eq!(ranges(&vmas), vec![(2, 3), (5, 6)]);
+        assert_eq!(ranges(&removed), vec![(3, 5)]);
+    }
+
+    #[test]
+    fn unmap_tail() {
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(4), page(6));
+        assert_eq!(ranges(&vmas), vec![(2, 4)]);
+        assert_eq!(ranges(&removed), vec![(4, 6)]);
+
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(5), page(9));
+        assert_eq!(ranges(&vmas), vec![(2, 5)]);
+        assert_eq!(ranges(&removed), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn unmap_whole() {
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(2), page(6));
+        assert!(vmas.is_empty());
+        assert_eq!(ranges(&removed), vec![(2, 6)]);
+
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(1), page(7));
+        assert!(vmas.is_empty());
+        assert_eq!(ranges(&removed), vec![(2, 6)]);
+    }
+
+    #[test]
+    fn unmap_outside() {
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(0), page(2));
+        assert_eq!(ranges(&vmas), vec![(2, 6)]);
+        assert!(removed.is_empty());
+
+        let mut vmas = mappings(&[(2, 6)]);
+        let removed = remove_range(&mut vmas, page(6), page(8));
+        assert_eq!(ranges(&vmas), vec![(2, 6)]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn unmap_across_mappings() {
+        let mut vmas = mappings(&[(0, 2), (2, 4), (6, 9), (10, 12)]);
+        let removed = remove_range(&mut vmas, page(1), page(7));
+        assert_eq!(ranges(&vmas), vec![(0, 1), (7, 9), (10, 12)]);
+        assert_eq!(ranges(&removed), vec![(1, 2), (2, 4), (6, 7)]);
+
+        let mut vmas = mappings(&[(0, 2), (4, 6), (8, 10)]);
+        let removed = remove_range(&mut vmas, page(2), page(8));
+        assert_eq!(ranges(&vmas), vec![(0, 2), (8, 10)]);
+        assert_eq!(ranges(&removed), vec![(4, 6)]);
+    }
+
+    #[test]
+    fn split_keeps_perm() {
+        let mut vmas = mappings(&[(2, 6)]);
+        vmas[0].perm = PagePerm::RX;
+        remove_range(&mut vmas, page(3), page(4));
+        assert!(vmas.iter().all(|vma| vma.perm == PagePerm::RX));
+    }
+
+    #[test]
+    fn fill_from_file() {
+        let data: Vec<u8> = (0..200u8).collect();
+        let mut page = [0xff; 64];
+        fill_from(&mut Cursor::new(&data[..]), data.len(), 64, &mut page).unwrap();
+        assert_eq!(&page[..], &data[64..128]);
+    }
+
+    #[test]
+    fn fill_past_eof() {
+        let data: Vec<u8> = (0..100u8).collect();
+
+        // The part of the page past the end of the file is zeroed.
+        let mut page = [0xff; 64];
+        fill_from(&mut Cursor::new(&data[..]), data.len(), 60, &mut page).unwrap();
+        assert_eq!(&page[..40], &data[60..]);
+        assert!(page[40..].iter().all(|&byte| byte == 0));
+
+        // So is a page wholly past it.
+        let mut page = [0xff; 64];
+        fill_from(&mut Cursor::new(&data[..]), data.len(), 100, &mut page).unwrap();
+        assert!(page.iter().all(|&byte| byte == 0));
+        fill_from(&mut Cursor::new(&data[..]), data.len(), 4096, &mut page).unwrap();
+        assert!(page.iter().all(|&byte| byte == 0));
+    }
+}
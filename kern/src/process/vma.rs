@@ -0,0 +1,130 @@
+use alloc::vec::Vec;
+
+use shim::io::{self, Read, Seek, SeekFrom};
+
+use fat32::vfat::File;
+
+use crate::fs::PiVFatHandle;
+use crate::vm::PagePerm;
+
+/// What the pages of a mapping are filled with when they are first touched.
+#[derive(Debug, Clone)]
+pub enum Backing {
+    /// Zero-filled pages.
+    Anonymous,
+    /// Pages read from `file`, the first one from byte `offset`. The part of
+    /// a page past the end of the file is zero-filled.
+    File {
+        file: File<PiVFatHandle>,
+        offset: usize,
+    },
+}
+
+/// A range of pages of a process made with `mmap`, from `start` up to `end`.
+/// The page fault handler maps its pages lazily.
+#[derive(Debug, Clone)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    pub perm: PagePerm,
+    pub backing: Backing,
+}
+
+impl Vma {
+    /// Returns `true` if `va` lies in this mapping.
+    pub fn contains(&self, va: usize) -> bool {
+        va >= self.start && va < self.end
+    }
+
+    /// Returns `true` if this mapping overlaps the range from `start` up to
+    /// `end`.
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+
+    /// Fills `page`, the page of this mapping at `va`, with its contents.
+    pub fn fill(&mut self, va: usize, page: &mut [u8]) -> io::Result<()> {
+        match &mut self.backing {
+            Backing::Anonymous => {
+                for byte in page.iter_mut() {
+                    *byte = 0;
+                }
+                Ok(())
+            }
+            Backing::File { file, offset } => {
+                let size = file.size;
+                fill_from(file, size, *offset + (va - self.start), page)
+            }
+        }
+    }
+
+    /// Splits this mapping at `at`, keeping the pages below it and returning
+    /// a mapping of the pages from `at` on.
+    pub fn split_off(&mut self, at: usize) -> Vma {
+        let backing = match &self.backing {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::File { file, offset } => Backing::File {
+                file: file.clone(),
+                offset: *offset + (at - self.start),
+            },
+        };
+        let tail = Vma {
+            start: at,
+            end: self.end,
+            perm: self.perm,
+            backing,
+        };
+        self.end = at;
+        tail
+    }
+}
+
+/// Fills `page` with the bytes of `file`, `size` bytes long, from byte
+/// `position` on. The part of the page past the end of the file is zeroed.
+pub fn fill_from<R: Read + Seek>(
+    file: &mut R,
+    size: usize,
+    position: usize,
+    page: &mut [u8],
+) -> io::Result<()> {
+    for byte in page.iter_mut() {
+        *byte = 0;
+    }
+    if position >= size {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(position as u64))?;
+    let mut filled = 0;
+    while filled < page.len() {
+        match file.read(&mut page[filled..])? {
+            0 => break,
+            bytes => filled += bytes,
+        }
+    }
+    Ok(())
+}
+
+/// Removes the range from `start` up to `end` from `vmas`, which are sorted
+/// by address, trimming or splitting the mappings that straddle it. Returns
+/// the parts of the mappings that were in the range.
+pub fn remove_range(vmas: &mut Vec<Vma>, start: usize, end: usize) -> Vec<Vma> {
+    let mut removed = Vec::new();
+    let mut kept = Vec::with_capacity(vmas.len() + 1);
+    for mut vma in vmas.drain(..) {
+        if !vma.overlaps(start, end) {
+            kept.push(vma);
+            continue;
+        }
+        if vma.start < start {
+            let rest = vma.split_off(start);
+            kept.push(vma);
+            vma = rest;
+        }
+        if vma.end > end {
+            kept.push(vma.split_off(end));
+        }
+        removed.push(vma);
+    }
+    *vmas = kept;
+    removed
+}
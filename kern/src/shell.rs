@@ -15,13 +15,19 @@ use fat32::traits::{Dir, Entry, Metadata};
 
 use aarch64;
 
-use crate::console::{kprint, kprintln, CONSOLE};
+use crate::console::{self, kprint, kprintln, CONSOLE};
+use crate::net::pcap::{CAPTURE, DEFAULT_CAPTURE_SIZE};
+use crate::allocator::{slab, stats};
 use crate::ALLOCATOR;
+use crate::FRAMES;
 use crate::FILESYSTEM;
 use crate::SCHEDULER;
 use crate::process::Process;
+use crate::watchdog;
 use pi::{timer, gpio, rng};
 
+pub mod remote;
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
@@ -113,7 +119,7 @@ pub fn shell(prefix: &str) {
         let mut input_buf = [0u8; 512];
         let mut input = StackVec::new(&mut input_buf);
         'read_char: loop {
-            let byte = CONSOLE.lock().read_byte();
+            let byte = console::read_byte();
             match byte {
                 DEL | BACK => {
                     if !input.is_empty() {
@@ -142,70 +148,11 @@ pub fn shell(prefix: &str) {
         let mut args_buf = [""; 64];
         match Command::parse(input_str, &mut args_buf) {
             Ok(command) => {
-                let exec_result = match command.path() {
-                    "echo" => {
-                        match Echo::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "pwd" => {
-                        match Pwd::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "cd" => {
-                        match  Cd::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "ls" =>{
-                        match Ls::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "ll" => {
-                        match Ls::new(Some("l")) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "cat" => {
-                        match Cat::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "exit" => {
-                        kprintln!("Goodbye...");
-                        break
-                    },
-                    "dice" => {
-                        match Dice::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                    "panic!" => panic!("called panic"),
-                    _path => {
-                        match Unknown::new(None) {
-                            Ok(ref mut executable) => executable
-                                .exec(&command, &mut cwd),
-                            Err(e) => Err(e),
-                        }
-                    },
-                };
-                match exec_result {
+                if command.path() == "exit" {
+                    kprintln!("Goodbye...");
+                    break
+                }
+                match exec(&command, &mut cwd) {
                     Ok(std_out) => {
                         kprint!("{}", std_out.result);
                         error_level = 0;
@@ -224,6 +171,121 @@ pub fn shell(prefix: &str) {
     }
 }
 
+/// Parses `line` and executes it as a shell command with `cwd` as the working
+/// directory. Used by shell sessions that are not attached to the console.
+///
+/// An empty line succeeds with no output.
+pub fn run(line: &str, cwd: &mut PathBuf) -> StdResult {
+    let mut args_buf = [""; 64];
+    match Command::parse(line, &mut args_buf) {
+        Ok(command) => exec(&command, cwd),
+        Err(Error::TooManyArgs) => Err(StdError {
+            result: String::from("bwsh: too many arguments\n"),
+            code: 1,
+        }),
+        Err(Error::Empty) => Ok(StdOut { result: String::new() }),
+    }
+}
+
+/// Executes the built-in or program named by `command`.
+fn exec(command: &Command, cwd: &mut PathBuf) -> StdResult {
+    match command.path() {
+        "echo" => {
+            match Echo::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "pwd" => {
+            match Pwd::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "cd" => {
+            match  Cd::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "ls" =>{
+            match Ls::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "ll" => {
+            match Ls::new(Some("l")) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "cat" => {
+            match Cat::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "dice" => {
+            match Dice::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "pcap" => {
+            match Pcap::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "board" => {
+            match Board::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "mem" => {
+            match Mem::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "slabs" => {
+            match Slabs::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+        "reboot" => {
+            kprintln!("Rebooting...");
+            watchdog::reboot()
+        }
+        "halt" => {
+            kprintln!("Halting, cycle the power to boot again.");
+            watchdog::halt()
+        }
+        "panic!" => panic!("called panic"),
+        _path => {
+            match Unknown::new(None) {
+                Ok(ref mut executable) => executable
+                    .exec(command, cwd),
+                Err(e) => Err(e),
+            }
+        },
+    }
+}
+
 pub type StdResult = Result<StdOut, StdError>;
 
 pub type ExecutableResult<T> = Result<T, StdError>;
@@ -664,6 +726,348 @@ impl Executable for Dice {
     }
 }
 
+struct Pcap;
+
+impl Executable for Pcap {
+    fn new(_params: Option<&str>) -> ExecutableResult<Self> {
+        Ok(Pcap)
+    }
+
+    fn exec(&mut self, cmd: &Command, _cwd: &mut PathBuf) -> StdResult {
+        let mut result = String::new();
+        let usage = "usage: pcap start [size_kb] | stop | status | clear | dump";
+        if cmd.args.len() < 2 || cmd.args.len() > 3 {
+            writeln!(result, "{}", usage)?;
+
+            return Err(StdError { result, code: 1 });
+        }
+        match cmd.args[1] {
+            "start" => {
+                let capacity = match cmd.args.get(2) {
+                    Some(size) => match size.parse::<usize>() {
+                        Ok(size_kb) if size_kb > 0 => size_kb * 1024,
+                        _ => {
+                            writeln!(result, "pcap: invalid size: {}", size)?;
+
+                            return Err(StdError { result, code: 1 });
+                        }
+                    },
+                    None => DEFAULT_CAPTURE_SIZE,
+                };
+                CAPTURE.start(capacity);
+                writeln!(result, "pcap: capturing into a {} KiB buffer", capacity / 1024)?;
+            }
+            "stop" => {
+                CAPTURE.stop();
+                let stats = CAPTURE.stats();
+                writeln!(result, "pcap: stopped, {} frames captured", stats.frames)?;
+            }
+            "status" => {
+                let stats = CAPTURE.stats();
+                writeln!(
+                    result,
+                    "pcap: {}, {} frames, {}/{} bytes, {} dropped",
+                    if stats.enabled { "capturing" } else { "stopped" },
+                    stats.frames,
+                    stats.size,
+                    stats.capacity,
+                    stats.dropped
+                )?;
+            }
+            "clear" => {
+                CAPTURE.clear();
+                writeln!(result, "pcap: buffer cleared")?;
+            }
+            "dump" => {
+                // The file system is read-only, so the capture is streamed over
+                // the UART instead of being written to the SD card.
+                let pcap = CAPTURE.to_pcap();
+                kprintln!("pcap: sending {} bytes with XMODEM, start the receiver now", pcap.len());
+                kprintln!("pcap: (e.g. `rx capture.pcap < /dev/ttyUSB0 > /dev/ttyUSB0`)");
+                let mut console = CONSOLE.lock();
+                match xmodem::Xmodem::transmit(&pcap[..], &mut *console) {
+                    Ok(bytes) => {
+                        drop(console);
+                        writeln!(result, "pcap: sent {} bytes", bytes)?;
+                    }
+                    Err(e) => {
+                        drop(console);
+                        writeln!(result, "pcap: transfer failed: {:?}", e)?;
+
+                        return Err(StdError { result, code: 1 });
+                    }
+                }
+            }
+            _ => {
+                writeln!(result, "{}", usage)?;
+
+                return Err(StdError { result, code: 1 });
+            }
+        }
+
+        Ok(StdOut { result })
+    }
+}
+
+struct Board;
+
+impl Executable for Board {
+    fn new(_params: Option<&str>) -> ExecutableResult<Self> {
+        Ok(Board)
+    }
+
+    fn exec(&mut self, cmd: &Command, _cwd: &mut PathBuf) -> StdResult {
+        use pi::mailbox::{self, Clock, Message, MemoryRegion};
+        use pi::mailbox::{GetArmMemory, GetBoardRevision, GetBoardSerial, GetClockRate};
+        use pi::mailbox::{GetFirmwareRevision, GetMacAddress, GetMaxClockRate};
+        use pi::mailbox::{GetTemperature, GetVcMemory};
+
+        let mut result = String::new();
+        if cmd.args.len() > 1 {
+            writeln!(result, "usage: board")?;
+
+            return Err(StdError { result, code: 1 });
+        }
+
+        let mut message = Message::new();
+        let tags = (|| -> mailbox::Result<_> {
+            Ok((
+                message.push(GetBoardRevision)?,
+                message.push(GetBoardSerial)?,
+                message.push(GetFirmwareRevision)?,
+                message.push(GetMacAddress)?,
+                message.push(GetArmMemory)?,
+                message.push(GetVcMemory)?,
+                message.push(GetClockRate(Clock::Arm))?,
+                message.push(GetMaxClockRate(Clock::Arm))?,
+                message.push(GetClockRate(Clock::Core))?,
+                message.push(GetTemperature)?,
+            ))
+        })();
+        let (revision, serial, firmware, mac, arm, vc, arm_clock, arm_max, core_clock, temp) =
+            match tags.and_then(|tags| message.send().map(|_| tags)) {
+                Ok(tags) => tags,
+                Err(e) => {
+                    writeln!(result, "board: mailbox request failed: {:?}", e)?;
+
+                    return Err(StdError { result, code: 1 });
+                }
+            };
+
+        // Tags the firmware (or QEMU) does not know about are reported as
+        // unavailable rather than failing the whole command.
+        match message.get(&revision) {
+            Ok(revision) => writeln!(result, "revision:     {:#08x}", revision)?,
+            Err(_) => writeln!(result, "revision:     unavailable")?,
+        }
+        match message.get(&serial) {
+            Ok(serial) => writeln!(result, "serial:       {:016x}", serial)?,
+            Err(_) => writeln!(result, "serial:       unavailable")?,
+        }
+        match message.get(&firmware) {
+            Ok(firmware) => writeln!(result, "firmware:     {:#x}", firmware)?,
+            Err(_) => writeln!(result, "firmware:     unavailable")?,
+        }
+        match message.get(&mac) {
+            Ok(m) => writeln!(
+                result,
+                "mac address:  {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                m[0], m[1], m[2], m[3], m[4], m[5]
+            )?,
+            Err(_) => writeln!(result, "mac address:  unavailable")?,
+        }
+        let regions = [("arm memory:", message.get(&arm)), ("vc memory: ", message.get(&vc))];
+        for &(name, region) in regions.iter() {
+            match region {
+                Ok(MemoryRegion { base, size }) => writeln!(
+                    result,
+                    "{}   {:#010x}-{:#010x} ({} MiB)",
+                    name,
+                    base,
+                    base as u64 + size as u64,
+                    size / (1024 * 1024)
+                )?,
+                Err(_) => writeln!(result, "{}   unavailable", name)?,
+            }
+        }
+        match (message.get(&arm_clock), message.get(&arm_max)) {
+            (Ok(rate), Ok(max)) => writeln!(
+                result,
+                "arm clock:    {} MHz (max {} MHz)",
+                rate / 1_000_000,
+                max / 1_000_000
+            )?,
+            (Ok(rate), Err(_)) => writeln!(result, "arm clock:    {} MHz", rate / 1_000_000)?,
+            _ => writeln!(result, "arm clock:    unavailable")?,
+        }
+        match message.get(&core_clock) {
+            Ok(rate) => writeln!(result, "core clock:   {} MHz", rate / 1_000_000)?,
+            Err(_) => writeln!(result, "core clock:   unavailable")?,
+        }
+        match message.get(&temp) {
+            Ok(temp) => writeln!(result, "temperature:  {}.{} C", temp / 1000, temp % 1000 / 100)?,
+            Err(_) => writeln!(result, "temperature:  unavailable")?,
+        }
+
+        Ok(StdOut { result })
+    }
+}
+
+struct Mem;
+
+impl Mem {
+    fn summary(&self, result: &mut String) -> fmt::Result {
+        let heap = ALLOCATOR.stats();
+        writeln!(
+            result,
+            "heap:          {} KiB, {} KiB free, largest free block {} KiB",
+            heap.total / 1024,
+            heap.free / 1024,
+            heap.largest_free / 1024
+        )?;
+        writeln!(
+            result,
+            "in use:        {} bytes in {} allocations, {} bytes in blocks",
+            heap.requested, heap.allocations, heap.allocated
+        )?;
+        writeln!(result, "fragmentation: {} bytes", heap.fragmentation())?;
+        writeln!(result, "high water:    {} bytes", heap.high_water)?;
+        writeln!(result, "failed:        {}", heap.failed)?;
+        for (class, &count) in heap.classes.iter().enumerate() {
+            if count != 0 {
+                writeln!(result, "  <= {:>10} bytes: {}", stats::class_size(class), count)?;
+            }
+        }
+
+        let frames = FRAMES.stats();
+        writeln!(
+            result,
+            "frames:        {} of {} in use, {} shared",
+            frames.used(),
+            frames.total,
+            frames.shared
+        )?;
+        let (objects, in_use) = slab::CACHES.iter().fold((0, 0), |(objects, in_use), cache| {
+            let stats = cache.stats();
+            (objects + stats.objects, in_use + stats.in_use)
+        });
+        writeln!(result, "slabs:         {} of {} objects in use", in_use, objects)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "leak-tracking")]
+    fn leaks(&self, result: &mut String) -> fmt::Result {
+        use alloc::collections::BTreeMap;
+        use crate::allocator::leaks::{LEAKS, TRACKED_MAX};
+
+        // Collect the records into memory allocated beforehand, as the heap
+        // cannot be used while the tracker is locked.
+        let mut records = Vec::with_capacity(TRACKED_MAX);
+        let untracked = {
+            let leaks = LEAKS.lock();
+            leaks.leaks(&mut records);
+            leaks.untracked()
+        };
+
+        let mut sites = BTreeMap::new();
+        for record in records.iter() {
+            let site = sites.entry(record.callers).or_insert((0, 0));
+            site.0 += 1;
+            site.1 += record.size;
+        }
+        for (callers, (count, bytes)) in sites {
+            write!(result, "{:>6} allocations, {:>9} bytes:", count, bytes)?;
+            for &caller in callers.iter().take_while(|&&caller| caller != 0) {
+                write!(result, " {:#x}", caller)?;
+            }
+            writeln!(result)?;
+        }
+        if untracked != 0 {
+            writeln!(result, "{} allocations were not tracked", untracked)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Executable for Mem {
+    fn new(_params: Option<&str>) -> ExecutableResult<Self> {
+        Ok(Mem)
+    }
+
+    fn exec(&mut self, cmd: &Command, _cwd: &mut PathBuf) -> StdResult {
+        let mut result = String::new();
+        if cmd.args.len() > 2 {
+            writeln!(result, "usage: mem [mark | leaks]")?;
+
+            return Err(StdError { result, code: 1 });
+        }
+        match cmd.args.get(1).cloned() {
+            None => self.summary(&mut result)?,
+            #[cfg(feature = "leak-tracking")]
+            Some("mark") => {
+                crate::allocator::leaks::LEAKS.lock().mark();
+                writeln!(result, "mem: allocations from now on are reported by `mem leaks`")?;
+            }
+            #[cfg(feature = "leak-tracking")]
+            Some("leaks") => self.leaks(&mut result)?,
+            #[cfg(not(feature = "leak-tracking"))]
+            Some("mark") | Some("leaks") => {
+                writeln!(result, "mem: leak tracking is off, build with LEAK_TRACKING=1")?;
+
+                return Err(StdError { result, code: 1 });
+            }
+            Some(_) => {
+                writeln!(result, "usage: mem [mark | leaks]")?;
+
+                return Err(StdError { result, code: 1 });
+            }
+        }
+
+        Ok(StdOut { result })
+    }
+}
+
+struct Slabs;
+
+impl Executable for Slabs {
+    fn new(_params: Option<&str>) -> ExecutableResult<Self> {
+        Ok(Slabs)
+    }
+
+    fn exec(&mut self, cmd: &Command, _cwd: &mut PathBuf) -> StdResult {
+        let mut result = String::new();
+        if cmd.args.len() > 1 {
+            writeln!(result, "usage: slabs")?;
+
+            return Err(StdError { result, code: 1 });
+        }
+
+        writeln!(
+            result,
+            "{:<10} {:>7} {:>6} {:>8} {:>7} {:>7} {:>9} {:>7}",
+            "cache", "size", "slabs", "objects", "in use", "cached", "hits", "misses"
+        )?;
+        for cache in slab::CACHES.iter() {
+            let stats = cache.stats();
+            writeln!(
+                result,
+                "{:<10} {:>7} {:>6} {:>8} {:>7} {:>7} {:>9} {:>7}",
+                stats.name,
+                stats.size,
+                stats.slabs,
+                stats.objects,
+                stats.in_use,
+                stats.cached,
+                stats.hits,
+                stats.misses
+            )?;
+        }
+
+        Ok(StdOut { result })
+    }
+}
 
 fn set_working_dir(path: &Path, cwd: &mut PathBuf) {
     if path.is_absolute() {
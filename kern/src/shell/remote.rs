@@ -0,0 +1,450 @@
+///! Telnet-style shell sessions served over TCP
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem;
+
+use shim::path::PathBuf;
+use smoltcp::socket::{SocketHandle, TcpState};
+
+use kernel_api::NR_SLEEP;
+
+use crate::console::capture_output;
+use crate::mutex::Mutex;
+use crate::param::{RSHELL_PORT, RSHELL_SESSIONS, RSHELL_STACK_SIZE};
+use crate::process::Process;
+use crate::watchdog::WATCHDOG;
+use crate::{ETHERNET, RSHELL, SCHEDULER};
+
+const NUL: u8 = 0;
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+const BELL: u8 = 7;
+const BACK: u8 = 8;
+const LF: u8 = b'\n';
+const CR: u8 = b'\r';
+const DEL: u8 = 127;
+const MAX_LINE_LEN: usize = 512;
+
+/// How long the shell process sleeps when no command is queued, in
+/// milliseconds.
+const IDLE_SLEEP_MS: u64 = 20;
+
+/// Commands that stop the whole machine, which only the console shell runs.
+const LOCAL_ONLY: [&str; 2] = ["halt", "panic!"];
+/// Commands that stop the whole machine but recover a hung board, which the
+/// remote shell runs once the client confirms them.
+const CONFIRMED: [&str; 1] = ["reboot"];
+
+// Telnet commands and options (RFC 854, 857, 858)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const OPT_ECHO: u8 = 1;
+const OPT_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Where the session is in the telnet command stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Telnet {
+    /// Plain data.
+    Data,
+    /// After `IAC`.
+    Command,
+    /// After `IAC WILL|WONT|DO|DONT`, expecting the option byte.
+    Option,
+    /// Inside a subnegotiation, after `IAC SB`.
+    Subnegotiation,
+    /// After `IAC` inside a subnegotiation.
+    SubnegotiationCommand,
+}
+
+/// The command line of a session, which the shell process runs.
+#[derive(Debug)]
+enum Job {
+    Idle,
+    /// A line waiting for the client to confirm it.
+    Confirm(String),
+    /// A line waiting for the shell process.
+    Queued(String),
+    /// The shell process is running the line.
+    Running,
+}
+
+/// A shell session bound to one TCP socket. The socket accepts a single
+/// connection at a time and listens again once that connection is closed.
+struct Session {
+    handle: SocketHandle,
+    connected: bool,
+    cwd: PathBuf,
+    line: Vec<u8>,
+    telnet: Telnet,
+    /// The previous data byte was a `CR`, so a following `LF` or `NUL` ends
+    /// the same line.
+    after_cr: bool,
+    /// Bytes waiting for room in the socket's transmit buffer.
+    output: VecDeque<u8>,
+    /// The connection is closed once `output` is flushed.
+    closing: bool,
+    job: Job,
+}
+
+impl Session {
+    fn new(handle: SocketHandle) -> Session {
+        Session {
+            handle,
+            connected: false,
+            cwd: PathBuf::from("/"),
+            line: Vec::new(),
+            telnet: Telnet::Data,
+            after_cr: false,
+            output: VecDeque::new(),
+            closing: false,
+            job: Job::Idle,
+        }
+    }
+
+    /// Forgets everything about the previous connection.
+    fn reset(&mut self) {
+        self.connected = false;
+        self.cwd = PathBuf::from("/");
+        self.line.clear();
+        self.telnet = Telnet::Data;
+        self.after_cr = false;
+        self.output.clear();
+        self.closing = false;
+        self.job = Job::Idle;
+    }
+
+    /// Receives pending input, queues complete command lines and sends queued
+    /// output.
+    fn poll(&mut self) {
+        let mut buf = [0u8; 512];
+        let (state, len) = ETHERNET.with_socket(self.handle, |socket| {
+            let len = match socket.can_recv() {
+                true => socket.recv_slice(&mut buf).unwrap_or(0),
+                false => 0,
+            };
+            (socket.state(), len)
+        });
+
+        match state {
+            TcpState::Closed => {
+                if self.connected {
+                    debug!("RemoteShell: session on {:?} closed", self.handle);
+                }
+                self.reset();
+                ETHERNET.with_socket(self.handle, |socket| {
+                    if let Err(e) = socket.listen(RSHELL_PORT) {
+                        error!("RemoteShell: listen() failed: {:?}", e);
+                    }
+                });
+                return;
+            }
+            TcpState::Listen | TcpState::SynReceived => return,
+            TcpState::CloseWait => self.closing = true,
+            _ => (),
+        }
+
+        if !self.connected {
+            debug!("RemoteShell: session on {:?} connected", self.handle);
+            self.connected = true;
+            self.start();
+        }
+        for &byte in buf[..len].iter() {
+            self.input(byte);
+        }
+        self.flush();
+    }
+
+    /// Negotiates character mode with the client and greets it.
+    fn start(&mut self) {
+        self.output.extend(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SUPPRESS_GO_AHEAD]);
+        self.write_str("\nBrentward Shell (bwsh: 0.0.1a)\n");
+        self.prompt();
+    }
+
+    /// Strips telnet commands from the input and passes data to `edit()`.
+    fn input(&mut self, byte: u8) {
+        let telnet = self.telnet;
+        self.telnet = match telnet {
+            Telnet::Data if byte == IAC => Telnet::Command,
+            Telnet::Data => {
+                self.edit(byte);
+                Telnet::Data
+            }
+            Telnet::Command => match byte {
+                WILL | WONT | DO | DONT => Telnet::Option,
+                SB => Telnet::Subnegotiation,
+                IAC => {
+                    self.edit(IAC);
+                    Telnet::Data
+                }
+                _ => Telnet::Data,
+            },
+            Telnet::Option => Telnet::Data,
+            Telnet::Subnegotiation if byte == IAC => Telnet::SubnegotiationCommand,
+            Telnet::Subnegotiation => Telnet::Subnegotiation,
+            Telnet::SubnegotiationCommand if byte == SE => Telnet::Data,
+            Telnet::SubnegotiationCommand => Telnet::Subnegotiation,
+        };
+    }
+
+    /// Line editing, mirroring the console shell. Input received while a
+    /// command line is queued or running is discarded.
+    fn edit(&mut self, byte: u8) {
+        if self.closing {
+            return;
+        }
+        match self.job {
+            Job::Idle | Job::Confirm(_) => (),
+            _ => return,
+        }
+        let after_cr = mem::replace(&mut self.after_cr, false);
+        match byte {
+            LF | NUL if after_cr => (),
+            CR | LF => {
+                self.after_cr = byte == CR;
+                self.exec_line();
+            }
+            DEL | BACK => {
+                if self.line.pop().is_some() {
+                    self.output.extend(&[BACK, b' ', BACK]);
+                } else {
+                    self.output.push_back(BELL);
+                }
+            }
+            CTRL_C => {
+                self.line.clear();
+                self.job = Job::Idle;
+                self.write_str("^C\n");
+                self.prompt();
+            }
+            CTRL_D if self.line.is_empty() => self.logout(),
+            byte if byte < 32 || byte > 127 => self.output.push_back(BELL),
+            byte => {
+                if self.line.len() < MAX_LINE_LEN {
+                    self.line.push(byte);
+                    self.output.push_back(byte);
+                } else {
+                    self.output.push_back(BELL);
+                }
+            }
+        }
+    }
+
+    /// Queues the command in the line buffer for the shell process. Session
+    /// commands and commands stopping the machine are handled right away, and
+    /// `CONFIRMED` commands are queued once the next line confirms them.
+    fn exec_line(&mut self) {
+        self.write_str("\n");
+        let line = String::from_utf8(mem::replace(&mut self.line, Vec::new()))
+            .expect("input bytes failed to cast back to string");
+        if let Job::Confirm(_) = self.job {
+            let command = match mem::replace(&mut self.job, Job::Idle) {
+                Job::Confirm(command) => command,
+                _ => unreachable!(),
+            };
+            return match line.trim() {
+                "y" | "yes" => self.job = Job::Queued(command),
+                _ => {
+                    self.write_str("cancelled\n");
+                    self.prompt();
+                }
+            };
+        }
+        match line.trim() {
+            "exit" | "logout" => return self.logout(),
+            _ => (),
+        }
+
+        let name = line.split_whitespace().next().unwrap_or("");
+        let mut message = String::new();
+        if LOCAL_ONLY.contains(&name) {
+            writeln!(message, "rsh: {}: only available on the console", name)
+                .expect("write macro error");
+            self.write_str(&message);
+            return self.prompt();
+        }
+        if CONFIRMED.contains(&name) {
+            write!(message, "rsh: {} the board? [y/N] ", name).expect("write macro error");
+            self.write_str(&message);
+            self.job = Job::Confirm(line);
+            return;
+        }
+        self.job = Job::Queued(line);
+    }
+
+    /// Sends the result of the command the shell process ran, as well as
+    /// everything it printed with `kprint!`, to the client.
+    fn complete(&mut self, cwd: PathBuf, printed: &str, result: &str) {
+        match self.job {
+            Job::Running => (),
+            // The connection was closed meanwhile.
+            _ => return,
+        }
+        self.job = Job::Idle;
+        self.cwd = cwd;
+        self.write_str(printed);
+        self.write_str(result);
+        self.prompt();
+        self.flush();
+    }
+
+    fn logout(&mut self) {
+        self.write_str("Goodbye...\n");
+        self.closing = true;
+    }
+
+    fn prompt(&mut self) {
+        let mut prompt = String::new();
+        let cwd = self.cwd.as_path().to_str().expect("cwd path is not valid Unicode");
+        write!(prompt, "{} rsh> ", cwd).expect("write macro error");
+        self.write_str(&prompt);
+    }
+
+    /// Queues `s` for the client, translating `\n` to the telnet `\r\n`.
+    fn write_str(&mut self, s: &str) {
+        let mut prev = 0u8;
+        for &byte in s.as_bytes() {
+            if byte == LF && prev != CR {
+                self.output.push_back(CR);
+            }
+            self.output.push_back(byte);
+            prev = byte;
+        }
+    }
+
+    /// Moves as much queued output as fits into the socket and closes the
+    /// connection once everything is sent after a logout.
+    fn flush(&mut self) {
+        let output = &mut self.output;
+        let closing = self.closing;
+        ETHERNET.with_socket(self.handle, |socket| {
+            while !output.is_empty() && socket.can_send() {
+                let sent = match socket.send_slice(output.as_slices().0) {
+                    Ok(sent) => sent,
+                    Err(_) => break,
+                };
+                if sent == 0 {
+                    break;
+                }
+                output.drain(..sent);
+            }
+            if closing && output.is_empty() {
+                socket.close();
+            }
+        });
+    }
+}
+
+/// A thread-safe wrapper for the remote shell sessions.
+pub struct RemoteShell(Mutex<Option<Vec<Session>>>);
+
+impl RemoteShell {
+    pub const fn uninitialized() -> RemoteShell {
+        RemoteShell(Mutex::new(None))
+    }
+
+    /// Creates `RSHELL_SESSIONS` sockets listening on `RSHELL_PORT`. The
+    /// ethernet driver must be initialized.
+    pub fn initialize(&self) {
+        if ETHERNET.mark_port(RSHELL_PORT).is_none() {
+            error!("RemoteShell: port {} is already in use", RSHELL_PORT);
+            return;
+        }
+        let mut sessions = Vec::with_capacity(RSHELL_SESSIONS);
+        for _ in 0..RSHELL_SESSIONS {
+            let handle = ETHERNET.add_socket();
+            ETHERNET.with_socket(handle, |socket| {
+                if let Err(e) = socket.listen(RSHELL_PORT) {
+                    error!("RemoteShell: listen() failed: {:?}", e);
+                }
+            });
+            sessions.push(Session::new(handle));
+        }
+        info!("RemoteShell: listening on port {}", RSHELL_PORT);
+        *self.0.lock() = Some(sessions);
+
+        match Process::kernel(run_commands, RSHELL_STACK_SIZE) {
+            Ok(process) => {
+                SCHEDULER.add(process);
+            }
+            Err(e) => error!("RemoteShell: failed to start the shell process: {:?}", e),
+        }
+    }
+
+    /// Serves all sessions. Must be called after polling the ethernet driver.
+    /// The command lines received are queued for the shell process.
+    pub fn poll(&self) {
+        if let Some(sessions) = self.0.lock().as_mut() {
+            for session in sessions.iter_mut() {
+                session.poll();
+            }
+        }
+    }
+
+    /// Takes the next queued command line. Returns the index of its session,
+    /// the line, and the working directory of the session.
+    fn next_command(&self) -> Option<(usize, String, PathBuf)> {
+        let mut sessions = self.0.lock();
+        for (index, session) in sessions.as_mut()?.iter_mut().enumerate() {
+            if let Job::Queued(_) = session.job {
+                let line = match mem::replace(&mut session.job, Job::Running) {
+                    Job::Queued(line) => line,
+                    _ => unreachable!(),
+                };
+                return Some((index, line, session.cwd.clone()));
+            }
+        }
+        None
+    }
+
+    /// Returns the outcome of the command line taken from session `index`.
+    fn complete(&self, index: usize, cwd: PathBuf, printed: &str, result: &str) {
+        if let Some(sessions) = self.0.lock().as_mut() {
+            sessions[index].complete(cwd, printed, result);
+        }
+    }
+}
+
+/// Sleeps for `ms` milliseconds with the `sleep` system call.
+fn sleep(ms: u64) {
+    unsafe {
+        asm!("mov x0, $0
+              svc $1"
+             :: "r"(ms), "i"(NR_SLEEP)
+             : "x0", "x7"
+             : "volatile");
+    }
+}
+
+/// Entry point of the shell process, a kernel process which runs the command
+/// lines of all sessions one at a time.
+///
+/// Commands take the file system and console locks, so they must not run in
+/// the interrupt handler that polls the network, where the interrupted code
+/// may hold those locks.
+extern "C" fn run_commands() -> ! {
+    loop {
+        let (index, line, mut cwd) = match RSHELL.next_command() {
+            Some(command) => command,
+            None => {
+                sleep(IDLE_SLEEP_MS);
+                continue;
+            }
+        };
+        let (result, printed) = capture_output(|| super::run(&line, &mut cwd));
+        let result = match result {
+            Ok(std_out) => std_out.result,
+            Err(std_err) => std_err.result,
+        };
+        RSHELL.complete(index, cwd, &printed, &result);
+        // Ticks are masked while commands run.
+        WATCHDOG.pet();
+    }
+}
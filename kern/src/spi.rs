@@ -0,0 +1,153 @@
+///! The SPI0 master, shared by user processes through interrupt-driven
+///! transfers
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use kernel_api::*;
+use pi::interrupt::Controller;
+use pi::spi::{self, ChipSelect, Mode, Progress, Spi};
+
+use crate::mutex::Mutex;
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::GLOABAL_IRQ;
+
+/// Longest transfer a process can ask for, in bytes.
+pub const MAX_TRANSFER_LEN: usize = 4096;
+
+/// A transfer driven by the SPI interrupt on behalf of a process.
+struct Transfer {
+    owner: u64,
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    progress: Progress,
+    done: bool,
+}
+
+struct Inner {
+    spi: Spi,
+    /// The transfer in progress, or finished but not collected by its owner
+    /// yet.
+    transfer: Option<Transfer>,
+}
+
+/// A thread-safe SPI0 master running one transfer at a time.
+///
+/// A process starts a transfer with `start()`, sleeps until `is_done()`, and
+/// collects the received bytes with `take()`. Other processes wait for the
+/// master to be idle again.
+pub struct SpiDevice(Mutex<Option<Inner>>);
+
+impl SpiDevice {
+    pub const fn uninitialized() -> SpiDevice {
+        SpiDevice(Mutex::new(None))
+    }
+
+    /// Sets up the master and routes its interrupt to `handle_irq()`.
+    pub fn initialize(&self) {
+        *self.0.lock() = Some(Inner {
+            spi: Spi::new(),
+            transfer: None,
+        });
+        GLOABAL_IRQ.register(spi::INTERRUPT, Box::new(|_tf| crate::SPI.handle_irq()));
+        Controller::new().enable(spi::INTERRUPT);
+    }
+
+    fn critical<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Inner) -> R,
+    {
+        let mut inner = self.0.lock();
+        f(inner.as_mut().expect("Uninitialized SpiDevice"))
+    }
+
+    /// Starts sending `tx` for `pid` with the `SPI_*` settings `flags` and a
+    /// clock of at most `hz`. Returns `false` if another transfer holds the
+    /// master.
+    pub fn start(&self, pid: u64, flags: u64, hz: u64, tx: Vec<u8>) -> OsResult<bool> {
+        if flags & !(SPI_MODE_MASK | SPI_CE_MASK | SPI_CS_HIGH) != 0 || hz == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let mode = match flags & SPI_MODE_MASK {
+            SPI_MODE_0 => Mode::Mode0,
+            SPI_MODE_1 => Mode::Mode1,
+            SPI_MODE_2 => Mode::Mode2,
+            _ => Mode::Mode3,
+        };
+        let chip_select = match flags & SPI_CE_MASK {
+            SPI_CE0 => ChipSelect::Ce0,
+            SPI_CE1 => ChipSelect::Ce1,
+            SPI_NO_CE => ChipSelect::None,
+            _ => return Err(OsError::InvalidArgument),
+        };
+        if tx.is_empty() || tx.len() > MAX_TRANSFER_LEN {
+            return Err(OsError::InvalidArgument);
+        }
+        self.critical(|inner| {
+            if inner.transfer.is_some() {
+                return Ok(false);
+            }
+            inner.spi.set_mode(mode);
+            inner.spi.set_chip_select(chip_select, flags & SPI_CS_HIGH != 0);
+            inner.spi.set_clock(hz.min(core::u32::MAX as u64) as u32);
+            inner.transfer = Some(Transfer {
+                owner: pid,
+                rx: vec![0; tx.len()],
+                tx,
+                progress: Progress::default(),
+                done: false,
+            });
+            inner.spi.start_transfer(true);
+            Ok(true)
+        })
+    }
+
+    /// Returns `true` if no transfer holds the master.
+    pub fn is_idle(&self) -> bool {
+        self.critical(|inner| inner.transfer.is_none())
+    }
+
+    /// Returns `true` if the transfer of `pid` is finished.
+    pub fn is_done(&self, pid: u64) -> bool {
+        self.critical(|inner| match inner.transfer {
+            Some(ref transfer) => transfer.owner == pid && transfer.done,
+            None => false,
+        })
+    }
+
+    /// Returns the bytes received by the finished transfer of `pid` and
+    /// frees the master.
+    pub fn take(&self, pid: u64) -> Option<Vec<u8>> {
+        self.critical(|inner| {
+            if !inner.transfer.as_ref().map_or(false, |t| t.owner == pid && t.done) {
+                return None;
+            }
+            inner.transfer.take().map(|transfer| transfer.rx)
+        })
+    }
+
+    /// Aborts or discards the transfer of `pid`, if any.
+    pub fn release(&self, pid: u64) {
+        let mut inner = self.0.lock();
+        if let Some(inner) = inner.as_mut() {
+            if inner.transfer.as_ref().map_or(false, |t| t.owner == pid) {
+                inner.spi.end_transfer();
+                inner.transfer = None;
+            }
+        }
+    }
+
+    /// Moves the data of the transfer in progress between its buffers and the
+    /// FIFOs.
+    fn handle_irq(&self) {
+        self.critical(|inner| {
+            let Inner { spi, transfer } = inner;
+            match transfer {
+                Some(ref mut t) if !t.done => {
+                    t.done = spi.poll_transfer(&t.tx, &mut t.rx, &mut t.progress);
+                }
+                _ => spi.end_transfer(),
+            }
+        })
+    }
+}
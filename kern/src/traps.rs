@@ -11,9 +11,10 @@ use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
 use crate::console::kprintln;
-use crate::GLOABAL_IRQ;
+use crate::param::NCORES;
+use crate::{GLOABAL_IRQ, SCHEDULER};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore;
 use crate::traps::irq::IrqHandlerRegistry;
@@ -43,6 +44,48 @@ pub struct Info {
     kind: Kind,
 }
 
+/// Size of the stack a core switches to to report a kernel stack overflow.
+/// `vectors.s` relies on it being 8 KiB.
+const OVERFLOW_STACK_SIZE: usize = 8 * 1024;
+
+#[repr(align(16))]
+struct OverflowStacks([[u8; OVERFLOW_STACK_SIZE]; NCORES]);
+
+/// The overflow stack of each core, used by `stack_overflow` in `vectors.s`.
+#[no_mangle]
+static mut OVERFLOW_STACKS: OverflowStacks =
+    OverflowStacks([[0; OVERFLOW_STACK_SIZE]; NCORES]);
+
+/// Called on the core's overflow stack when a synchronous exception is taken
+/// while the kernel stack pointer, `sp`, is in or near a stack guard page.
+#[no_mangle]
+pub extern "C" fn kernel_stack_overflow(sp: u64) -> ! {
+    let (elr, far) = unsafe { (aarch64::ELR_EL1.get(), aarch64::FAR_EL1.get()) };
+    panic!(
+        "kernel stack overflow on core {}: sp={:#x}, elr={:#x}, far={:#x}",
+        aarch64::affinity(),
+        sp,
+        elr,
+        far
+    );
+}
+
+/// Handles an abort taken from user space. A translation fault on a page of a
+/// mapping made with `mmap` maps the page and returns to the faulting
+/// instruction; any other abort kills the process.
+fn handle_user_abort(kind: Fault, syndrome: Syndrome, tf: &mut TrapFrame) {
+    let far = unsafe { aarch64::FAR_EL1.get() } as usize;
+    if kind == Fault::Translation && SCHEDULER.fault(tf, far, false).is_ok() {
+        return;
+    }
+    info!(
+        "pid {} killed by {:?} at {:#x}, elr={:#x}",
+        tf.tpidr, syndrome, far, tf.elr
+    );
+    let _pid_option = SCHEDULER.kill(tf);
+    SCHEDULER.switch_to(tf);
+}
+
 /// This function is called when an exception occurs. The `info` parameter
 /// specifies the source and kind of exception that has occurred. The `esr` is
 /// the value of the exception syndrome register. Finally, `tf` is a pointer to
@@ -72,6 +115,11 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                     handle_syscall(num, tf);
                     aarch64::disable_fiq_interrupt();
                 },
+                Syndrome::DataAbort { kind, .. } | Syndrome::InstructionAbort { kind, .. }
+                    if info.source == Source::LowerAArch64 =>
+                {
+                    handle_user_abort(kind, syndrome, tf);
+                }
                 _ => (),
             }
         }
@@ -33,6 +33,8 @@ impl GlobalIrq {
             Mutex::new(None),
             Mutex::new(None),
             Mutex::new(None),
+            Mutex::new(None),
+            Mutex::new(None),
         ])
     }
 }
@@ -76,6 +78,8 @@ impl Index<Interrupt> for GlobalIrq {
             Gpio2 => 5,
             Gpio3 => 6,
             Uart => 7,
+            Aux => 8,
+            Spi => 9,
         };
         &self.0[index]
     }
@@ -1,17 +1,25 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
 use core::time::Duration;
-use core::ops::Add;
+use core::ops::{Add, Sub};
 
-use smoltcp::wire::{IpAddress, IpEndpoint};
+use fat32::traits::{Entry, FileSystem};
+use shim::path::Path;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
 
 use crate::console::{kprint, CONSOLE};
-use crate::param::USER_IMG_BASE;
-use crate::process::State;
+use crate::gpio::USER_GPIO;
+use crate::net::{icmp, recv_would_block, send_would_block};
+use crate::param::USER_VA_BASE;
+use crate::process::{Backing, Process, State};
 use crate::traps::TrapFrame;
-use crate::{ETHERNET, SCHEDULER};
+use crate::{ETHERNET, FILESYSTEM, I2C, SCHEDULER, SPI};
 use crate::vm::{VirtualAddr, Page, PagePerm};
+use crate::watchdog;
 
 use kernel_api::*;
+use pi::gpio::Gpio;
 use pi::timer;
 
 /// Sleep for `ms` milliseconds.
@@ -86,10 +94,39 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.x[7] = OsError::Ok as u64;
 }
 
-pub fn sys_sbrk(size: usize, tf: &mut TrapFrame)  {
+/// Moves the current process's program break by `increment` bytes.
+///
+/// A positive `increment` grows the heap, mapping pages up to the new break.
+/// A negative one shrinks it, unmapping the pages wholly above the new break
+/// and returning their frames. The break never moves below the heap's base;
+/// trying to returns `InvalidArgument`.
+///
+/// In addition to the usual status value, this system call returns a
+/// parameter: the new program break.
+pub fn sys_sbrk(increment: isize, tf: &mut TrapFrame)  {
     SCHEDULER.critical(|scheduler| {
         let mut process = scheduler.find_process(tf);
-        let next_heap_ptr = process.heap_ptr.add(VirtualAddr::from(size));
+        if increment < 0 {
+            let decrement = increment.wrapping_neg() as usize;
+            let heap_size = process.heap_ptr.as_usize() - process.heap_base.as_usize();
+            if decrement > heap_size {
+                tf.x[7] = OsError::InvalidArgument as u64;
+                return;
+            }
+            let next_heap_ptr = process.heap_ptr.sub(VirtualAddr::from(decrement));
+            while process.heap_page.as_usize() > process.heap_base.as_usize()
+                && process.heap_page.as_usize() >= next_heap_ptr.as_usize()
+            {
+                let heap_page = process.heap_page;
+                process.vmap.dealloc(heap_page);
+                process.heap_page = heap_page.sub(VirtualAddr::from(Page::SIZE));
+            }
+            process.heap_ptr = next_heap_ptr;
+            tf.x[0] = process.heap_ptr.as_u64();
+            tf.x[7] = OsError::Ok as u64;
+            return;
+        }
+        let next_heap_ptr = process.heap_ptr.add(VirtualAddr::from(increment as usize));
         while process.heap_page.add(VirtualAddr::from(Page::SIZE)).as_usize() < next_heap_ptr.as_usize() {
             let next_heap_page = process.heap_page.add(VirtualAddr::from(Page::SIZE));
             if next_heap_page.as_usize() >= process.stack_base.as_usize() {
@@ -105,6 +142,94 @@ pub fn sys_sbrk(size: usize, tf: &mut TrapFrame)  {
     });
 }
 
+/// Maps memory into the address space of the calling process.
+///
+/// This system call takes the address hint, the length in bytes, the
+/// `PROT_*` protection flags, the `MAP_*` flags, the file descriptor and the
+/// offset in the file as parameters, in this order. `MAP_PRIVATE` is
+/// required. With `MAP_ANONYMOUS` the pages are zero-filled; otherwise they
+/// are read from the file, which can only be mapped without `PROT_WRITE` at
+/// an offset that is a multiple of the page size. No page is mapped until the
+/// process touches it: the page fault handler fills it then.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The length is 0, the flags or the protection are invalid, the
+///   offset is not aligned, or the descriptor is not an open file.
+/// - `OsError::NoAccess`: A file mapping is requested with `PROT_WRITE`.
+/// - `OsError::NoVmSpace`: No free range of the address space fits the mapping.
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: u64,
+    flags: u64,
+    fd: usize,
+    offset: usize,
+    tf: &mut TrapFrame,
+) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        if prot == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        if flags & MAP_PRIVATE == 0 || flags & !(MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let perm = match (prot & PROT_WRITE != 0, prot & PROT_EXEC != 0) {
+            (false, false) => PagePerm::RO,
+            (false, true) => PagePerm::RX,
+            (true, false) => PagePerm::RW,
+            (true, true) => PagePerm::RWX,
+        };
+        let backing = if flags & MAP_ANONYMOUS != 0 {
+            Backing::Anonymous
+        } else {
+            if prot & PROT_WRITE != 0 {
+                return Err(OsError::NoAccess);
+            }
+            if offset % PAGE_SIZE != 0 {
+                return Err(OsError::InvalidArgument);
+            }
+            let file = process.file(fd).ok_or(OsError::InvalidArgument)?;
+            Backing::File { file: file.clone(), offset }
+        };
+        process.map(addr, len, perm, backing, flags & MAP_FIXED != 0)
+    });
+    match result {
+        Ok(addr) => {
+            tf.x[0] = addr as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Unmaps memory mapped with `sys_mmap`.
+///
+/// This system call takes the page aligned address as the first parameter
+/// and the length in bytes as the second parameter. The pages of the range
+/// that were touched are given back; the parts of mappings outside of the
+/// range stay mapped.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The address is not aligned, the length is 0, or the range is
+///   outside of the area mappings are placed in.
+pub fn sys_munmap(addr: usize, len: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).unmap(addr, len));
+    tf.x[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
 pub fn sys_rand(min: u32, max: u32, tf: &mut TrapFrame) {
     let rand = {
         let mut rng = crate::rng::RNG.lock();
@@ -281,28 +406,33 @@ pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
     });
 }
 
-/// Returns a slice from a virtual address and a legnth.
+/// Returns a slice from a virtual address and a legnth, faulting in the pages
+/// of the `mmap` mappings of the calling process it covers.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
+/// in mapped userspace.
+unsafe fn to_user_slice<'a>(va: usize, len: usize, tf: &TrapFrame) -> OsResult<&'a [u8]> {
     let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
+    if va >= USER_VA_BASE && !overflow {
+        SCHEDULER.fault_in(tf, va, len, false)?;
         Ok(core::slice::from_raw_parts(va as *const u8, len))
     } else {
         Err(OsError::BadAddress)
     }
 }
 
-/// Returns a mutable slice from a virtual address and a legnth.
+/// Returns a mutable slice from a virtual address and a legnth, faulting in the
+/// pages of the `mmap` mappings of the calling process it covers.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
+/// in mapped userspace, and `Err(OsError::NoAccess)` if it covers a read-only
+/// mapping.
+unsafe fn to_user_slice_mut<'a>(va: usize, len: usize, tf: &TrapFrame) -> OsResult<&'a mut [u8]> {
     let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
+    if va >= USER_VA_BASE && !overflow {
+        SCHEDULER.fault_in(tf, va, len, true)?;
         Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
     } else {
         Err(OsError::BadAddress)
@@ -315,6 +445,9 @@ unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]>
 /// address of the buffer as the second parameter, and the length of the buffer
 /// as the third parameter.
 ///
+/// If the descriptor is in blocking mode and the socket cannot accept data
+/// yet, the process sleeps until it can.
+///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the number of bytes sent.
 ///
@@ -326,29 +459,41 @@ unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]>
 /// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    match unsafe { to_user_slice(va, len) } {
-        Ok(data) => {
-            SCHEDULER.critical(|scheduler|{
-                let mut process = scheduler.find_process(tf);
-                match process.sockets.get(sock_idx - 3) {
-                    Some(handle) => {
-                        ETHERNET.with_socket(*handle, |socket| {
-                            match socket.send_slice(data) {
-                                Ok(bytes) => {
-                                    tf.x[0] = bytes as u64;
-                                    tf.x[7] = OsError::Ok as u64;
-                                }
-                                Err(smoltcp::Error::Illegal) => tf.x[7] = OsError::IllegalSocketOperation as u64,
-                                Err(_) => tf.x[7] = OsError::Unknown as u64,
-                            }
-                        });
-
+    let data = match unsafe { to_user_slice(va, len, tf) } {
+        Ok(data) => data,
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    };
+    let blocked_on = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        let blocking = process.is_blocking(sock_idx);
+        match process.socket(sock_idx) {
+            Some(handle) => ETHERNET.with_socket(handle, |socket| {
+                if blocking && send_would_block(socket) {
+                    return Some(handle);
+                }
+                match socket.send_slice(data) {
+                    Ok(bytes) => {
+                        tf.x[0] = bytes as u64;
+                        tf.x[7] = OsError::Ok as u64;
                     }
-                    None => tf.x[7] = OsError::InvalidSocket as u64,
-                };
-            });
+                    Err(smoltcp::Error::Illegal) => tf.x[7] = OsError::IllegalSocketOperation as u64,
+                    Err(_) => tf.x[7] = OsError::Unknown as u64,
+                }
+                None
+            }),
+            None => {
+                tf.x[7] = OsError::InvalidSocket as u64;
+                None
+            }
         }
-        Err(e) => tf.x[7] = e as u64,
+    });
+    if let Some(handle) = blocked_on {
+        block_and_restart(tf, move |_| {
+            !ETHERNET.with_socket(handle, |socket| send_would_block(socket))
+        });
     }
 }
 
@@ -358,6 +503,9 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// address of the buffer as the second parameter, and the length of the buffer
 /// as the third parameter.
 ///
+/// If the descriptor is in blocking mode and no data has arrived yet, the
+/// process sleeps until data arrives or the connection is closed.
+///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the number of bytes read.
 ///
@@ -369,28 +517,690 @@ pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    match unsafe { to_user_slice_mut(va, len) } {
-        Ok(data) => {
-            SCHEDULER.critical(|scheduler|{
-                let mut process = scheduler.find_process(tf);
-                match process.sockets.get(sock_idx - 3) {
-                    Some(handle) => {
-                        ETHERNET.with_socket(*handle, |socket| {
-                            match socket.recv_slice(data) {
-                                Ok(bytes) => {
-                                    tf.x[0] = bytes as u64;
-                                    tf.x[7] = OsError::Ok as u64;
-                                }
-                                Err(smoltcp::Error::Illegal) => tf.x[7] = OsError::IllegalSocketOperation as u64,
-                                Err(_) => tf.x[7] = OsError::Unknown as u64,
-                            }
-                        });
-
+    let data = match unsafe { to_user_slice_mut(va, len, tf) } {
+        Ok(data) => data,
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    };
+    let blocked_on = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        let blocking = process.is_blocking(sock_idx);
+        match process.socket(sock_idx) {
+            Some(handle) => ETHERNET.with_socket(handle, |socket| {
+                if blocking && recv_would_block(socket) {
+                    return Some(handle);
+                }
+                match socket.recv_slice(data) {
+                    Ok(bytes) => {
+                        tf.x[0] = bytes as u64;
+                        tf.x[7] = OsError::Ok as u64;
                     }
-                    None => tf.x[7] = OsError::InvalidSocket as u64,
-                };
-            });
+                    Err(smoltcp::Error::Illegal) => tf.x[7] = OsError::IllegalSocketOperation as u64,
+                    Err(_) => tf.x[7] = OsError::Unknown as u64,
+                }
+                None
+            }),
+            None => {
+                tf.x[7] = OsError::InvalidSocket as u64;
+                None
+            }
+        }
+    });
+    if let Some(handle) = blocked_on {
+        block_and_restart(tf, move |_| {
+            !ETHERNET.with_socket(handle, |socket| recv_would_block(socket))
+        });
+    }
+}
+
+/// Puts the current process to sleep until `ready` returns `true`, then
+/// re-issues the system call that trapped into `tf`.
+///
+/// Event poll functions run in whatever address space happens to be active,
+/// so they must not touch user memory. Restarting the call lets it complete
+/// from the woken process's own address space instead. The arguments are
+/// still in the saved registers since the call has not written any result.
+fn block_and_restart<F>(tf: &mut TrapFrame, mut ready: F)
+where
+    F: FnMut(&mut Process) -> bool + Send + 'static,
+{
+    SCHEDULER.switch(State::Waiting(Box::new(move |p| {
+        if ready(p) {
+            p.context.elr -= 4;
+            true
+        } else {
+            false
+        }
+    })), tf);
+}
+
+/// Returns the subset of `events` (plus error conditions) that are ready on
+/// descriptor `fd` of `process`.
+fn poll_ready(process: &Process, fd: usize, events: u16) -> u16 {
+    match fd {
+        0 => {
+            let mut console = CONSOLE.lock();
+            if events & POLLIN != 0
+                && console.may_read(process.context.tpidr)
+                && console.input_ready()
+            {
+                POLLIN
+            } else {
+                0
+            }
+        }
+        1 | 2 => events & POLLOUT,
+        fd if process.file(fd).is_some() => events & (POLLIN | POLLOUT),
+        fd => match process.socket(fd) {
+            Some(handle) => ETHERNET.with_socket(handle, |socket| {
+                let mut revents = 0;
+                if events & POLLIN != 0 && !recv_would_block(socket) {
+                    revents |= POLLIN;
+                }
+                if events & POLLOUT != 0 && !send_would_block(socket) {
+                    revents |= POLLOUT;
+                }
+                if !socket.is_open() {
+                    revents |= POLLHUP;
+                }
+                revents
+            }),
+            None => POLLNVAL,
+        },
+    }
+}
+
+/// Waits for one of a set of descriptors to become ready.
+///
+/// This system call takes the address of an array of `PollFd` as the first
+/// parameter, the number of entries in the array as the second parameter, and
+/// the timeout in milliseconds as the third parameter. A timeout of
+/// `u64::MAX` waits forever and a timeout of 0 never sleeps.
+///
+/// Descriptors 0 (readable when the console has input), 1 and 2 (always
+/// writable) are the console. Files opened with `sys_open` are always ready;
+/// the remaining descriptors are sockets.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of entries with a non-zero `revents`.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The array is not a valid, aligned userspace slice.
+pub fn sys_poll(va: usize, nfds: usize, timeout_ms: u64, tf: &mut TrapFrame) {
+    let len = match nfds.checked_mul(mem::size_of::<PollFd>()) {
+        Some(len) => len,
+        None => {
+            tf.x[7] = OsError::BadAddress as u64;
+            return;
+        }
+    };
+    if va % mem::align_of::<PollFd>() != 0 {
+        tf.x[7] = OsError::BadAddress as u64;
+        return;
+    }
+    let fds = match unsafe { to_user_slice_mut(va, len, tf) } {
+        Ok(slice) => unsafe {
+            core::slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut PollFd, nfds)
+        },
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    };
+
+    let ready = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        let mut ready = 0;
+        for pollfd in fds.iter_mut() {
+            pollfd.revents = poll_ready(process, pollfd.fd as usize, pollfd.events);
+            if pollfd.revents != 0 {
+                ready += 1;
+            }
+        }
+        ready
+    });
+    if ready > 0 || timeout_ms == 0 {
+        tf.x[0] = ready as u64;
+        tf.x[7] = OsError::Ok as u64;
+        return;
+    }
+
+    let watched: Vec<(usize, u16)> = fds
+        .iter()
+        .map(|pollfd| (pollfd.fd as usize, pollfd.events))
+        .collect();
+    let deadline = match timeout_ms {
+        core::u64::MAX => None,
+        ms => Some(timer::current_time() + Duration::from_millis(ms)),
+    };
+    SCHEDULER.switch(State::Waiting(Box::new(move |p| {
+        if watched.iter().any(|&(fd, events)| poll_ready(p, fd, events) != 0) {
+            // Let the restarted call fill in `revents` without sleeping again.
+            p.context.x[2] = 0;
+            p.context.elr -= 4;
+            true
+        } else if deadline.map_or(false, |deadline| timer::current_time() >= deadline) {
+            p.context.x[0] = 0;
+            p.context.x[7] = OsError::Ok as u64;
+            true
+        } else {
+            false
+        }
+    })), tf);
+}
+
+/// Switches a descriptor between blocking and non-blocking mode.
+///
+/// This system call takes a descriptor as the first parameter and `1` to make
+/// it non-blocking (or `0` to make it blocking) as the second parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// open.
+pub fn sys_set_nonblocking(fd: usize, nonblocking: bool, tf: &mut TrapFrame) {
+    SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        if fd > 2 && process.socket(fd).is_none() && process.file(fd).is_none() {
+            tf.x[7] = OsError::InvalidArgument as u64;
+            return;
+        }
+        if nonblocking {
+            process.nonblocking.insert(fd);
+        } else {
+            process.nonblocking.remove(&fd);
+        }
+        tf.x[7] = OsError::Ok as u64;
+    });
+}
+
+/// Sends an ICMP echo request and waits for the matching echo reply.
+///
+/// This system call takes the IPv4 address of the remote host in big endian
+/// as the first parameter, the sequence number of the request as the second
+/// parameter, and the timeout in milliseconds as the third parameter. The
+/// process ID is used as the ICMP identifier.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters:
+///  - the round trip time in microseconds
+///  - the TTL of the echo reply
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::IoError`: The echo request could not be queued.
+/// - `OsError::IoErrorTimedOut`: No matching echo reply arrived before the timeout.
+pub fn sys_ping(ip: u32, seq_no: u16, timeout_ms: u32, tf: &mut TrapFrame) {
+    let dst_addr = Ipv4Address::from_bytes(&ip.to_be_bytes());
+    let ident = tf.tpidr as u16;
+    let handle = ETHERNET.add_icmp_socket();
+    let sent = ETHERNET.with_raw_socket(handle, |socket| {
+        icmp::send_echo_request(socket, dst_addr, ident, seq_no)
+    });
+    let release = move || ETHERNET.critical(|ethernet| {
+        ethernet.release(handle);
+        ethernet.prune();
+    });
+    if let Err(e) = sent {
+        debug!("sys_ping() failed to queue echo request: {:?}", e);
+        release();
+        tf.x[7] = OsError::IoError as u64;
+        return;
+    }
+
+    // The process owns the socket while it waits, so killing it releases the
+    // socket too.
+    SCHEDULER.critical(|scheduler| scheduler.find_process(tf).raw_sockets.push(handle));
+
+    let start_time = timer::current_time();
+    let end_time = start_time + Duration::from_millis(timeout_ms as u64);
+    SCHEDULER.switch(State::Waiting(Box::new(move |p| {
+        let current_time = timer::current_time();
+        let reply = ETHERNET.with_raw_socket(handle, |socket| {
+            icmp::recv_echo_reply(socket, ident, seq_no)
+        });
+        match reply {
+            Some(reply) => {
+                p.context.x[0] = (current_time - start_time).as_micros() as u64;
+                p.context.x[1] = reply.ttl as u64;
+                p.context.x[7] = OsError::Ok as u64;
+            }
+            None if current_time >= end_time => {
+                p.context.x[7] = OsError::IoErrorTimedOut as u64;
+            }
+            None => return false,
         }
+        p.raw_sockets.retain(|raw_socket| *raw_socket != handle);
+        release();
+        true
+    })), tf);
+}
+
+/// Reads input from the console.
+///
+/// This system call takes a descriptor as the first parameter, the address of
+/// the buffer as the second parameter, and the length of the buffer as the
+/// third parameter. Only the console input (descriptor 0) can be read.
+///
+/// The calling process becomes the owner of the console input if nobody owns
+/// it. In canonical mode (the default) the input is edited a line at a time
+/// and at most one line, including its newline, is returned. In raw mode the
+/// bytes are returned as they arrive. See `sys_console_mode`.
+///
+/// If the descriptor is in blocking mode, the process sleeps until input is
+/// ready and, if another process owns the console input, until it is released.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read. 0 bytes are returned on end-of-file
+/// (`Ctrl-D` on an empty line).
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The descriptor is not the console input.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::NoAccess`: The descriptor is non-blocking and another process owns the console input.
+/// - `OsError::IoErrorWouldBlock`: The descriptor is non-blocking and no input is ready.
+pub fn sys_read(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    if fd != 0 {
+        tf.x[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let data = match unsafe { to_user_slice_mut(va, len, tf) } {
+        Ok(data) => data,
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    };
+    let pid = tf.tpidr;
+    let blocking = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).is_blocking(fd));
+    let ready = {
+        let mut console = CONSOLE.lock();
+        console.may_read(pid) && (data.is_empty() || console.input_ready())
+    };
+    if blocking && !ready {
+        block_and_restart(tf, move |_| {
+            let mut console = CONSOLE.lock();
+            console.may_read(pid) && console.input_ready()
+        });
+        return;
+    }
+
+    let mut console = CONSOLE.lock();
+    if !console.claim(pid) {
+        tf.x[7] = OsError::NoAccess as u64;
+        return;
+    }
+    match console.read_input(data) {
+        Some(bytes) => {
+            tf.x[0] = bytes as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        None => tf.x[7] = OsError::IoErrorWouldBlock as u64,
+    }
+}
+
+/// Opens a file for reading.
+///
+/// This system call takes the address of the absolute path as the first
+/// parameter and the length of the path as the second parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor of the file, which `sys_mmap` can map.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or is not a file.
+/// - `OsError::NoEntry`: Nothing exists at the path.
+/// - `OsError::IoError`: The file system could not be read.
+pub fn sys_open(va: usize, len: usize, tf: &mut TrapFrame) {
+    let file = unsafe { to_user_slice(va, len, tf) }
+        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))
+        .and_then(|path| {
+            let entry = FILESYSTEM.open(Path::new(path))?;
+            entry.into_file().ok_or(OsError::InvalidArgument)
+        });
+
+    match file {
+        Ok(file) => {
+            let fd = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).add_file(file));
+            tf.x[0] = fd as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Closes a file opened with `sys_open`. The mappings of the file stay valid.
+///
+/// This system call takes the file descriptor as the only parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::InvalidArgument` if the descriptor is not
+/// an open file.
+pub fn sys_close(fd: usize, tf: &mut TrapFrame) {
+    let closed = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_process(tf);
+        // The descriptor may be reused by the next file, which starts blocking.
+        process.nonblocking.remove(&fd);
+        process.close_file(fd)
+    });
+    tf.x[7] = if closed {
+        OsError::Ok
+    } else {
+        OsError::InvalidArgument
+    } as u64;
+}
+
+/// Sets the mode of the console input.
+///
+/// This system call takes the mode flags as the only parameter:
+/// `CONSOLE_CANONICAL` selects line editing instead of raw input and
+/// `CONSOLE_ECHO` echoes the input back to the console. The calling process
+/// becomes the owner of the console input if nobody owns it.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: Unknown flags are set.
+/// - `OsError::NoAccess`: Another process owns the console input.
+pub fn sys_console_mode(flags: u64, tf: &mut TrapFrame) {
+    if flags & !(CONSOLE_CANONICAL | CONSOLE_ECHO) != 0 {
+        tf.x[7] = OsError::InvalidArgument as u64;
+        return;
+    }
+    let mut console = CONSOLE.lock();
+    if !console.claim(tf.tpidr) {
+        tf.x[7] = OsError::NoAccess as u64;
+        return;
+    }
+    console.set_mode(flags & CONSOLE_CANONICAL != 0, flags & CONSOLE_ECHO != 0);
+    tf.x[7] = OsError::Ok as u64;
+}
+
+/// Gives up the console input so that another process can read it. The input
+/// mode is reset to canonical with echo. Does nothing if the calling process
+/// does not own the console input.
+///
+/// This system call does not take parameter.
+pub fn sys_console_release(tf: &mut TrapFrame) {
+    CONSOLE.lock().release(tf.tpidr);
+    tf.x[7] = OsError::Ok as u64;
+}
+
+/// Resets the board.
+///
+/// This system call does not take parameter. Only the owner of the console
+/// input, the process the user interacts with, may reset the board.
+///
+/// It does not return unless it fails with the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::NoAccess` if the calling process does not
+/// own the console input.
+pub fn sys_reboot(tf: &mut TrapFrame) {
+    if !CONSOLE.lock().is_owner(tf.tpidr) {
+        tf.x[7] = OsError::NoAccess as u64;
+        return;
+    }
+    watchdog::reboot()
+}
+
+/// Stops the board until its power is cycled.
+///
+/// This system call does not take parameter. Only the owner of the console
+/// input may stop the board.
+///
+/// It does not return unless it fails with the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::NoAccess` if the calling process does not
+/// own the console input.
+pub fn sys_halt(tf: &mut TrapFrame) {
+    if !CONSOLE.lock().is_owner(tf.tpidr) {
+        tf.x[7] = OsError::NoAccess as u64;
+        return;
+    }
+    watchdog::halt()
+}
+
+/// Claims a GPIO pin for the calling process.
+///
+/// This system call takes the pin number as the only parameter. The pin is
+/// switched to an input. It stays with the process until `sys_gpio_release`
+/// is called or the process exits.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin does not exist or is used by the kernel.
+/// - `OsError::NoAccess`: Another process owns the pin.
+pub fn sys_gpio_claim(pin: u64, tf: &mut TrapFrame) {
+    tf.x[7] = match USER_GPIO.claim(tf.tpidr, pin) {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Releases a GPIO pin owned by the calling process. The pin is reset to an
+/// input without pull-up or pull-down.
+///
+/// This system call takes the pin number as the only parameter.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin does not exist or is used by the kernel.
+/// - `OsError::NoAccess`: The calling process does not own the pin.
+pub fn sys_gpio_release(pin: u64, tf: &mut TrapFrame) {
+    tf.x[7] = match USER_GPIO.release(tf.tpidr, pin) {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Configures a GPIO pin owned by the calling process.
+///
+/// This system call takes the pin number as the first parameter, the function
+/// (`GPIO_INPUT`, `GPIO_OUTPUT` or `GPIO_ALTn`) as the second parameter and the
+/// pull-up/pull-down setting (`GPIO_PULL_*`) as the third parameter. Edge
+/// detection set up by `sys_gpio_wait` is turned off.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin, function or pull setting is invalid.
+/// - `OsError::NoAccess`: The calling process does not own the pin.
+pub fn sys_gpio_configure(pin: u64, function: u64, pull: u64, tf: &mut TrapFrame) {
+    tf.x[7] = match USER_GPIO.configure(tf.tpidr, pin, function, pull) {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Sets or clears an output GPIO pin owned by the calling process.
+///
+/// This system call takes the pin number as the first parameter and `1` to
+/// drive the pin high (or `0` to drive it low) as the second parameter.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin is invalid or not an output.
+/// - `OsError::NoAccess`: The calling process does not own the pin.
+pub fn sys_gpio_write(pin: u64, level: bool, tf: &mut TrapFrame) {
+    tf.x[7] = match USER_GPIO.write(tf.tpidr, pin, level) {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Reads a GPIO pin owned by the calling process.
+///
+/// This system call takes the pin number as the only parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: `1` if the pin is high and `0` if it is low.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin is invalid.
+/// - `OsError::NoAccess`: The calling process does not own the pin.
+pub fn sys_gpio_read(pin: u64, tf: &mut TrapFrame) {
+    match USER_GPIO.read(tf.tpidr, pin) {
+        Ok(level) => {
+            tf.x[0] = level as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Waits for an edge on an input GPIO pin owned by the calling process.
+///
+/// This system call takes the pin number as the first parameter, the edges to
+/// wait for (`GPIO_RISING` and/or `GPIO_FALLING`) as the second parameter and
+/// the timeout in milliseconds (`u64::MAX` to wait forever) as the third
+/// parameter. Detection of the edges stays enabled after the call, so edges
+/// occurring between two calls are not missed.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the level of the pin after the edge.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidArgument`: The pin is invalid or not an input, or the edges are invalid.
+/// - `OsError::NoAccess`: The calling process does not own the pin.
+/// - `OsError::IoErrorTimedOut`: No edge was detected before the timeout.
+pub fn sys_gpio_wait(pin: u64, edges: u64, timeout_ms: u64, tf: &mut TrapFrame) {
+    match USER_GPIO.watch(tf.tpidr, pin, edges) {
+        Ok(true) => {
+            tf.x[0] = Gpio::new(pin as u8).level() as u64;
+            tf.x[7] = OsError::Ok as u64;
+            return;
+        }
+        Ok(false) => (),
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    }
+
+    let deadline = match timeout_ms {
+        core::u64::MAX => None,
+        ms => Some(timer::current_time() + Duration::from_millis(ms)),
+    };
+    SCHEDULER.switch(State::Waiting(Box::new(move |p| {
+        if USER_GPIO.take_event(pin) {
+            p.context.x[0] = Gpio::new(pin as u8).level() as u64;
+            p.context.x[7] = OsError::Ok as u64;
+            true
+        } else if deadline.map_or(false, |deadline| timer::current_time() >= deadline) {
+            p.context.x[7] = OsError::IoErrorTimedOut as u64;
+            true
+        } else {
+            false
+        }
+    })), tf);
+}
+
+/// Transfers data to and from a device on the kernel's I2C bus.
+///
+/// This system call takes the 7-bit address of the device as the first
+/// parameter, the address and the length of the buffer to write as the second
+/// and third parameters, and the address and the length of the buffer to read
+/// into as the fourth and fifth parameters. The write is done first, and the
+/// read follows after a repeated start. One of the buffers may be empty; a
+/// write followed by a read can write at most 16 bytes.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: A buffer is not a valid userspace slice.
+/// - `OsError::InvalidArgument`: The address is invalid, or the buffers are both empty or too long.
+/// - `OsError::NoEntry`: The device did not acknowledge its address or the data.
+/// - `OsError::IoErrorTimedOut`: The transfer did not complete in time.
+pub fn sys_i2c_transfer(
+    addr: u64,
+    write_va: usize,
+    write_len: usize,
+    read_va: usize,
+    read_len: usize,
+    tf: &mut TrapFrame,
+) {
+    // Empty buffers are not checked, so user space may pass any pointer.
+    let data = match write_len {
+        0 => Ok(&[][..]),
+        len => unsafe { to_user_slice(write_va, len, tf) },
+    };
+    let buf = match read_len {
+        0 => Ok(&mut [][..]),
+        len => unsafe { to_user_slice_mut(read_va, len, tf) },
+    };
+    let result = data.and_then(|data| buf.and_then(|buf| I2C.transfer(addr, data, buf)));
+    tf.x[7] = match result {
+        Ok(()) => OsError::Ok,
+        Err(e) => e,
+    } as u64;
+}
+
+/// Exchanges data with a device on the SPI bus.
+///
+/// This system call takes the `SPI_*` flags selecting the mode and the chip
+/// select line as the first parameter, the highest clock frequency in Hz as
+/// the second parameter, the addresses of the buffer to send and of the
+/// buffer to receive into as the third and fourth parameters, and the length
+/// of both buffers as the fifth parameter. The calling process sleeps while
+/// the transfer runs, and while the transfer of another process does.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: A buffer is not a valid userspace slice.
+/// - `OsError::InvalidArgument`: The flags or the clock are invalid, or the length is 0 or too big.
+pub fn sys_spi_transfer(
+    flags: u64,
+    hz: u64,
+    tx_va: usize,
+    rx_va: usize,
+    len: usize,
+    tf: &mut TrapFrame,
+) {
+    let buffers = unsafe { to_user_slice(tx_va, len, tf) }
+        .and_then(|tx| unsafe { to_user_slice_mut(rx_va, len, tf) }.map(|rx| (tx, rx)));
+    let (tx, rx) = match buffers {
+        Ok(buffers) => buffers,
+        Err(e) => {
+            tf.x[7] = e as u64;
+            return;
+        }
+    };
+    let pid = tf.tpidr;
+    if let Some(received) = SPI.take(pid) {
+        rx.copy_from_slice(&received);
+        tf.x[7] = OsError::Ok as u64;
+        return;
+    }
+    match SPI.start(pid, flags, hz, tx.to_vec()) {
+        Ok(true) => block_and_restart(tf, move |_| SPI.is_done(pid)),
+        Ok(false) => block_and_restart(tf, |_| SPI.is_idle()),
         Err(e) => tf.x[7] = e as u64,
     }
 }
@@ -409,7 +1219,7 @@ pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::InvalidArgument`: The provided buffer is not UTF-8 encoded.
 pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
-    let result = unsafe { to_user_slice(va, len) }
+    let result = unsafe { to_user_slice(va, len, tf) }
         .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument));
 
     match result {
@@ -454,16 +1264,58 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         4 => sys_write(tf.x[0] as u8, tf),
         5 => sys_getpid(tf),
         6 => sys_write_str(tf.x[0] as usize, tf.x[1] as usize, tf),
-        7 => sys_sbrk(tf.x[0] as usize, tf),
+        7 => sys_sbrk(tf.x[0] as isize, tf),
         8 => sys_rand(tf.x[0] as u32, tf.x[1] as u32, tf),
         9 => sys_rrand(tf),
         10 => sys_entropy(tf),
+        11 => sys_poll(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf),
+        12 => sys_set_nonblocking(tf.x[0] as usize, tf.x[1] != 0, tf),
+        13 => sys_read(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf),
+        14 => sys_console_mode(tf.x[0], tf),
+        15 => sys_console_release(tf),
+        16 => sys_reboot(tf),
+        17 => sys_halt(tf),
         20 => sys_sock_create(tf),
         21 => sys_sock_status(tf.x[0] as usize, tf),
         22 => sys_sock_connect(tf.x[0] as usize, IpAddr::from(tf.x[1], tf.x[2]), tf),
         23 => sys_sock_listen(tf.x[0] as usize, tf.x[1] as u16, tf),
         24 => sys_sock_send(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf),
         25 => sys_sock_recv(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf),
+        26 => sys_ping(tf.x[0] as u32, tf.x[1] as u16, tf.x[2] as u32, tf),
+        30 => sys_gpio_claim(tf.x[0], tf),
+        31 => sys_gpio_release(tf.x[0], tf),
+        32 => sys_gpio_configure(tf.x[0], tf.x[1], tf.x[2], tf),
+        33 => sys_gpio_write(tf.x[0], tf.x[1] != 0, tf),
+        34 => sys_gpio_read(tf.x[0], tf),
+        35 => sys_gpio_wait(tf.x[0], tf.x[1], tf.x[2], tf),
+        40 => sys_i2c_transfer(
+            tf.x[0],
+            tf.x[1] as usize,
+            tf.x[2] as usize,
+            tf.x[3] as usize,
+            tf.x[4] as usize,
+            tf,
+        ),
+        41 => sys_spi_transfer(
+            tf.x[0],
+            tf.x[1],
+            tf.x[2] as usize,
+            tf.x[3] as usize,
+            tf.x[4] as usize,
+            tf,
+        ),
+        50 => sys_open(tf.x[0] as usize, tf.x[1] as usize, tf),
+        51 => sys_close(tf.x[0] as usize, tf),
+        52 => sys_mmap(
+            tf.x[0] as usize,
+            tf.x[1] as usize,
+            tf.x[2],
+            tf.x[3],
+            tf.x[4] as usize,
+            tf.x[5] as usize,
+            tf,
+        ),
+        53 => sys_munmap(tf.x[0] as usize, tf.x[1] as usize, tf),
         _ => tf.x[7] = OsError::Unknown as u64,
     }
 }
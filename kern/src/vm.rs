@@ -1,8 +1,10 @@
 mod address;
 mod pagetable;
+mod tlb;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
 pub use self::pagetable::*;
+pub use self::tlb::*;
 
 use aarch64::*;
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -71,7 +73,8 @@ impl VMManager {
             (0b01 << 26) | // ORGN1=1 write back
             (0b01 << 24) | // IRGN1=1 write back
             (0b0  << 23) | // EPD1 enables higher half
-            ((USER_MASK_BITS as u64) << 16) | // T1SZ=34 (1GB)
+            (0b1  << 22) | // A1=1, TTBR1 holds the ASID
+            ((USER_MASK_BITS as u64) << 16) | // T1SZ=16 (256TB)
             (0b01 << 14) | // TG0=64k
             (0b11 << 12) | // SH0=3 inner
             (0b01 << 10) | // ORGN0=1 write back
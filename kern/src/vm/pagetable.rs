@@ -1,16 +1,13 @@
-use core::iter::Chain;
-use core::ops::{Deref, DerefMut, BitAnd, Sub};
-use core::slice::Iter;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
 use core::slice::from_raw_parts_mut;
 
-use alloc::boxed::Box;
 use alloc::fmt;
-use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::param::*;
-use crate::vm::{PhysicalAddr, VirtualAddr};
-use crate::ALLOCATOR;
+use crate::vm::{assign_asid, invalidate_page, release_asid, shootdown, Asid, AsidContext, PhysicalAddr, VirtualAddr};
+use crate::FRAMES;
 
 use aarch64::vmsa::*;
 use shim::const_assert_size;
@@ -22,27 +19,27 @@ const_assert_size!(Page, PAGE_SIZE);
 impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
-
-    fn layout() -> Layout {
-        unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
-    }
 }
 
+/// Number of entries in a table.
+const ENTRIES: usize = 8192;
+/// Number of address bits translated by a table.
+const INDEX_BITS: usize = 13;
+/// Number of address bits of an offset in a page.
+const PAGE_BITS: usize = 16;
+/// Largest number of address bits translated by a tree of tables.
+const MAX_VA_BITS: usize = 48;
+
+/// A table of level 1 or 2. Its entries point to the tables of the next
+/// level, in the same format on both levels.
 #[repr(C)]
 #[repr(align(65536))]
 pub struct L2PageTable {
-    pub entries: [RawL2Entry; 8192],
+    pub entries: [RawL2Entry; ENTRIES],
 }
 const_assert_size!(L2PageTable, PAGE_SIZE);
 
 impl L2PageTable {
-    /// Returns a new `L2PageTable`
-    fn new() -> L2PageTable {
-        L2PageTable {
-            entries: [RawL2Entry::new(0); 8192]
-        }
-    }
-
     /// Returns a `PhysicalAddr` of the pagetable.
     pub fn as_ptr(&self) -> PhysicalAddr {
         PhysicalAddr::from(self as *const L2PageTable)
@@ -53,19 +50,14 @@ impl L2PageTable {
 pub struct L3Entry(RawL3Entry);
 
 impl L3Entry {
-    /// Returns a new `L3Entry`.
-    fn new() -> L3Entry {
-        L3Entry(RawL3Entry::new(0))
-    }
-
     /// Returns `true` if the L3Entry is valid and `false` otherwise.
-    fn is_valid(&self) -> bool {
+    pub fn is_valid(&self) -> bool {
         self.0.get_value(RawL3Entry::VALID) == EntryValid::Valid
     }
 
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
-    fn get_page_addr(&self) -> Option<PhysicalAddr> {
+    pub fn get_page_addr(&self) -> Option<PhysicalAddr> {
         if self.is_valid() {
             Some(PhysicalAddr::from(self.0.get_masked(RawL3Entry::ADDR)))
         } else {
@@ -74,101 +66,146 @@ impl L3Entry {
     }
 }
 
+impl fmt::Debug for L3Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
 #[repr(C)]
 #[repr(align(65536))]
 pub struct L3PageTable {
-    pub entries: [L3Entry; 8192],
+    pub entries: [L3Entry; ENTRIES],
 }
 const_assert_size!(L3PageTable, PAGE_SIZE);
 
 impl L3PageTable {
-    /// Returns a new `L3PageTable`.
-    fn new() -> L3PageTable {
-        L3PageTable {
-            entries: [L3Entry::new(); 8192]
-        }
-    }
-
     /// Returns a `PhysicalAddr` of the pagetable.
     pub fn as_ptr(&self) -> PhysicalAddr {
         PhysicalAddr::from(self as *const L3PageTable)
     }
 }
 
-#[derive(Debug)]
-#[repr(C)]
-#[repr(align(65536))]
+/// Allocates a table from `FRAMES`, with all of its entries invalid.
+///
+/// # Panics
+///
+/// Panics if no physical page frame is free.
+fn alloc_table() -> usize {
+    let addr = FRAMES.alloc().expect("out of physical page frames for page tables");
+    unsafe { ptr::write_bytes(addr as *mut u8, 0, PAGE_SIZE) };
+    addr
+}
+
+/// Returns the number of address bits below the ones translated by a table
+/// of `level`.
+fn level_shift(level: usize) -> usize {
+    PAGE_BITS + INDEX_BITS * (3 - level)
+}
+
+/// A tree of tables translating an address space of `va_bits` bits that
+/// starts at `base`.
+///
+/// The walk starts at a root table of level 1 or 2, depending on the size of
+/// the address space, and ends at L3 tables mapping pages. Only the root
+/// table exists at first: the tables below it are allocated from `FRAMES` as
+/// the first page under them is mapped, so pages can be mapped sparsely
+/// anywhere in the address space. The tables are given back when the
+/// `PageTable` is dropped.
 pub struct PageTable {
-    pub l2: L2PageTable,
-    pub l3: [L3PageTable; 3],
+    root: *mut L2PageTable,
+    /// Level of the root table.
+    root_level: usize,
+    base: usize,
+    va_bits: usize,
+    /// Number of tables, the root included.
+    tables: usize,
 }
 
+// The tables are owned by the `PageTable`.
+unsafe impl Send for PageTable {}
+
 impl PageTable {
-    /// Returns a new `Box` containing `PageTable`.
-    /// Entries in L2PageTable should be initialized properly before return.
-    fn new(perm: u64) -> Box<PageTable> {
-        let mut page_table = Box::new(PageTable {
-            l2: L2PageTable::new(),
-            l3: [
-                L3PageTable::new(),
-                L3PageTable::new(),
-                L3PageTable::new(),
-            ],
-        });
-
-        page_table.l2.entries[0].set_masked(page_table.l3[0].as_ptr().as_u64(), RawL2Entry::ADDR);
-        page_table.l2.entries[0].set_value(EntryType::Table, RawL2Entry::TYPE);
-        page_table.l2.entries[0].set_value(EntryValid::Valid, RawL2Entry::VALID);
-
-        page_table.l2.entries[1].set_masked(page_table.l3[1].as_ptr().as_u64(), RawL2Entry::ADDR);
-        page_table.l2.entries[1].set_value(EntryType::Table, RawL2Entry::TYPE);
-        page_table.l2.entries[1].set_value(EntryValid::Valid, RawL2Entry::VALID);
-
-        page_table.l2.entries[2].set_masked(page_table.l3[2].as_ptr().as_u64(), RawL2Entry::ADDR);
-        page_table.l2.entries[2].set_value(EntryType::Table, RawL2Entry::TYPE);
-        page_table.l2.entries[2].set_value(EntryValid::Valid, RawL2Entry::VALID);
-
-        page_table
-    }
-
-    /// Returns the (L2index, L3index) extracted from the given virtual address.
-    /// L2index should be smaller than the number of L3PageTable.
+    /// Returns a new `PageTable` of an address space of `va_bits` bits that
+    /// starts at `base`, with no page mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `va_bits` is not more than a single L3 table translates, or
+    /// more than `MAX_VA_BITS`.
+    fn new(base: usize, va_bits: usize) -> PageTable {
+        assert!(
+            va_bits > PAGE_BITS + INDEX_BITS && va_bits <= MAX_VA_BITS,
+            "PageTable: unsupported address space of {} bits",
+            va_bits
+        );
+        let root_level = if va_bits > level_shift(1) { 1 } else { 2 };
+        PageTable {
+            root: alloc_table() as *mut L2PageTable,
+            root_level,
+            base,
+            va_bits,
+            tables: 1,
+        }
+    }
+
+    /// Returns the index of the entry for the virtual address in a table of
+    /// `level`.
     ///
     /// # Panics
     ///
     /// Panics if the virtual address is not properly aligned to page size.
-    /// Panics if extracted L2index exceeds the number of L3PageTable.
-    fn locate(va: VirtualAddr) -> (usize, usize) {
-        if !allocator::util::has_alignment(va.as_usize(), Page::SIZE) {
-            panic!("VirtualAddr: {} is not aligned to page size: {}", va.as_usize(), Page::SIZE);
+    /// Panics if the virtual address is outside of the address space.
+    fn index(&self, va: VirtualAddr, level: usize) -> usize {
+        let va = va.as_usize();
+        if !allocator::util::has_alignment(va, Page::SIZE) {
+            panic!("VirtualAddr: {:#x} is not aligned to page size: {}", va, Page::SIZE);
         }
-        let l2_index = va.bitand(VirtualAddr::from(0b11usize << 29)).as_usize() >> 29;
-        let l3_index = va.bitand(VirtualAddr::from(0x1fffusize << 16)).as_usize() >> 16;
-        if l2_index >= 3 {
+        if va < self.base || (va - self.base) >> self.va_bits != 0 {
             panic!(
-                "L2 Index: {} from VirtualAddr: {} is greater than L3PageTable count: 3",
-                l2_index,
-                va.as_usize()
+                "VirtualAddr: {:#x} is outside of the address space at {:#x} ({} bits)",
+                va, self.base, self.va_bits
             );
         }
-        (l2_index, l3_index)
+        ((va - self.base) >> level_shift(level)) & (ENTRIES - 1)
+    }
+
+    /// Returns the L3 table holding the L3entry indicated by the given
+    /// virtual address, or `None` if that table does not exist yet.
+    fn walk(&self, va: VirtualAddr) -> Option<&L3PageTable> {
+        let mut table = self.root as usize;
+        for level in self.root_level..3 {
+            let entry = unsafe { &(*(table as *const L2PageTable)).entries[self.index(va, level)] };
+            if entry.get_value(RawL2Entry::VALID) != EntryValid::Valid {
+                return None;
+            }
+            table = entry.get_masked(RawL2Entry::ADDR) as usize;
+        }
+        Some(unsafe { &*(table as *const L3PageTable) })
+    }
+
+    /// Returns the L3 table holding the L3entry indicated by the given
+    /// virtual address, allocating the missing tables on the way to it.
+    fn walk_alloc(&mut self, va: VirtualAddr) -> &mut L3PageTable {
+        let mut table = self.root as usize;
+        for level in self.root_level..3 {
+            let index = self.index(va, level);
+            let entry = unsafe { &mut (*(table as *mut L2PageTable)).entries[index] };
+            if entry.get_value(RawL2Entry::VALID) != EntryValid::Valid {
+                entry.set_masked(alloc_table() as u64, RawL2Entry::ADDR);
+                entry.set_value(EntryType::Table, RawL2Entry::TYPE);
+                entry.set_value(EntryValid::Valid, RawL2Entry::VALID);
+                self.tables += 1;
+            }
+            table = entry.get_masked(RawL2Entry::ADDR) as usize;
+        }
+        unsafe { &mut *(table as *mut L3PageTable) }
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is valid.
     /// Otherwise, `false` is returned.
     pub fn is_valid(&self, va: VirtualAddr) -> bool {
-        let (l2_index, l3_index) = PageTable::locate(va);
-        let l2_entry = self.l2.entries[l2_index];
-        // TODO: Make l3_addr usize
-        let l3_addr = l2_entry.get_masked(RawL2Entry::ADDR);
-
-        if self.l3[0].as_ptr().as_u64() == l3_addr {
-            return self.l3[0].entries[l3_index].is_valid()
-        } else if self.l3[1].as_ptr().as_u64() == l3_addr {
-            return self.l3[1].entries[l3_index].is_valid()
-        } else {
-            panic!("Unexpected failure to find L3PageTable in PageTable::is_valid()")
-        }
+        self.get_page_addr(va).is_some()
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is invalid.
@@ -177,78 +214,223 @@ impl PageTable {
         !self.is_valid(va)
     }
 
+    /// Returns the physical address of the page the L3entry indicated by the
+    /// given virtual address translates to, or `None` if the entry is invalid.
+    pub fn get_page_addr(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        let l3 = self.walk(va)?;
+        l3.entries[self.index(va, 3)].get_page_addr()
+    }
+
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
-    /// address.
+    /// address, allocating the tables on the way to it as needed.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
-        let (l2_index, l3_index) = PageTable::locate(va);
-        let l2_entry = self.l2.entries[l2_index];
-        let l3_addr = l2_entry.get_masked(RawL2Entry::ADDR);
-
-        if self.l3[0].as_ptr().as_u64() == l3_addr {
-            self.l3[0].entries[l3_index].0.set(entry.get())
-        } else if self.l3[1].as_ptr().as_u64() == l3_addr {
-            self.l3[1].entries[l3_index].0.set(entry.get())
-        } else if self.l3[2].as_ptr().as_u64() == l3_addr {
-            self.l3[2].entries[l3_index].0.set(entry.get())
-        } else {
-            panic!("Unexpected failure to find L3PageTable in PageTable::set_entry()")
-        };
-
+        let index = self.index(va, 3);
+        self.walk_alloc(va).entries[index].0.set(entry.get());
         self
     }
 
     /// Returns a base address of the pagetable. The returned `PhysicalAddr` value
-    /// will point the start address of the L2PageTable.
+    /// will point the start address of the root table.
     pub fn get_baddr(&self) -> PhysicalAddr {
-        self.l2.as_ptr()
+        PhysicalAddr::from(self.root as usize)
+    }
+
+    /// Returns the number of tables, the root included.
+    pub fn tables(&self) -> usize {
+        self.tables
+    }
+
+    /// Gives the table at `table` of `level` and the tables below it back to
+    /// `FRAMES`.
+    fn release_table(table: usize, level: usize) {
+        if level < 3 {
+            let entries = unsafe { &(*(table as *const L2PageTable)).entries };
+            for entry in entries.iter() {
+                if entry.get_value(RawL2Entry::VALID) == EntryValid::Valid {
+                    PageTable::release_table(entry.get_masked(RawL2Entry::ADDR) as usize, level + 1);
+                }
+            }
+        }
+        FRAMES.release(table);
+    }
+}
+
+impl Drop for PageTable {
+    fn drop(&mut self) {
+        PageTable::release_table(self.root as usize, self.root_level);
+    }
+}
+
+/// An iterator over the valid L3entries of a `PageTable`, with the virtual
+/// addresses they translate, in address order.
+pub struct Mappings<'a> {
+    page_table: &'a PageTable,
+    /// Address of the table the walk is in on each level, from level 1.
+    tables: [usize; 3],
+    /// Index of the next entry to look at in each of those tables.
+    indices: [usize; 3],
+    level: usize,
+}
+
+impl<'a> Mappings<'a> {
+    /// Returns the virtual address translated by the L3entry just looked at.
+    fn va(&self) -> VirtualAddr {
+        let offset = (self.page_table.root_level..=3)
+            .map(|level| (self.indices[level - 1] - 1) << level_shift(level))
+            .sum::<usize>();
+        VirtualAddr::from(self.page_table.base + offset)
+    }
+}
+
+impl<'a> Iterator for Mappings<'a> {
+    type Item = (VirtualAddr, &'a L3Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let level = self.level;
+            let index = self.indices[level - 1];
+            if index == ENTRIES {
+                if level == self.page_table.root_level {
+                    return None;
+                }
+                self.level -= 1;
+                continue;
+            }
+            self.indices[level - 1] += 1;
+
+            let table = self.tables[level - 1];
+            if level == 3 {
+                let entry = unsafe { &(*(table as *const L3PageTable)).entries[index] };
+                if entry.is_valid() {
+                    return Some((self.va(), entry));
+                }
+            } else {
+                let entry = unsafe { &(*(table as *const L2PageTable)).entries[index] };
+                if entry.get_value(RawL2Entry::VALID) == EntryValid::Valid {
+                    self.tables[level] = entry.get_masked(RawL2Entry::ADDR) as usize;
+                    self.indices[level] = 0;
+                    self.level += 1;
+                }
+            }
+        }
     }
 }
 
 impl<'a> IntoIterator for &'a PageTable {
-    type Item = &'a L3Entry;
-    type IntoIter = Chain<Iter<'a, L3Entry>, Iter<'a, L3Entry>>;
+    type Item = (VirtualAddr, &'a L3Entry);
+    type IntoIter = Mappings<'a>;
+
+    fn into_iter(self) -> Mappings<'a> {
+        let mut tables = [0; 3];
+        tables[self.root_level - 1] = self.root as usize;
+        Mappings {
+            page_table: self,
+            tables,
+            indices: [0; 3],
+            level: self.root_level,
+        }
+    }
+}
 
-    fn into_iter(self) -> Chain<Iter<'a, L3Entry>, Iter<'a, L3Entry>> {
-        self.l3[0].entries.iter().chain(self.l3[1].entries.iter())
+impl fmt::Debug for PageTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "PageTable {{")?;
+        writeln!(f, "  root: {:#x} (level {})", self.root as usize, self.root_level)?;
+        writeln!(f, "  address space: {:#x} ({} bits)", self.base, self.va_bits)?;
+        writeln!(f, "  tables: {}", self.tables)?;
+        // Runs of pages mapped at consecutive addresses.
+        let mut run: Option<(usize, usize)> = None;
+        for (va, _) in self {
+            let va = va.as_usize();
+            run = match run {
+                Some((start, end)) if end == va => Some((start, va + Page::SIZE)),
+                Some((start, end)) => {
+                    writeln!(f, "  {:#x}-{:#x}: {} pages", start, end, (end - start) / Page::SIZE)?;
+                    Some((va, va + Page::SIZE))
+                }
+                None => Some((va, va + Page::SIZE)),
+            };
+        }
+        if let Some((start, end)) = run {
+            writeln!(f, "  {:#x}-{:#x}: {} pages", start, end, (end - start) / Page::SIZE)?;
+        }
+        writeln!(f, "}}")
     }
 }
 
 #[derive(Debug)]
-pub struct KernPageTable(Box<PageTable>);
+pub struct KernPageTable(PageTable);
 
 impl KernPageTable {
-    /// Returns a new `KernPageTable`. `KernPageTable` should have a `Pagetable`
-    /// created with `KERN_RW` permission.
+    /// Returns a new `KernPageTable` identity mapping the kernel's address
+    /// space.
     ///
-    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
+    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM,
+    /// uncached entries for the VideoCore memory between RAM and `IO_BASE`, and
     /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
     /// Each L3 entry should have correct value for lower attributes[10:0] as well
     /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
     /// more details.
+    ///
+    /// Only the kernel's text is executable, and it is read-only like its
+    /// rodata. The guard page below each core's kernel stack is left unmapped.
     pub fn new() -> KernPageTable {
-        let mut page_table = PageTable::new(EntryPerm::KERN_RW);
+        extern "C" {
+            static __text_beg: u8;
+            static __rodata_beg: u8;
+            static __data_beg: u8;
+        }
+
+        let mut page_table = PageTable::new(0, 64 - KERNEL_MASK_BITS);
         let mem_start = 0x0000_0000usize;
         let (_, mem_end) = allocator::memory_map()
             .expect("unexpected None from allocator::memory_map()");
+        let (text_beg, rodata_beg, data_beg) = unsafe {
+            (
+                &__text_beg as *const u8 as usize,
+                &__rodata_beg as *const u8 as usize,
+                &__data_beg as *const u8 as usize,
+            )
+        };
 
         for addr in (mem_start..IO_BASE_END).step_by(Page::SIZE) {
             let mut raw_l3_entry = RawL3Entry::new(0);
             raw_l3_entry.set_masked(addr as u64, RawL3Entry::ADDR);
-
-            if addr < mem_end {
+            raw_l3_entry.set_bit(RawL3Entry::UXN);
+
+            if addr < mem_end && !is_stack_guard(addr) {
+                let perm = if addr >= text_beg && addr < data_beg {
+                    EntryPerm::KERN_RO
+                } else {
+                    EntryPerm::KERN_RW
+                };
+                if addr < text_beg || addr >= rodata_beg {
+                    raw_l3_entry.set_bit(RawL3Entry::PXN);
+                }
                 raw_l3_entry.set_bit(RawL3Entry::AF);
                 raw_l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-                raw_l3_entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
+                raw_l3_entry.set_value(perm, RawL3Entry::AP);
                 raw_l3_entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
                 raw_l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
                 raw_l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
             } else if addr >= IO_BASE && addr < IO_BASE_END {
+                raw_l3_entry.set_bit(RawL3Entry::PXN);
                 raw_l3_entry.set_bit(RawL3Entry::AF);
                 raw_l3_entry.set_value(EntrySh::OSh, RawL3Entry::SH);
                 raw_l3_entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
                 raw_l3_entry.set_value(EntryAttr::Dev, RawL3Entry::ATTR);
                 raw_l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
                 raw_l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+            } else if addr >= mem_end && addr < IO_BASE {
+                // VideoCore memory, which holds the framebuffer. Mapped
+                // uncached so drawing shows up without cache maintenance.
+                raw_l3_entry.set_bit(RawL3Entry::PXN);
+                raw_l3_entry.set_bit(RawL3Entry::AF);
+                raw_l3_entry.set_value(EntrySh::OSh, RawL3Entry::SH);
+                raw_l3_entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
+                raw_l3_entry.set_value(EntryAttr::Nc, RawL3Entry::ATTR);
+                raw_l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
+                raw_l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
             } else {
                 raw_l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
                 raw_l3_entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
@@ -263,58 +445,176 @@ impl KernPageTable {
     }
 }
 
+/// Returns `true` if the page at `addr` is the guard page below the kernel
+/// stack of a core.
+fn is_stack_guard(addr: usize) -> bool {
+    (0..NCORES).any(|core| {
+        let stack_top = KERN_STACK_BASE - KERN_STACK_STRIDE * core;
+        let guard = stack_top - KERN_STACK_SIZE - KERN_STACK_GUARD_SIZE;
+        addr >= guard && addr < guard + KERN_STACK_GUARD_SIZE
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
+/// Returns a non-global L3 entry mapping a user page at `pa` with `perm`.
+fn user_entry(pa: usize, perm: PagePerm) -> RawL3Entry {
+    let mut raw_l3_entry = RawL3Entry::new(0);
+    raw_l3_entry.set_masked(pa as u64, RawL3Entry::ADDR);
+    raw_l3_entry.set_bit(RawL3Entry::PXN);
+    match perm {
+        PagePerm::RW => {
+            raw_l3_entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+            raw_l3_entry.set_bit(RawL3Entry::UXN);
+        }
+        PagePerm::RO => {
+            raw_l3_entry.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+            raw_l3_entry.set_bit(RawL3Entry::UXN);
+        }
+        PagePerm::RX => {
+            raw_l3_entry.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+        }
+        PagePerm::RWX => {
+            raw_l3_entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        }
+    }
+    raw_l3_entry.set_bit(RawL3Entry::NG);
+    raw_l3_entry.set_bit(RawL3Entry::AF);
+    raw_l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
+    raw_l3_entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
+    raw_l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
+    raw_l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+    raw_l3_entry
+}
+
 #[derive(Debug)]
-pub struct UserPageTable(Box<PageTable>);
+pub struct UserPageTable {
+    table: PageTable,
+    /// The ASID tagging the translations of this address space, assigned
+    /// when it runs.
+    asid: AsidContext,
+    /// Bitmask of the cores that may hold translations of this address space.
+    cores: usize,
+}
 
 impl UserPageTable {
-    /// Returns a new `UserPageTable` containing a `PageTable` created with
-    /// `USER_RW` permission.
+    /// Returns a new `UserPageTable` of the user address space, with no page
+    /// mapped. It gets an ASID when it first runs.
     pub fn new() -> UserPageTable {
-        UserPageTable(PageTable::new(EntryPerm::USER_RW))
+        UserPageTable {
+            table: PageTable::new(USER_VA_BASE, 64 - USER_MASK_BITS),
+            asid: AsidContext::NONE,
+            cores: 0,
+        }
+    }
+
+    /// Returns the ASID of this address space.
+    pub fn asid(&self) -> Asid {
+        self.asid.asid()
+    }
+
+    /// Returns the value of TTBR1 for this address space: the base address of
+    /// the page table tagged with its ASID.
+    pub fn get_ttbr(&self) -> u64 {
+        self.get_baddr().as_u64() | ((self.asid() as u64) << 48)
+    }
+
+    /// Records that this address space is about to run on `core`, which may
+    /// then cache its translations, and returns the value of TTBR1 to run it
+    /// with.
+    ///
+    /// The address space gets a new ASID if its ASID was freed when the
+    /// generation of ASIDs was bumped. Every TLB was flushed then, so no core
+    /// holds its translations anymore.
+    pub fn activate(&mut self, core: usize) -> u64 {
+        let asid = assign_asid(self.asid, core);
+        if asid != self.asid {
+            self.asid = asid;
+            self.cores = 0;
+        }
+        self.cores |= 1 << core;
+        self.get_ttbr()
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page with `perm`. Returns the allocated page.
+    ///
+    /// The page is zeroed first, as frames are recycled without being cleared
+    /// and must not leak the data of their previous owner.
     ///
     /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    /// Panics if the virtual address is outside of the user address space.
     /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
+    /// Panics if no physical page frame is free.
     ///
     /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
-        if va.as_usize() < USER_IMG_BASE {
-            panic!("UserPageTable::alloc() called with VirtualAddr lower than {}", USER_IMG_BASE);
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        if self.is_valid(va) {
+            panic!("VirtualAddr already allocated");
         }
 
-        let va_locate = va.sub(VirtualAddr::from(USER_IMG_BASE));
-        // let va_locate = VirtualAddr::from(va.as_u64() - USER_IMG_BASE as u64);
-        if self.is_valid(va_locate) {
+        let page_ptr = FRAMES.alloc().expect("out of physical page frames") as *mut u8;
+        unsafe { ptr::write_bytes(page_ptr, 0, PAGE_SIZE) };
+        self.map(va, page_ptr as usize, perm);
+
+        let page = unsafe { from_raw_parts_mut(page_ptr, PAGE_SIZE)} ;
+        page
+    }
+
+    /// Maps the page at the given virtual address to the frame at `frame`, a
+    /// frame taken from `FRAMES`, with `perm`. The page table releases the
+    /// frame once the page is unmapped.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is outside of the user address space.
+    /// Panics if the virtual address has already been allocated.
+    pub fn map(&mut self, va: VirtualAddr, frame: usize, perm: PagePerm) {
+        if self.is_valid(va) {
             panic!("VirtualAddr already allocated");
         }
+        self.set_entry(va, user_entry(frame, perm));
+    }
 
-        let page_ptr = unsafe { ALLOCATOR.alloc(Page::layout()) };
-
-        let mut raw_l3_entry = RawL3Entry::new(0);
-        raw_l3_entry.set_masked(page_ptr as u64, RawL3Entry::ADDR);
-        raw_l3_entry.set_bit(RawL3Entry::AF);
-        raw_l3_entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-        raw_l3_entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
-        raw_l3_entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
-        raw_l3_entry.set_value(PageType::Page, RawL3Entry::TYPE);
-        raw_l3_entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+    /// Unmaps the page at the given virtual address and releases its frame.
+    ///
+    /// The translation is invalidated on this core and shot down on the other
+    /// cores the address space has run on.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is outside of the user address space.
+    /// Panics if the virtual address is not allocated.
+    pub fn dealloc(&mut self, va: VirtualAddr) {
+        let pa = self.get_page_addr(va).expect("VirtualAddr not allocated");
+        self.set_entry(va, RawL3Entry::new(0));
+        self.invalidate(va);
+        FRAMES.release(pa.as_usize());
+    }
 
-        self.set_entry(va_locate, raw_l3_entry);
+    /// Changes the permission of the page at the given virtual address to
+    /// `perm`, invalidating its old translation like `dealloc()` does.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is outside of the user address space.
+    /// Panics if the virtual address is not allocated.
+    pub fn protect(&mut self, va: VirtualAddr, perm: PagePerm) {
+        let pa = self.get_page_addr(va).expect("VirtualAddr not allocated");
+        self.set_entry(va, user_entry(pa.as_usize(), perm));
+        self.invalidate(va);
+    }
 
-        let page = unsafe { from_raw_parts_mut(page_ptr, PAGE_SIZE)} ;
-        page
+    /// Invalidates the translation of `va` on every core that may cache it.
+    fn invalidate(&self, va: VirtualAddr) {
+        invalidate_page(self.asid(), va);
+        let others = self.cores & !(1 << aarch64::affinity());
+        if others != 0 {
+            shootdown(self.asid(), others);
+        }
     }
 }
 
@@ -330,7 +630,7 @@ impl Deref for UserPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.table
     }
 }
 
@@ -342,18 +642,19 @@ impl DerefMut for KernPageTable {
 
 impl DerefMut for UserPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.table
     }
 }
 
 impl Drop for UserPageTable {
     fn drop(&mut self) {
-        for entry in &*self.0 {
-            if entry.is_valid() {
-                let mut pa = PhysicalAddr::from(entry.0.get_masked(RawL3Entry::ADDR));
-                unsafe { ALLOCATOR.dealloc(pa.as_mut_ptr(), Page::layout()) };
+        // The tables themselves are released by the `PageTable`.
+        for (_, entry) in &self.table {
+            if let Some(pa) = entry.get_page_addr() {
+                FRAMES.release(pa.as_usize());
             }
         }
+        release_asid(self.asid);
     }
 }
 
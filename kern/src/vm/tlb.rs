@@ -0,0 +1,283 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aarch64::affinity;
+use pi::local_interrupt::LocalController;
+
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+use crate::vm::VirtualAddr;
+
+/// An address space identifier. Translations of user pages are tagged with
+/// the ASID of their page table, so switching between processes does not
+/// need to invalidate the TLB.
+pub type Asid = u8;
+
+/// Number of ASIDs. Every ARMv8-A core supports 8-bit ASIDs.
+const ASIDS: usize = 256;
+/// Number of words of an ASID bitmap.
+const ASID_WORDS: usize = ASIDS / 64;
+
+/// Mailbox of each core through which other cores request a TLB shootdown.
+pub const SHOOTDOWN_MAILBOX: usize = 0;
+
+/// An ASID along with the generation of ASIDs it was allocated in. An ASID
+/// of an older generation may have been handed out again since, so it must
+/// be replaced before its address space runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AsidContext {
+    generation: u64,
+    asid: Asid,
+}
+
+impl AsidContext {
+    /// The context of an address space that has not run yet. No generation
+    /// is 0, so it is always replaced.
+    pub const NONE: AsidContext = AsidContext {
+        generation: 0,
+        asid: 0,
+    };
+
+    /// Returns the ASID.
+    pub fn asid(&self) -> Asid {
+        self.asid
+    }
+}
+
+/// Bitmap of the ASIDs in use in the current generation.
+///
+/// Once every ASID is in use, the generation is bumped: all ASIDs are freed
+/// at once and every TLB is flushed, and address spaces get a new ASID the
+/// next time they run. The ASID each core ran last is kept though, as the
+/// core may still be running with it.
+struct AsidMap {
+    generation: u64,
+    used: [u64; ASID_WORDS],
+    next: usize,
+    /// The context each core ran last.
+    active: [AsidContext; NCORES],
+    /// The contexts of `active` when the generation was bumped, whose ASIDs
+    /// were carried over to the current generation.
+    reserved: [AsidContext; NCORES],
+}
+
+impl AsidMap {
+    /// ASID 0 is reserved for the kernel page table, which TTBR1 holds while
+    /// no process has been scheduled yet.
+    const fn new() -> AsidMap {
+        AsidMap {
+            generation: 1,
+            used: [1, 0, 0, 0],
+            next: 1,
+            active: [AsidContext::NONE; NCORES],
+            reserved: [AsidContext::NONE; NCORES],
+        }
+    }
+
+    /// Returns the context `context` runs with on `core`, replacing it if it
+    /// is of an older generation.
+    fn assign(&mut self, context: AsidContext, core: usize) -> AsidContext {
+        let context = if context.generation == self.generation {
+            context
+        } else {
+            self.carry_over(context).unwrap_or_else(|| self.alloc())
+        };
+        self.active[core] = context;
+        context
+    }
+
+    /// Moves `context` to the current generation if its ASID was kept when
+    /// the generation was bumped.
+    fn carry_over(&mut self, context: AsidContext) -> Option<AsidContext> {
+        if context == AsidContext::NONE || !self.reserved.contains(&context) {
+            return None;
+        }
+        let current = AsidContext {
+            generation: self.generation,
+            asid: context.asid,
+        };
+        for reserved in self.reserved.iter_mut().filter(|reserved| **reserved == context) {
+            *reserved = current;
+        }
+        Some(current)
+    }
+
+    /// Returns the next free ASID after the last allocated one, so that a
+    /// released ASID is reused as late as possible. Bumps the generation if
+    /// none is free.
+    fn alloc(&mut self) -> AsidContext {
+        loop {
+            for i in 0..ASIDS {
+                let asid = (self.next + i) % ASIDS;
+                let (word, bit) = (asid / 64, asid % 64);
+                if self.used[word] & (1 << bit) == 0 {
+                    self.used[word] |= 1 << bit;
+                    self.next = asid + 1;
+                    return AsidContext {
+                        generation: self.generation,
+                        asid: asid as Asid,
+                    };
+                }
+            }
+            self.rollover();
+        }
+    }
+
+    /// Frees every ASID but those the cores ran last, and flushes the TLBs of
+    /// all cores so that the freed ones can be handed out again.
+    fn rollover(&mut self) {
+        self.generation += 1;
+        self.used = [1, 0, 0, 0];
+        self.next = 1;
+        self.reserved = self.active;
+        for context in self.reserved.iter().filter(|context| **context != AsidContext::NONE) {
+            let asid = context.asid as usize;
+            self.used[asid / 64] |= 1 << (asid % 64);
+        }
+        invalidate_all();
+    }
+
+    /// Frees the ASID of `context`. Returns `false` if it was already freed
+    /// when the generation was bumped.
+    fn release(&mut self, context: AsidContext) -> bool {
+        if context == AsidContext::NONE {
+            return false;
+        }
+        if context.generation != self.generation {
+            if !self.reserved.contains(&context) {
+                return false;
+            }
+            for reserved in self.reserved.iter_mut().filter(|reserved| **reserved == context) {
+                *reserved = AsidContext::NONE;
+            }
+        }
+        let asid = context.asid as usize;
+        self.used[asid / 64] &= !(1 << (asid % 64));
+        true
+    }
+}
+
+/// ASIDs whose translations a core must invalidate before it runs a process.
+struct Pending([AtomicU64; ASID_WORDS]);
+
+impl Pending {
+    const fn new() -> Pending {
+        Pending([
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ])
+    }
+
+    fn insert(&self, asid: Asid) {
+        let asid = asid as usize;
+        self.0[asid / 64].fetch_or(1 << (asid % 64), Ordering::SeqCst);
+    }
+}
+
+static ASID_MAP: Mutex<AsidMap> = Mutex::new(AsidMap::new());
+
+static PENDING: [Pending; NCORES] = [
+    Pending::new(),
+    Pending::new(),
+    Pending::new(),
+    Pending::new(),
+];
+
+/// Returns the context an address space with the context `context` runs with
+/// on `core`. The address space gets a new ASID if it has none yet, or if its
+/// ASID is of an older generation and may have been handed out again.
+pub fn assign_asid(context: AsidContext, core: usize) -> AsidContext {
+    ASID_MAP.lock().assign(context, core)
+}
+
+/// Releases the ASID of `context` once no process uses it anymore.
+///
+/// Every core is made to invalidate the translations tagged with the ASID
+/// before it runs another process, so the next owner of the ASID never sees
+/// them.
+pub fn release_asid(context: AsidContext) {
+    let mut map = ASID_MAP.lock();
+    if map.release(context) {
+        shootdown(context.asid, (1 << NCORES) - 1);
+    }
+}
+
+/// Invalidates the translation of the page at `va` tagged with `asid` in the
+/// TLB of this core.
+pub fn invalidate_page(asid: Asid, va: VirtualAddr) {
+    // (ref. C5.5.51: TLBI VALE1) VA[55:12] in bits [43:0], ASID in [63:48]
+    let arg = ((asid as u64) << 48) | ((va.as_u64() >> 12) & ((1 << 44) - 1));
+    unsafe {
+        asm!("dsb ishst
+              tlbi vale1, $0
+              dsb ish
+              isb"
+             :: "r"(arg) : "memory" : "volatile");
+    }
+}
+
+/// Invalidates all translations tagged with `asid` in the TLB of this core.
+pub fn invalidate_asid(asid: Asid) {
+    let arg = (asid as u64) << 48;
+    unsafe {
+        asm!("dsb ishst
+              tlbi aside1, $0
+              dsb ish
+              isb"
+             :: "r"(arg) : "memory" : "volatile");
+    }
+}
+
+/// Invalidates all translations in the TLBs of every core.
+pub fn invalidate_all() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1is
+              dsb ish
+              isb"
+             ::: "memory" : "volatile");
+    }
+}
+
+/// Invalidates the translations tagged with `asid` on every core in the
+/// bitmask `cores`.
+///
+/// This core invalidates them right away. Every other core is sent an IPI
+/// through its shootdown mailbox, and also catches up in `sync_tlb()` before
+/// it switches to a process, in case it had interrupts masked meanwhile. The
+/// caller does not wait for the other cores, so the address space must not be
+/// running on any of them.
+pub fn shootdown(asid: Asid, cores: usize) {
+    let this = affinity();
+    if cores & (1 << this) != 0 {
+        invalidate_asid(asid);
+    }
+
+    let mut controller = LocalController::new(this);
+    for core in (0..NCORES).filter(|&core| core != this && cores & (1 << core) != 0) {
+        PENDING[core].insert(asid);
+        // The request must be visible before the mailbox raises the IPI.
+        unsafe { asm!("dsb ish" :::: "volatile") };
+        controller.send_mailbox(core, SHOOTDOWN_MAILBOX, 1 << (asid % 32));
+    }
+}
+
+/// Invalidates the translations that other cores requested this core to drop.
+pub fn sync_tlb() {
+    for (word, pending) in PENDING[affinity()].0.iter().enumerate() {
+        let mut bits = pending.swap(0, Ordering::SeqCst);
+        while bits != 0 {
+            let bit = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            invalidate_asid((word * 64 + bit) as Asid);
+        }
+    }
+}
+
+/// Handles the shootdown IPI on this core by acknowledging the mailbox and
+/// calling `sync_tlb()`.
+pub fn handle_shootdown() {
+    LocalController::new(affinity()).take_mailbox(SHOOTDOWN_MAILBOX);
+    sync_tlb();
+}
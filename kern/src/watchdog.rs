@@ -0,0 +1,137 @@
+///! Kernel watchdog resetting the board when a core stops taking its
+///! scheduler ticks, and reboot/halt of the board
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use aarch64::affinity;
+use pi::pm::{self, Watchdog};
+use pi::timer;
+
+use shim::const_assert_eq;
+
+use crate::console::CONSOLE;
+use crate::param::{NCORES, PANIC_REBOOT_DELAY, WATCHDOG_TIMEOUT};
+
+// `KernelWatchdog::new()` spells out one pet time per core.
+const_assert_eq!(NCORES, 4);
+
+/// Time given to the UART to send out its FIFO before the board is reset.
+const UART_DRAIN_TIME: Duration = Duration::from_millis(10);
+
+/// The hardware watchdog as used by the kernel.
+///
+/// Every core pets the watchdog on its scheduler ticks, and in loops that
+/// run for long with interrupts masked, such as waiting for console input.
+/// The watchdog is restarted as long as every core has petted it within half
+/// of `WATCHDOG_TIMEOUT`, so the board is reset if any core locks up. Parked
+/// cores, which have nothing to run, are left out.
+pub struct KernelWatchdog {
+    /// Whether pets restart the watchdog.
+    enabled: AtomicBool,
+    /// Time of the last pet of each core, in milliseconds since boot.
+    petted: [AtomicU64; NCORES],
+    /// Bit mask of the parked cores.
+    parked: AtomicUsize,
+}
+
+impl KernelWatchdog {
+    pub const fn new() -> KernelWatchdog {
+        KernelWatchdog {
+            enabled: AtomicBool::new(false),
+            petted: [
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+            ],
+            parked: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts the watchdog, unless `WATCHDOG_TIMEOUT` is `None`.
+    pub fn start(&self) {
+        if let Some(timeout) = WATCHDOG_TIMEOUT {
+            let now = timer::current_time().as_millis() as u64;
+            for petted in self.petted.iter() {
+                petted.store(now, Ordering::SeqCst);
+            }
+            Watchdog::new().start(timeout);
+            self.enabled.store(true, Ordering::SeqCst);
+            info!("watchdog: started with a {:?} timeout", timeout.min(pm::MAX_TIMEOUT));
+        }
+    }
+
+    /// Records a scheduler tick of the current core.
+    pub fn tick(&self) {
+        self.pet();
+    }
+
+    /// Records that the current core is making progress, restarting the
+    /// watchdog if every other core that is not parked has done so recently.
+    pub fn pet(&self) {
+        let timeout = match WATCHDOG_TIMEOUT {
+            Some(timeout) if self.enabled.load(Ordering::Relaxed) => timeout,
+            _ => return,
+        };
+        let now = timer::current_time().as_millis() as u64;
+        self.petted[affinity()].store(now, Ordering::SeqCst);
+
+        let window = timeout.as_millis() as u64 / 2;
+        let parked = self.parked.load(Ordering::SeqCst);
+        let alive = (0..NCORES).all(|core| {
+            parked & (1 << core) != 0
+                || now.saturating_sub(self.petted[core].load(Ordering::SeqCst)) < window
+        });
+        if alive {
+            Watchdog::new().start(timeout);
+        }
+    }
+
+    /// Leaves the current core out while it has nothing to run.
+    pub fn park(&self) {
+        self.parked.fetch_or(1 << affinity(), Ordering::SeqCst);
+        self.pet();
+    }
+
+    /// Takes the current core into account again once it runs a process.
+    pub fn unpark(&self) {
+        self.pet();
+        self.parked.fetch_and(!(1 << affinity()), Ordering::SeqCst);
+    }
+
+    /// Applies the panic reboot policy: the board is reset after
+    /// `PANIC_REBOOT_DELAY`, or the watchdog is stopped if it is `None`.
+    /// Ticks no longer restart the watchdog afterwards.
+    pub fn panic(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+        match PANIC_REBOOT_DELAY {
+            Some(delay) => Watchdog::new().start(delay),
+            None => Watchdog::new().stop(),
+        }
+    }
+}
+
+/// Writes out the queued console output and stops the kernel watchdog so it
+/// does not race with a deliberate reset.
+fn prepare_reset() {
+    WATCHDOG.enabled.store(false, Ordering::SeqCst);
+    CONSOLE.lock().disable_interrupts();
+    timer::spin_sleep(UART_DRAIN_TIME);
+}
+
+/// Resets the board.
+pub fn reboot() -> ! {
+    info!("rebooting");
+    prepare_reset();
+    pm::reset()
+}
+
+/// Stops the board until its power is cycled.
+pub fn halt() -> ! {
+    info!("halting");
+    prepare_reset();
+    pm::halt()
+}
+
+/// The kernel's watchdog.
+pub static WATCHDOG: KernelWatchdog = KernelWatchdog::new();
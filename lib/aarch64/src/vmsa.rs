@@ -61,7 +61,10 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
+        NG[11 - 11],
         AF[10 - 10],
         SH[09 - 08],
         AP[07 - 06],
@@ -91,10 +94,10 @@ defreg!(
 defreg!(TCR_EL1);
 
 // (ref: D7.2.99: Translation Table Base Register 0)
-defreg!(TTBR0_EL1, [TTBR_CNP[00 - 00],]);
+defreg!(TTBR0_EL1, [TTBR_ASID[63 - 48], TTBR_CNP[00 - 00],]);
 
 // (ref: D7.2.102: Translation Table Base Register 1)
-defreg!(TTBR1_EL1, [TTBR_CNP[00 - 00],]);
+defreg!(TTBR1_EL1, [TTBR_ASID[63 - 48], TTBR_CNP[00 - 00],]);
 
 // (ref: D7.2.43: AArch64 Memory Model Feature Register 0)
 defreg!(
@@ -202,6 +205,23 @@ impl fmt::Debug for RawL3Entry {
             }
         )?;
 
+        write!(
+            f,
+            "{}{}{}",
+            match self.get_value(RawL3Entry::NG) {
+                1 => "-nG",
+                _ => "",
+            },
+            match self.get_value(RawL3Entry::PXN) {
+                1 => "-PXN",
+                _ => "",
+            },
+            match self.get_value(RawL3Entry::UXN) {
+                1 => "-UXN",
+                _ => "",
+            }
+        )?;
+
         // NS    [05-05],
 
         write!(
@@ -2,25 +2,25 @@ use core::alloc::Layout;
 use core::fmt;
 use crate::allocator::util::*;
 use crate::allocator::linked_list::LinkedList;
-use crate::LocalAlloc;
-use kernel_api::syscall::sbrk;
+use crate::{Break, LocalAlloc};
 
-const USER_VMM_ADDRESS_SIZE: usize= 30;
 const BIN_COUNT_MAX: usize = USER_VMM_ADDRESS_SIZE - 3;
 
-pub struct Allocator {
+pub struct Allocator<B: Break> {
+    brk: B,
     current: usize,
     bins: [LinkedList; BIN_COUNT_MAX],
 }
 
-impl Allocator {
-    /// Creates a new bin allocator that will allocate memory from the region
-    /// starting at address `start` and ending at address `end`.
-    pub fn new() -> Allocator {
-        let current = sbrk(0).unwrap() as usize;
+impl<B: Break> Allocator<B> {
+    /// Creates a new bin allocator that will allocate memory from the heap,
+    /// starting at the current break of `brk`.
+    pub fn new(mut brk: B) -> Allocator<B> {
+        let current = brk.sbrk(0).unwrap();
         let bins = [LinkedList::new(); BIN_COUNT_MAX];
 
         Allocator {
+            brk,
             current,
             bins,
         }
@@ -33,7 +33,7 @@ impl Allocator {
     fn map_to_bin(&self, layout: &Layout) -> usize {
         let required_size = layout.size().max(layout.align());
         for index in 0..self.bins.len() {
-            if Allocator::bin_size(index) >= required_size{
+            if Self::bin_size(index) >= required_size{
                 return index
             }
         }
@@ -42,7 +42,7 @@ impl Allocator {
 
 }
 
-impl LocalAlloc for Allocator {
+impl<B: Break> LocalAlloc for Allocator<B> {
     /// Allocates memory. Returns a pointer meeting the size and alignment
     /// properties of `layout.size()` and `layout.align()`.
     ///
@@ -71,16 +71,16 @@ impl LocalAlloc for Allocator {
                 return node.pop() as *mut u8
             }
         }
-        let aligned_addr = align_up(self.current, Allocator::bin_size(index));
+        let aligned_addr = align_up(self.current, Self::bin_size(index));
 
-        let alloc_end = aligned_addr + Allocator::bin_size(index);
+        let alloc_end = aligned_addr + Self::bin_size(index);
         let request_size = alloc_end - self.current;
-        match sbrk(request_size) {
-            Ok(_ptr) => {
+        match self.brk.sbrk(request_size as isize) {
+            Some(_brk) => {
                 self.current = alloc_end;
                 aligned_addr as *mut u8
             }
-            Err(_e) => core::ptr::null_mut()
+            None => core::ptr::null_mut()
         }
     }
 
@@ -103,7 +103,7 @@ impl LocalAlloc for Allocator {
     }
 }
 
-impl fmt::Debug for Allocator {
+impl<B: Break> fmt::Debug for Allocator<B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Allocator {{")?;
         writeln!(f, "  current: {}", self.current)?;
@@ -112,7 +112,7 @@ impl fmt::Debug for Allocator {
                 f,
                 "  bin#{} size={} = {:#?}",
                 i,
-                Allocator::bin_size(i),
+                Self::bin_size(i),
                 self.bins[i]
             )?;
         }
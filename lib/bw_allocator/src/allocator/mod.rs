@@ -1,4 +1,10 @@
+#[allow(dead_code)]
 pub mod bin;
+#[allow(dead_code)]
 pub mod linked_list;
 pub mod mutex;
-pub mod util;
\ No newline at end of file
+pub mod segfit;
+pub mod util;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,414 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use crate::allocator::util::*;
+use crate::{Break, LocalAlloc};
+use kernel_api::PAGE_SIZE;
+
+/// Size of a chunk header: the chunk's size and its flags.
+const HEADER_SIZE: usize = mem::size_of::<usize>();
+/// Alignment of chunk sizes and of the memory handed out.
+const ALIGN: usize = 16;
+/// Size of the smallest chunk: a header, the two links of a free list and a
+/// footer.
+const MIN_CHUNK: usize = 4 * HEADER_SIZE;
+/// Size of the largest chunk of an exact size class.
+const SMALL_MAX: usize = 512;
+const SMALL_COUNT: usize = (SMALL_MAX - MIN_CHUNK) / ALIGN + 1;
+/// log2 of `SMALL_MAX`: the first range class holds chunks of up to twice it.
+const LARGE_SHIFT: usize = 9;
+const CLASS_COUNT: usize = SMALL_COUNT + USER_VMM_ADDRESS_SIZE - LARGE_SHIFT;
+
+/// Free memory at the top of the heap is given back to the kernel once there
+/// is this much of it.
+const TRIM_THRESHOLD: usize = 2 * PAGE_SIZE;
+/// Free memory kept at the top of the heap when it is trimmed.
+const TOP_PAD: usize = PAGE_SIZE;
+
+/// Header flag of a chunk in use.
+const USED: usize = 0b01;
+/// Header flag of a chunk following a chunk in use.
+const PREV_USED: usize = 0b10;
+const FLAGS: usize = USED | PREV_USED;
+
+/// A segregated-fit allocator on top of the program break `brk`.
+///
+/// The heap is a run of chunks, each starting with a header holding its size
+/// and whether it and the chunk before it are in use. Free chunks also end
+/// with their size (a boundary tag), so a freed chunk is merged with both of
+/// its neighbours when they are free, and two free chunks are never adjacent.
+/// Free chunks are kept on doubly linked lists by size class:
+///   class 0..31 : chunks of exactly 32, 48, ..., 512 bytes
+///   class 31..  : chunks of 2^k + 1 to 2^(k+1) - 1 bytes, k >= 9
+///
+/// Small requests take the first chunk of their class or of the next class
+/// that is not empty. Large requests take the best fit of their class before
+/// moving up. Otherwise the chunk is carved from the top of the heap, and the
+/// break is moved up by whole pages as needed.
+///
+/// A freed chunk that reaches the top of the heap is merged into it, and the
+/// pages above the top are given back with a negative `sbrk` once more than
+/// `TRIM_THRESHOLD` bytes are unused there. Resizing grows a chunk into a free
+/// neighbour or the top of the heap, and shrinking frees its tail, so neither
+/// has to move the memory.
+pub struct Allocator<B: Break> {
+    brk: B,
+    start: usize,
+    /// Start of the memory not carved into chunks yet.
+    top: usize,
+    /// The program break.
+    end: usize,
+    /// Number of bytes in free chunks.
+    free: usize,
+    /// First chunk of the free list of each class, 0 if the list is empty.
+    lists: [usize; CLASS_COUNT],
+}
+
+impl<B: Break> Allocator<B> {
+    /// Creates a new allocator that will allocate memory from the heap,
+    /// starting at the current break of `brk`.
+    pub fn new(mut brk: B) -> Allocator<B> {
+        let end = brk.sbrk(0).unwrap();
+        // Chunks start right before an aligned address, so that is where the
+        // memory they hand out starts.
+        let start = align_up(end, ALIGN) + ALIGN - HEADER_SIZE;
+
+        Allocator {
+            brk,
+            start,
+            top: start,
+            end,
+            free: 0,
+            lists: [0; CLASS_COUNT],
+        }
+    }
+
+    /// Returns the size of the chunk handing out `size` bytes, or `None` if
+    /// it would not fit in the address space.
+    fn chunk_size(size: usize) -> Option<usize> {
+        if size >= 1 << USER_VMM_ADDRESS_SIZE {
+            return None;
+        }
+        Some(align_up((size + HEADER_SIZE).max(MIN_CHUNK), ALIGN))
+    }
+
+    /// Returns the class of the free list of a chunk of `size` bytes.
+    fn class_of(size: usize) -> usize {
+        if size <= SMALL_MAX {
+            (size - MIN_CHUNK) / ALIGN
+        } else {
+            let log2 = mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize;
+            (SMALL_COUNT + log2 - LARGE_SHIFT).min(CLASS_COUNT - 1)
+        }
+    }
+
+    /// Adds the free chunk `chunk` to the free list of its class.
+    unsafe fn link(&mut self, chunk: usize) {
+        let size = size_of(chunk);
+        let class = Self::class_of(size);
+        let next = self.lists[class];
+        *next_link(chunk) = next;
+        *prev_link(chunk) = 0;
+        if next != 0 {
+            *prev_link(next) = chunk;
+        }
+        self.lists[class] = chunk;
+        self.free += size;
+    }
+
+    /// Removes the free chunk `chunk` from the free list of its class.
+    unsafe fn unlink(&mut self, chunk: usize) {
+        let size = size_of(chunk);
+        let (next, prev) = (*next_link(chunk), *prev_link(chunk));
+        if prev == 0 {
+            self.lists[Self::class_of(size)] = next;
+        } else {
+            *next_link(prev) = next;
+        }
+        if next != 0 {
+            *prev_link(next) = prev;
+        }
+        self.free -= size;
+    }
+
+    /// Returns a free chunk of at least `size` bytes, if any.
+    unsafe fn find(&self, size: usize) -> Option<usize> {
+        let class = Self::class_of(size);
+        if class >= SMALL_COUNT {
+            // The chunks of a range class may be smaller than `size`.
+            let mut best: Option<usize> = None;
+            let mut chunk = self.lists[class];
+            while chunk != 0 {
+                let chunk_size = size_of(chunk);
+                if chunk_size >= size && best.map_or(true, |best| chunk_size < size_of(best)) {
+                    best = Some(chunk);
+                    if chunk_size == size {
+                        break;
+                    }
+                }
+                chunk = *next_link(chunk);
+            }
+            if best.is_some() {
+                return best;
+            }
+        } else if self.lists[class] != 0 {
+            return Some(self.lists[class]);
+        }
+        (class + 1..CLASS_COUNT)
+            .map(|class| self.lists[class])
+            .find(|&chunk| chunk != 0)
+    }
+
+    /// Takes the free chunk `chunk` for a chunk of `size` bytes, freeing what
+    /// is left of it.
+    unsafe fn take(&mut self, chunk: usize, size: usize) {
+        self.unlink(chunk);
+        *header(chunk) |= USED;
+        *header(chunk + size_of(chunk)) |= PREV_USED;
+        self.shrink(chunk, size);
+    }
+
+    /// Moves the break up until there are at least `size` bytes above the top
+    /// of the heap. Returns `false` if the kernel refused.
+    fn extend(&mut self, size: usize) -> bool {
+        let needed = (self.top + size).saturating_sub(self.end);
+        if needed == 0 {
+            return true;
+        }
+        match self.brk.sbrk(align_up(needed, PAGE_SIZE) as isize) {
+            Some(end) => {
+                self.end = end;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Carves a chunk of `size` bytes from the top of the heap.
+    unsafe fn carve(&mut self, size: usize) -> Option<usize> {
+        if !self.extend(size) {
+            return None;
+        }
+        // The chunk before the top is never free: it would have been merged
+        // into the top.
+        let chunk = self.top;
+        set_header(chunk, size, USED | PREV_USED);
+        self.top += size;
+        Some(chunk)
+    }
+
+    /// Gives the whole pages above the top of the heap back to the kernel if
+    /// there are enough of them.
+    fn trim(&mut self) {
+        if self.end.saturating_sub(self.top) < TRIM_THRESHOLD {
+            return;
+        }
+        let end = align_up(self.top + TOP_PAD, PAGE_SIZE);
+        if let Some(end) = self.brk.sbrk(-((self.end - end) as isize)) {
+            self.end = end;
+        }
+    }
+
+    /// Frees the chunk `chunk` in use, merging it with its free neighbours or
+    /// into the top of the heap.
+    unsafe fn release(&mut self, chunk: usize) {
+        let mut chunk = chunk;
+        let mut size = size_of(chunk);
+        if *header(chunk) & PREV_USED == 0 {
+            let prev_size = *header(chunk - HEADER_SIZE);
+            chunk -= prev_size;
+            self.unlink(chunk);
+            size += prev_size;
+        }
+
+        let next = chunk + size;
+        if next == self.top {
+            self.top = chunk;
+            self.trim();
+            return;
+        }
+        if *header(next) & USED == 0 {
+            self.unlink(next);
+            size += size_of(next);
+        } else {
+            *header(next) &= !PREV_USED;
+        }
+        // Either neighbour of a free chunk is in use.
+        set_header(chunk, size, PREV_USED);
+        *header(chunk + size - HEADER_SIZE) = size;
+        self.link(chunk);
+    }
+
+    /// Shrinks the chunk `chunk` in use to `size` bytes, freeing its tail if
+    /// that is large enough to be a chunk.
+    unsafe fn shrink(&mut self, chunk: usize, size: usize) {
+        let chunk_size = size_of(chunk);
+        if chunk_size - size < MIN_CHUNK {
+            return;
+        }
+        set_header(chunk, size, *header(chunk) & FLAGS);
+        let tail = chunk + size;
+        set_header(tail, chunk_size - size, USED | PREV_USED);
+        self.release(tail);
+    }
+}
+
+/// Returns the header of the chunk `chunk`.
+unsafe fn header(chunk: usize) -> *mut usize {
+    chunk as *mut usize
+}
+
+/// Returns the link to the next chunk on the free list of the free chunk
+/// `chunk`.
+unsafe fn next_link(chunk: usize) -> *mut usize {
+    (chunk + HEADER_SIZE) as *mut usize
+}
+
+/// Returns the link to the previous chunk on the free list of the free chunk
+/// `chunk`.
+unsafe fn prev_link(chunk: usize) -> *mut usize {
+    (chunk + 2 * HEADER_SIZE) as *mut usize
+}
+
+unsafe fn size_of(chunk: usize) -> usize {
+    *header(chunk) & !FLAGS
+}
+
+unsafe fn set_header(chunk: usize, size: usize, flags: usize) {
+    *header(chunk) = size | flags;
+}
+
+impl<B: Break> LocalAlloc for Allocator<B> {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// pointing to a block of storage suitable for holding an instance of
+    /// `layout`. In particular, the block will be at least `layout.size()`
+    /// bytes large and will be aligned to `layout.align()`. The returned block
+    /// of storage may or may not have its contents initialized or zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that `layout.size() > 0` and that
+    /// `layout.align()` is a power of two. Parameters not meeting these
+    /// conditions may result in undefined behavior.
+    ///
+    /// # Errors
+    ///
+    /// Returning null pointer (`core::ptr::null_mut`)
+    /// indicates that either memory is exhausted
+    /// or `layout` does not meet this allocator's
+    /// size or alignment constraints.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = match Self::chunk_size(layout.size()) {
+            Some(size) => size,
+            None => return ptr::null_mut(),
+        };
+        // Leave room to cut a free chunk off the front for a larger alignment.
+        let request = if layout.align() <= ALIGN {
+            size
+        } else {
+            size + layout.align() + MIN_CHUNK
+        };
+
+        let chunk = match self.find(request) {
+            Some(chunk) => {
+                self.take(chunk, request);
+                chunk
+            }
+            None => match self.carve(request) {
+                Some(chunk) => chunk,
+                None => return ptr::null_mut(),
+            },
+        };
+        if layout.align() <= ALIGN {
+            return (chunk + HEADER_SIZE) as *mut u8;
+        }
+
+        let addr = align_up(chunk + HEADER_SIZE + MIN_CHUNK, layout.align());
+        let aligned = addr - HEADER_SIZE;
+        set_header(aligned, chunk + size_of(chunk) - aligned, USED);
+        set_header(chunk, aligned - chunk, *header(chunk) & FLAGS);
+        self.release(chunk);
+        self.shrink(aligned, size);
+        addr as *mut u8
+    }
+
+    /// Deallocates the memory referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure the following:
+    ///
+    ///   * `ptr` must denote a block of memory currently allocated via this
+    ///     allocator
+    ///   * `layout` must properly represent the original layout used in the
+    ///     allocation call that returned `ptr`
+    ///
+    /// Parameters not meeting these conditions may result in undefined
+    /// behavior.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, _layout: Layout) {
+        self.release(ptr as usize - HEADER_SIZE);
+    }
+
+    /// Resizes the memory referenced by `ptr` to `new_size` bytes in place,
+    /// growing it into the free chunk after it or the top of the heap.
+    unsafe fn resize_in_place(&mut self, ptr: *mut u8, _layout: Layout, new_size: usize) -> bool {
+        let chunk = ptr as usize - HEADER_SIZE;
+        let size = match Self::chunk_size(new_size) {
+            Some(size) => size,
+            None => return false,
+        };
+        let chunk_size = size_of(chunk);
+        if size <= chunk_size {
+            self.shrink(chunk, size);
+            return true;
+        }
+
+        let next = chunk + chunk_size;
+        if next == self.top {
+            if !self.extend(size - chunk_size) {
+                return false;
+            }
+            set_header(chunk, size, *header(chunk) & FLAGS);
+            self.top = chunk + size;
+            return true;
+        }
+        if *header(next) & USED == 0 && chunk_size + size_of(next) >= size {
+            let merged = chunk_size + size_of(next);
+            self.unlink(next);
+            set_header(chunk, merged, *header(chunk) & FLAGS);
+            *header(chunk + merged) |= PREV_USED;
+            self.shrink(chunk, size);
+            return true;
+        }
+        false
+    }
+}
+
+impl<B: Break> fmt::Debug for Allocator<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Allocator {{")?;
+        writeln!(f, "  start: {:#x}", self.start)?;
+        writeln!(f, "  top: {:#x}", self.top)?;
+        writeln!(f, "  end: {:#x}", self.end)?;
+        writeln!(f, "  free mem: {}", self.free)?;
+        for class in 0..CLASS_COUNT {
+            let mut count = 0;
+            let mut chunk = self.lists[class];
+            while chunk != 0 {
+                count += 1;
+                chunk = unsafe { *next_link(chunk) };
+            }
+            if count != 0 {
+                writeln!(f, "  class {}: {} free", class, count)?;
+            }
+        }
+        writeln!(f, "}}")?;
+
+        Ok(())
+    }
+}
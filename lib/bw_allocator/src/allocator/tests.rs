@@ -0,0 +1,238 @@
+use core::alloc::Layout;
+use core::cell::Cell;
+
+use kernel_api::PAGE_SIZE;
+
+use crate::allocator::segfit::Allocator;
+use crate::{Break, LocalAlloc};
+
+/// Heap memory standing in for the program break of a process. The break
+/// starts at the bottom of `size` bytes aligned to a page.
+struct Heap {
+    mem: *mut u8,
+    layout: Layout,
+    brk: Cell<usize>,
+}
+
+impl Heap {
+    fn new(size: usize) -> Heap {
+        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
+        let mem = unsafe { std::alloc::alloc(layout) };
+        assert!(!mem.is_null(), "out of host memory");
+        Heap {
+            mem,
+            layout,
+            brk: Cell::new(mem as usize),
+        }
+    }
+
+    fn start(&self) -> usize {
+        self.mem as usize
+    }
+
+    fn end(&self) -> usize {
+        self.start() + self.layout.size()
+    }
+
+    fn brk(&self) -> usize {
+        self.brk.get()
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.mem, self.layout) };
+    }
+}
+
+impl<'a> Break for &'a Heap {
+    fn sbrk(&mut self, increment: isize) -> Option<usize> {
+        let brk = (self.brk() as isize + increment) as usize;
+        if brk < self.start() || brk > self.end() {
+            return None;
+        }
+        self.brk.set(brk);
+        Some(brk)
+    }
+}
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+fn alloc(allocator: &mut Allocator<&Heap>, size: usize, align: usize) -> usize {
+    let ptr = unsafe { allocator.alloc(layout(size, align)) };
+    assert!(!ptr.is_null(), "allocation of {} bytes failed", size);
+    ptr as usize
+}
+
+fn dealloc(allocator: &mut Allocator<&Heap>, ptr: usize, size: usize, align: usize) {
+    unsafe { allocator.dealloc(ptr as *mut u8, layout(size, align)) };
+}
+
+fn resize(allocator: &mut Allocator<&Heap>, ptr: usize, size: usize, new_size: usize) -> bool {
+    unsafe { allocator.resize_in_place(ptr as *mut u8, layout(size, 8), new_size) }
+}
+
+#[test]
+fn carve_from_break() {
+    let heap = Heap::new(4 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let x = alloc(&mut a, 64, 8);
+    let y = alloc(&mut a, 64, 8);
+    assert_eq!(x % 16, 0);
+    assert!(x >= heap.start() && y + 64 <= heap.brk());
+    assert_eq!(heap.brk(), heap.start() + PAGE_SIZE);
+
+    let z = alloc(&mut a, 2 * PAGE_SIZE, 8);
+    assert!(z + 2 * PAGE_SIZE <= heap.brk());
+    assert_eq!(heap.brk() % PAGE_SIZE, 0);
+}
+
+#[test]
+fn refused_by_break() {
+    let heap = Heap::new(2 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let ptr = unsafe { a.alloc(layout(2 * PAGE_SIZE, 8)) };
+    assert!(ptr.is_null());
+    assert_eq!(heap.brk(), heap.start());
+
+    // A refusal leaves the allocator usable.
+    alloc(&mut a, 64, 8);
+}
+
+#[test]
+fn split_free_chunk() {
+    let heap = Heap::new(4 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let _x = alloc(&mut a, 64, 8);
+    let y = alloc(&mut a, 64, 8);
+    let _z = alloc(&mut a, 64, 8);
+
+    dealloc(&mut a, y, 64, 8);
+    assert_eq!(alloc(&mut a, 64, 8), y);
+
+    // An 80 byte chunk is split into a 32 byte one and a 48 byte one.
+    dealloc(&mut a, y, 64, 8);
+    assert_eq!(alloc(&mut a, 16, 8), y);
+    assert_eq!(alloc(&mut a, 32, 8), y + 32);
+}
+
+#[test]
+fn coalesce_free_chunks() {
+    let heap = Heap::new(4 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let w = alloc(&mut a, 64, 8);
+    let x = alloc(&mut a, 64, 8);
+    let y = alloc(&mut a, 64, 8);
+    let z = alloc(&mut a, 64, 8);
+    let _guard = alloc(&mut a, 64, 8);
+
+    // With the chunk before it.
+    dealloc(&mut a, w, 64, 8);
+    dealloc(&mut a, x, 64, 8);
+    assert_eq!(alloc(&mut a, 144, 8), w);
+
+    // With the chunk after it, and with both.
+    dealloc(&mut a, z, 64, 8);
+    dealloc(&mut a, y, 64, 8);
+    assert_eq!(alloc(&mut a, 144, 8), y);
+
+    dealloc(&mut a, w, 144, 8);
+    let hole = alloc(&mut a, 64, 8);
+    dealloc(&mut a, y, 144, 8);
+    dealloc(&mut a, hole, 64, 8);
+    assert_eq!(alloc(&mut a, 304, 8), w);
+}
+
+#[test]
+fn merge_into_top() {
+    let heap = Heap::new(4 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let x = alloc(&mut a, 64, 8);
+    let y = alloc(&mut a, 64, 8);
+    dealloc(&mut a, x, 64, 8);
+    dealloc(&mut a, y, 64, 8);
+    assert_eq!(alloc(&mut a, 1000, 8), x);
+}
+
+#[test]
+fn trim_break() {
+    let heap = Heap::new(16 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let small = alloc(&mut a, 64, 8);
+    let big = alloc(&mut a, 8 * PAGE_SIZE, 8);
+    assert!(heap.brk() >= big + 8 * PAGE_SIZE);
+
+    // The pages above the top of the heap are given back, but for a page of
+    // padding.
+    dealloc(&mut a, big, 8 * PAGE_SIZE, 8);
+    assert_eq!(heap.brk(), heap.start() + 2 * PAGE_SIZE);
+
+    // Less than the threshold is kept.
+    let brk = heap.brk();
+    let x = alloc(&mut a, PAGE_SIZE / 2, 8);
+    dealloc(&mut a, x, PAGE_SIZE / 2, 8);
+    assert_eq!(heap.brk(), brk);
+
+    dealloc(&mut a, small, 64, 8);
+    assert_eq!(heap.brk(), brk);
+    assert_eq!(alloc(&mut a, 64, 8), small);
+}
+
+#[test]
+fn resize_in_place() {
+    let heap = Heap::new(4 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    // Into the top of the heap.
+    let p = alloc(&mut a, 64, 8);
+    assert!(resize(&mut a, p, 64, 1000));
+    let q = alloc(&mut a, 64, 8);
+    assert_eq!(q, p + 1008);
+
+    // Shrinking frees the tail.
+    assert!(resize(&mut a, p, 1000, 64));
+    let r = alloc(&mut a, 64, 8);
+    assert_eq!(r, p + 80);
+
+    // Into the free chunk after it.
+    assert!(resize(&mut a, r, 64, 800));
+    assert_eq!(alloc(&mut a, 64, 8), r + 816);
+
+    // Not over a chunk in use.
+    assert!(!resize(&mut a, p, 64, 200));
+    assert!(!resize(&mut a, r, 800, 2000));
+}
+
+#[test]
+fn over_aligned() {
+    let heap = Heap::new(8 * PAGE_SIZE);
+    let mut a = Allocator::new(&heap);
+
+    let p = alloc(&mut a, 100, 4096);
+    assert_eq!(p % 4096, 0);
+
+    // The memory cut off in front of it is reused.
+    let small = alloc(&mut a, 64, 8);
+    assert!(small < p);
+
+    let q = alloc(&mut a, 64, PAGE_SIZE);
+    assert_eq!(q % PAGE_SIZE, 0);
+    assert!(q > p);
+
+    unsafe {
+        core::ptr::write_bytes(p as *mut u8, 0xaa, 100);
+        core::ptr::write_bytes(q as *mut u8, 0xbb, 64);
+    }
+    dealloc(&mut a, p, 100, 4096);
+    dealloc(&mut a, q, 64, PAGE_SIZE);
+    dealloc(&mut a, small, 64, 8);
+    assert_eq!(alloc(&mut a, 64, 8), small);
+}
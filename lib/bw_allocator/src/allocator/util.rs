@@ -1,3 +1,7 @@
+/// Width of user virtual addresses. Nothing larger than the address space
+/// can be allocated.
+pub const USER_VMM_ADDRESS_SIZE: usize = 64 - kernel_api::USER_MASK_BITS;
+
 /// Align `addr` downwards to the nearest multiple of `align`.
 ///
 /// The returned usize is always <= `addr.`
@@ -0,0 +1,74 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+
+use kernel_api::syscall::sbrk;
+
+use crate::allocator::{mutex::Mutex, segfit};
+use crate::{Break, LocalAlloc};
+
+type AllocatorImpl = segfit::Allocator<Sbrk>;
+
+/// The program break of this process, moved with the `sbrk` system call.
+pub struct Sbrk;
+
+impl Break for Sbrk {
+    fn sbrk(&mut self, increment: isize) -> Option<usize> {
+        sbrk(increment).ok().map(|brk| brk as usize)
+    }
+}
+
+/// Thread-safe (locking) wrapper around a particular memory allocator.
+pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+
+impl Allocator {
+    /// Returns an `Allocator`.
+    pub const fn new() -> Self {
+        Allocator(Mutex::new(None))
+    }
+
+    /// Calls `f` with the allocator, initializing it on first use.
+    fn critical<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut AllocatorImpl) -> R,
+    {
+        let mut guard = self.0.lock();
+        if guard.is_none() {
+            *guard = Some(AllocatorImpl::new(Sbrk));
+        }
+        f(guard.as_mut().expect("allocator uninitialized"))
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.critical(|allocator| allocator.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.critical(|allocator| allocator.dealloc(ptr, layout));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if self.critical(|allocator| allocator.resize_in_place(ptr, layout, new_size)) {
+            return ptr;
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.lock().as_mut() {
+            Some(ref alloc) => write!(f, "{:?}", alloc)?,
+            None => write!(f, "Not yet initialized")?,
+        }
+        Ok(())
+    }
+}
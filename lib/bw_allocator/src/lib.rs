@@ -1,67 +1,40 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(alloc_error_handler)]
 #![feature(optin_builtin_traits)]
 
 mod allocator;
-use allocator::{bin, mutex::Mutex};
+// System calls exist only on the target, so the global allocator does too.
+#[cfg(target_arch = "aarch64")]
+mod global;
+#[cfg(target_arch = "aarch64")]
+pub use global::{Allocator, Sbrk};
 
-type AllocatorImpl = bin::Allocator;
-
-use core::alloc::{GlobalAlloc, Layout};
-use core::fmt;
+use core::alloc::Layout;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
 pub trait LocalAlloc {
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
-}
-
-/// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
-
-impl Allocator {
-    /// Returns an `Allocator`.
-    pub const fn new() -> Self {
-        Allocator(Mutex::new(None))
-    }
-}
-
-unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if self.0.lock().is_none() {
-            *self.0.lock() = Some(AllocatorImpl::new());
-        }
-        self.0
-            .lock()
-            .as_mut()
-            .expect("allocator uninitialized")
-            .alloc(layout)
-    }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if self.0.lock().is_none() {
-            *self.0.lock() = Some(AllocatorImpl::new());
-        }
-        self.0
-            .lock()
-            .as_mut()
-            .expect("allocator uninitialized")
-            .dealloc(ptr, layout);
+    /// Resizes the memory referenced by `ptr`, allocated for `layout`, to
+    /// `new_size` bytes without moving it. Returns `false`, leaving the
+    /// memory as it was, if that is not possible.
+    unsafe fn resize_in_place(&mut self, _ptr: *mut u8, _layout: Layout, _new_size: usize) -> bool {
+        false
     }
 }
 
-impl fmt::Debug for Allocator {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.0.lock().as_mut() {
-            Some(ref alloc) => write!(f, "{:?}", alloc)?,
-            None => write!(f, "Not yet initialized")?,
-        }
-        Ok(())
-    }
+/// The memory the heap grows into: the program break of a process.
+pub trait Break {
+    /// Moves the break by `increment` bytes and returns the new break, or
+    /// `None` if it cannot be moved. A negative `increment` gives the memory
+    /// above the new break back. `sbrk(0)` returns the current break.
+    fn sbrk(&mut self, increment: isize) -> Option<usize>;
 }
 
+#[cfg(not(test))]
 #[alloc_error_handler]
 pub fn oom(_layout: Layout) -> ! {
     panic!("OOM");
-}
\ No newline at end of file
+}
@@ -6,7 +6,7 @@ use shim::ioerr;
 use crate::traits;
 use crate::vfat::{Cluster, Metadata, VFatHandle, Status};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct File<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub first_cluster: Cluster,
@@ -2,10 +2,11 @@
 #![no_std]
 
 use core::fmt;
+use core::time::Duration;
 
 use shim::io;
 
-#[cfg(feature = "user-space")]
+#[cfg(all(feature = "user-space", target_arch = "aarch64"))]
 pub mod syscall;
 pub type OsResult<T> = core::result::Result<T, OsError>;
 
@@ -27,6 +28,7 @@ pub enum OsError {
     IoErrorInvalidData = 103,
     IoErrorInvalidInput = 104,
     IoErrorTimedOut = 105,
+    IoErrorWouldBlock = 106,
 
     InvalidSocket = 200,
     IllegalSocketOperation = 201,
@@ -49,6 +51,8 @@ impl core::convert::From<u64> for OsError {
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
+            106 => OsError::IoErrorWouldBlock,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
@@ -65,6 +69,7 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidData => OsError::IoErrorInvalidData,
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
+            io::ErrorKind::WouldBlock => OsError::IoErrorWouldBlock,
             io::ErrorKind::NotFound => OsError::NoEntry,
             _ => OsError::IoError,
         }
@@ -78,6 +83,12 @@ impl core::convert::From<OsError> for fmt::Error {
 }
 
 
+/// Size of a page of user memory. The heap grows and shrinks by whole pages.
+pub const PAGE_SIZE: usize = 64 * 1024;
+/// Number of high bits set in every user virtual address: user addresses are
+/// `64 - USER_MASK_BITS` bits wide.
+pub const USER_MASK_BITS: usize = 16;
+
 pub const NR_SLEEP: usize = 1;
 pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
@@ -88,6 +99,63 @@ pub const NR_SBRK: usize = 7;
 pub const NR_RAND: usize = 8;
 pub const NR_RRAND: usize = 9;
 pub const NR_ENTROPY: usize = 10;
+pub const NR_POLL: usize = 11;
+pub const NR_SET_NONBLOCKING: usize = 12;
+pub const NR_READ: usize = 13;
+pub const NR_CONSOLE_MODE: usize = 14;
+pub const NR_CONSOLE_RELEASE: usize = 15;
+pub const NR_REBOOT: usize = 16;
+pub const NR_HALT: usize = 17;
+
+/// Descriptor of the console input.
+pub const STDIN: u64 = 0;
+/// Descriptor of the console output.
+pub const STDOUT: u64 = 1;
+/// Descriptor of the console error output.
+pub const STDERR: u64 = 2;
+
+/// Console input mode flag: edit input a line at a time. Without it, input is
+/// read byte by byte as it arrives (raw mode).
+pub const CONSOLE_CANONICAL: u64 = 0x1;
+/// Console input mode flag: echo input back to the console.
+pub const CONSOLE_ECHO: u64 = 0x2;
+/// The console input mode a process starts with.
+pub const CONSOLE_DEFAULT: u64 = CONSOLE_CANONICAL | CONSOLE_ECHO;
+
+/// There is data to read.
+pub const POLLIN: u16 = 0x01;
+/// Writing now will not block.
+pub const POLLOUT: u16 = 0x04;
+/// An error condition occurred on the descriptor (`revents` only). Kept for
+/// compatibility: the kernel does not report it, a reset connection is
+/// reported as `POLLHUP`.
+pub const POLLERR: u16 = 0x08;
+/// The peer closed its end of the descriptor (`revents` only).
+pub const POLLHUP: u16 = 0x10;
+/// The descriptor is not open (`revents` only).
+pub const POLLNVAL: u16 = 0x20;
+
+/// A descriptor and the events to wait for, as passed to `poll`.
+///
+/// The kernel fills `revents` with the subset of `events` (plus `POLLHUP` and
+/// `POLLNVAL`) that are ready on `fd`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PollFd {
+    pub fd: u64,
+    pub events: u16,
+    pub revents: u16,
+}
+
+impl PollFd {
+    pub fn new(fd: u64, events: u16) -> Self {
+        PollFd {
+            fd,
+            events,
+            revents: 0,
+        }
+    }
+}
 
 
 #[derive(Clone, Copy, Debug)]
@@ -132,9 +200,85 @@ impl fmt::Debug for IpAddr {
     }
 }
 
+pub const NR_GPIO_CLAIM: usize = 30;
+pub const NR_GPIO_RELEASE: usize = 31;
+pub const NR_GPIO_CONFIGURE: usize = 32;
+pub const NR_GPIO_WRITE: usize = 33;
+pub const NR_GPIO_READ: usize = 34;
+pub const NR_GPIO_WAIT: usize = 35;
+pub const NR_I2C_TRANSFER: usize = 40;
+pub const NR_SPI_TRANSFER: usize = 41;
+
+/// GPIO pin function: input.
+pub const GPIO_INPUT: u64 = 0;
+/// GPIO pin function: output.
+pub const GPIO_OUTPUT: u64 = 1;
+/// GPIO pin functions: alternative functions 0 to 5.
+pub const GPIO_ALT0: u64 = 2;
+pub const GPIO_ALT1: u64 = 3;
+pub const GPIO_ALT2: u64 = 4;
+pub const GPIO_ALT3: u64 = 5;
+pub const GPIO_ALT4: u64 = 6;
+pub const GPIO_ALT5: u64 = 7;
+
+/// GPIO pull-up/pull-down setting: neither.
+pub const GPIO_PULL_OFF: u64 = 0;
+/// GPIO pull-up/pull-down setting: pull the pin low.
+pub const GPIO_PULL_DOWN: u64 = 1;
+/// GPIO pull-up/pull-down setting: pull the pin high.
+pub const GPIO_PULL_UP: u64 = 2;
+
+/// GPIO edge flag: a low to high transition.
+pub const GPIO_RISING: u64 = 0x1;
+/// GPIO edge flag: a high to low transition.
+pub const GPIO_FALLING: u64 = 0x2;
+
+/// SPI transfer flags: clock polarity and phase, modes 0 to 3.
+pub const SPI_MODE_0: u64 = 0x0;
+pub const SPI_MODE_1: u64 = 0x1;
+pub const SPI_MODE_2: u64 = 0x2;
+pub const SPI_MODE_3: u64 = 0x3;
+pub const SPI_MODE_MASK: u64 = 0x3;
+/// SPI transfer flags: the chip select line asserted, CE0 (GPIO 8), CE1
+/// (GPIO 7) or none.
+pub const SPI_CE0: u64 = 0x0;
+pub const SPI_CE1: u64 = 0x4;
+pub const SPI_NO_CE: u64 = 0x8;
+pub const SPI_CE_MASK: u64 = 0xc;
+/// SPI transfer flag: the chip select line is active high.
+pub const SPI_CS_HIGH: u64 = 0x10;
+
+pub const NR_OPEN: usize = 50;
+pub const NR_CLOSE: usize = 51;
+pub const NR_MMAP: usize = 52;
+pub const NR_MUNMAP: usize = 53;
+
+/// `mmap` protection flags: the pages may be read, written or executed.
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+/// `mmap` flag: the pages are private to the process. Required, as shared
+/// mappings are not supported.
+pub const MAP_PRIVATE: u64 = 0x02;
+/// `mmap` flag: place the mapping exactly at `addr`, replacing the mappings
+/// there.
+pub const MAP_FIXED: u64 = 0x10;
+/// `mmap` flag: the pages are zero-filled instead of read from a file, and
+/// `fd` and `offset` are ignored.
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
 pub const NR_SOCK_CREATE: usize = 20;
 pub const NR_SOCK_STATUS: usize = 21;
 pub const NR_SOCK_CONNECT: usize = 22;
 pub const NR_SOCK_LISTEN: usize = 23;
 pub const NR_SOCK_SEND: usize = 24;
 pub const NR_SOCK_RECV: usize = 25;
+pub const NR_PING: usize = 26;
+
+/// The result of a successful `ping`.
+#[derive(Debug, Clone, Copy)]
+pub struct EchoReply {
+    pub rtt: Duration,
+    pub ttl: u8,
+}
@@ -64,6 +64,41 @@ pub fn exit() -> ! {
     loop { }
 }
 
+/// Resets the board. Only returns, with `OsError::NoAccess`, if the calling
+/// process does not own the console input.
+pub fn reboot() -> OsError {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1
+              mov $0, x7"
+             : "=r"(ecode)
+             : "i"(NR_REBOOT)
+             : "x7"
+             : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
+/// Stops the board until its power is cycled. Only returns, with
+/// `OsError::NoAccess`, if the calling process does not own the console
+/// input.
+pub fn halt() -> OsError {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("svc $1
+              mov $0, x7"
+             : "=r"(ecode)
+             : "i"(NR_HALT)
+             : "x7"
+             : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
 pub fn write(b: u8) {
     if !b.is_ascii() {
         panic!("{} is not valid ascii", b)
@@ -81,6 +116,67 @@ pub fn write(b: u8) {
     }
 }
 
+/// Reads console input from descriptor `fd` (only `STDIN`) into `buf`.
+///
+/// The calling process takes over the console input unless another process
+/// owns it. In canonical mode at most one line is read. In blocking mode (the
+/// default) this waits until input is ready and the console input is free.
+/// Returns the number of bytes read, 0 meaning end-of-file. In non-blocking
+/// mode `OsError::IoErrorWouldBlock` is returned if no input is ready.
+pub fn read(fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+    let buf_ptr = buf.as_mut_ptr() as u64;
+    let len = buf.len() as u64;
+    let mut ecode: u64;
+    let mut bytes: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes), "=r"(ecode)
+             : "r"(fd), "r"(buf_ptr), "r"(len), "i"(NR_READ)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, bytes as usize)
+}
+
+/// Sets the console input mode to `flags`, a combination of
+/// `CONSOLE_CANONICAL` and `CONSOLE_ECHO`.
+pub fn console_mode(flags: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(flags), "i"(NR_CONSOLE_MODE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Gives up the console input so that other processes can read it.
+pub fn console_release() {
+    let mut _ecode: u64;
+
+    unsafe {
+        asm!("svc $1
+              mov $0, x7"
+             : "=r"(_ecode)
+             : "i"(NR_CONSOLE_RELEASE)
+             : "x7"
+             : "volatile");
+    }
+}
+
 // pub fn write_str(msg: &str) -> OsResult<usize> {
 pub fn write_str(msg: &str) {
     let msg_ptr = msg.as_ptr() as u64;
@@ -120,7 +216,10 @@ pub fn getpid() -> u64 {
     pid
 }
 
-pub fn sbrk(size: usize) -> OsResult<*mut u8> {
+/// Moves the program break by `increment` bytes and returns the new break.
+/// A negative `increment` gives the pages wholly above the new break back to
+/// the kernel. `sbrk(0)` returns the current break.
+pub fn sbrk(increment: isize) -> OsResult<*mut u8> {
     let mut ecode: u64;
     let mut ptr: u64;
 
@@ -130,7 +229,7 @@ pub fn sbrk(size: usize) -> OsResult<*mut u8> {
               mov $0, x0
               mov $1, x7"
              : "=r"(ptr), "=r"(ecode)
-             : "r"(size as u64), "i"(NR_SBRK)
+             : "r"(increment as u64), "i"(NR_SBRK)
              : "x0", "x7"
              : "volatile");
     }
@@ -188,6 +287,60 @@ pub fn entropy() -> u32 {
     entropy as u32
 }
 
+/// Waits until one of the descriptors in `fds` is ready or `timeout` elapses.
+/// A `timeout` of `None` waits forever.
+///
+/// Returns the number of descriptors with a non-zero `revents`.
+pub fn poll(fds: &mut [PollFd], timeout: Option<Duration>) -> OsResult<usize> {
+    let timeout_ms = match timeout {
+        Some(span) => {
+            if span.as_millis() >= core::u64::MAX as u128 {
+                panic!("too big!");
+            }
+            span.as_millis() as u64
+        }
+        None => core::u64::MAX,
+    };
+    let fds_ptr = fds.as_mut_ptr() as u64;
+    let nfds = fds.len() as u64;
+    let mut ecode: u64;
+    let mut ready: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(ready), "=r"(ecode)
+             : "r"(fds_ptr), "r"(nfds), "r"(timeout_ms), "i"(NR_POLL)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ready as usize)
+}
+
+/// Switches descriptor `fd` between blocking (the default) and non-blocking
+/// mode.
+pub fn set_nonblocking(fd: u64, nonblocking: bool) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(fd), "r"(nonblocking as u64), "i"(NR_SET_NONBLOCKING)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
 pub fn sock_create() -> SocketDescriptor {
     let mut _ecode: u64;
     let mut sid: u64;
@@ -308,6 +461,308 @@ pub fn sock_recv(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<usize
     err_or!(ecode, bytes)
 }
 
+pub fn ping(ip: (u8, u8, u8, u8), seq_no: u16, timeout: Duration) -> OsResult<EchoReply> {
+    if timeout.as_millis() > core::u32::MAX as u128 {
+        panic!("too big!");
+    }
+
+    let ip = u32::from_be_bytes([ip.0, ip.1, ip.2, ip.3]);
+    let timeout_ms = timeout.as_millis() as u64;
+    let mut ecode: u64;
+    let mut rtt_us: u64;
+    let mut ttl: u64;
+
+    unsafe {
+        asm!("mov x0, $3
+              mov x1, $4
+              mov x2, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+             : "=r"(rtt_us), "=r"(ttl), "=r"(ecode)
+             : "r"(ip), "r"(seq_no), "r"(timeout_ms), "i"(NR_PING)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, EchoReply { rtt: Duration::from_micros(rtt_us), ttl: ttl as u8 })
+}
+
+/// Claims GPIO pin `pin` for the calling process and makes it an input. The
+/// pin is released when the process exits.
+pub fn gpio_claim(pin: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(pin), "i"(NR_GPIO_CLAIM)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Releases GPIO pin `pin` so that other processes can claim it.
+pub fn gpio_release(pin: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(pin), "i"(NR_GPIO_RELEASE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Sets the function (`GPIO_INPUT`, `GPIO_OUTPUT` or `GPIO_ALTn`) and the
+/// pull-up/pull-down resistor (`GPIO_PULL_*`) of a claimed pin.
+pub fn gpio_configure(pin: u64, function: u64, pull: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(pin), "r"(function), "r"(pull), "i"(NR_GPIO_CONFIGURE)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Drives a claimed output pin high if `level` is `true` and low otherwise.
+pub fn gpio_write(pin: u64, level: bool) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(pin), "r"(level as u64), "i"(NR_GPIO_WRITE)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Returns the level of a claimed pin: `true` if it is high.
+pub fn gpio_read(pin: u64) -> OsResult<bool> {
+    let mut ecode: u64;
+    let mut level: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(level), "=r"(ecode)
+             : "r"(pin), "i"(NR_GPIO_READ)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, level != 0)
+}
+
+/// Waits until one of `edges` (`GPIO_RISING` and/or `GPIO_FALLING`) is
+/// detected on a claimed input pin, or `timeout` elapses. A `timeout` of
+/// `None` waits forever. Edges detected since the previous wait with the same
+/// `edges` end the wait right away.
+///
+/// Returns the level of the pin after the edge.
+pub fn gpio_wait(pin: u64, edges: u64, timeout: Option<Duration>) -> OsResult<bool> {
+    let timeout_ms = match timeout {
+        Some(span) => {
+            if span.as_millis() >= core::u64::MAX as u128 {
+                panic!("too big!");
+            }
+            span.as_millis() as u64
+        }
+        None => core::u64::MAX,
+    };
+    let mut ecode: u64;
+    let mut level: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(level), "=r"(ecode)
+             : "r"(pin), "r"(edges), "r"(timeout_ms), "i"(NR_GPIO_WAIT)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, level != 0)
+}
+
+/// Writes `data` to the device at the 7-bit address `addr` on the kernel's
+/// I2C bus, then reads `buf.len()` bytes from it into `buf` after a repeated
+/// start. Either buffer may be empty, but not both. When both are used,
+/// `data` can hold at most 16 bytes.
+pub fn i2c_transfer(addr: u64, data: &[u8], buf: &mut [u8]) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              mov x4, $5
+              svc $6
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(addr), "r"(data.as_ptr() as u64), "r"(data.len() as u64),
+               "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64), "i"(NR_I2C_TRANSFER)
+             : "x0", "x1", "x2", "x3", "x4", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Sends `tx` on the SPI bus while receiving as many bytes into `rx`, with
+/// the `SPI_*` settings `flags` and a clock of at most `hz`. Blocks until the
+/// transfer is done.
+pub fn spi_transfer(flags: u64, hz: u64, tx: &[u8], rx: &mut [u8]) -> OsResult<()> {
+    if tx.len() != rx.len() {
+        return Err(OsError::InvalidArgument);
+    }
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              mov x4, $5
+              svc $6
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(flags), "r"(hz), "r"(tx.as_ptr() as u64),
+               "r"(rx.as_mut_ptr() as u64), "r"(tx.len() as u64), "i"(NR_SPI_TRANSFER)
+             : "x0", "x1", "x2", "x3", "x4", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Opens the file at the absolute `path` for reading and returns its
+/// descriptor, which can be mapped with `mmap`.
+pub fn open(path: &str) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut fd: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(fd), "=r"(ecode)
+             : "r"(path.as_ptr() as u64), "r"(path.len() as u64), "i"(NR_OPEN)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, fd)
+}
+
+/// Closes the file descriptor `fd` returned by `open`. Mappings of the file
+/// stay valid.
+pub fn close(fd: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(fd), "i"(NR_CLOSE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Maps `len` bytes with the `PROT_*` protection `prot` and returns their
+/// address. With `MAP_ANONYMOUS` in `flags` the pages are zero-filled;
+/// otherwise they are read from the file `fd` starting at `offset`, which
+/// must be a multiple of `PAGE_SIZE`, and cannot be written. `addr` is only a
+/// hint unless `flags` has `MAP_FIXED`. The pages are filled as they are
+/// first touched.
+pub fn mmap(
+    addr: usize,
+    len: usize,
+    prot: u64,
+    flags: u64,
+    fd: u64,
+    offset: usize,
+) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut ptr: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              mov x3, $5
+              mov x4, $6
+              mov x5, $7
+              svc $8
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(ptr), "=r"(ecode)
+             : "r"(addr as u64), "r"(len as u64), "r"(prot), "r"(flags),
+               "r"(fd), "r"(offset as u64), "i"(NR_MMAP)
+             : "x0", "x1", "x2", "x3", "x4", "x5", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ptr as *mut u8)
+}
+
+/// Unmaps the pages of the `len` bytes at `addr`, which must be a multiple of
+/// `PAGE_SIZE`. Pages in the range that are not mapped are skipped.
+pub fn munmap(addr: *mut u8, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(addr as u64), "r"(len as u64), "i"(NR_MUNMAP)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
 struct Console;
 
 impl fmt::Write for Console {
@@ -0,0 +1,268 @@
+use core::fmt;
+use core::ptr;
+
+use crate::mailbox::{self, Message, PixelOrder};
+use crate::mailbox::{AllocateBuffer, GetPitch, SetDepth, SetPhysicalSize};
+use crate::mailbox::{SetPixelOrder, SetVirtualOffset, SetVirtualSize};
+
+mod font;
+mod text;
+
+pub use self::text::TextConsole;
+
+/// Bits per pixel of the framebuffer.
+const DEPTH: u32 = 32;
+
+/// Mask turning a VideoCore bus address into an ARM physical address.
+const BUS_ADDRESS_MASK: u32 = 0x3fff_ffff;
+
+/// A 24-bit color.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(0xff, 0xff, 0xff);
+    pub const GRAY: Color = Color::rgb(0xaa, 0xaa, 0xaa);
+    pub const RED: Color = Color::rgb(0xaa, 0, 0);
+    pub const GREEN: Color = Color::rgb(0, 0xaa, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 0xaa);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+}
+
+/// A 32-bit framebuffer allocated by the VideoCore.
+///
+/// Drawing outside of the framebuffer is clipped. The kernel maps the
+/// VideoCore's memory uncached, so everything drawn shows up right away.
+pub struct FrameBuffer {
+    base: usize,
+    size: usize,
+    width: usize,
+    height: usize,
+    /// Bytes per line.
+    pitch: usize,
+    order: PixelOrder,
+}
+
+impl FrameBuffer {
+    /// Asks the VideoCore for a `width` x `height` framebuffer with 32 bits
+    /// per pixel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the VideoCore could not allocate the framebuffer,
+    /// e.g. because no display is attached.
+    pub fn new(width: u32, height: u32) -> mailbox::Result<FrameBuffer> {
+        let mut message = Message::new();
+        let physical = message.push(SetPhysicalSize { width, height })?;
+        message.push(SetVirtualSize { width, height })?;
+        message.push(SetVirtualOffset { x: 0, y: 0 })?;
+        let depth = message.push(SetDepth(DEPTH))?;
+        let order = message.push(SetPixelOrder(PixelOrder::Rgb))?;
+        let buffer = message.push(AllocateBuffer { alignment: 16 })?;
+        let pitch = message.push(GetPitch)?;
+        message.send()?;
+
+        let (width, height) = message.get(&physical)?;
+        let buffer = message.get(&buffer)?;
+        if message.get(&depth)? != DEPTH || buffer.base == 0 {
+            return Err(mailbox::Error::RequestFailed);
+        }
+        Ok(FrameBuffer {
+            base: (buffer.base & BUS_ADDRESS_MASK) as usize,
+            size: buffer.size as usize,
+            width: width as usize,
+            height: height as usize,
+            pitch: message.get(&pitch)? as usize,
+            order: message.get(&order)?,
+        })
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the ARM physical address of the framebuffer and its size in
+    /// bytes.
+    pub fn region(&self) -> (usize, usize) {
+        (self.base, self.size)
+    }
+
+    /// Returns the value stored in memory for `color`.
+    fn encode(&self, color: Color) -> u32 {
+        let (first, third) = match self.order {
+            PixelOrder::Rgb => (color.r, color.b),
+            PixelOrder::Bgr => (color.b, color.r),
+        };
+        first as u32 | (color.g as u32) << 8 | (third as u32) << 16
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        (self.base + y * self.pitch + x * 4) as *mut u32
+    }
+
+    /// Sets the pixel at (`x`, `y`) to `color`.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let value = self.encode(color);
+            unsafe { ptr::write_volatile(self.pixel_ptr(x, y), value) };
+        }
+    }
+
+    /// Fills the `width` x `height` rectangle at (`x`, `y`) with `color`.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let value = self.encode(color);
+        let x_end = self.width.min(x.saturating_add(width));
+        let y_end = self.height.min(y.saturating_add(height));
+        if x >= x_end {
+            return;
+        }
+        for row in y..y_end {
+            unsafe { fill_pixels(self.pixel_ptr(x, row), x_end - x, value) };
+        }
+    }
+
+    /// Fills the whole framebuffer with `color`.
+    pub fn clear(&mut self, color: Color) {
+        let (width, height) = (self.width, self.height);
+        self.fill_rect(0, 0, width, height, color);
+    }
+
+    /// Draws the `width` x `height` image `pixels`, stored row by row, at
+    /// (`x`, `y`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` holds less than `width * height` colors.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, height: usize, pixels: &[Color]) {
+        assert!(pixels.len() >= width * height, "FrameBuffer::blit(): image too small");
+        let x_end = self.width.min(x.saturating_add(width));
+        let y_end = self.height.min(y.saturating_add(height));
+        for row in y..y_end {
+            for col in x..x_end {
+                let value = self.encode(pixels[(row - y) * width + (col - x)]);
+                unsafe { ptr::write_volatile(self.pixel_ptr(col, row), value) };
+            }
+        }
+    }
+
+    /// Copies the `width` x `height` rectangle at (`src_x`, `src_y`) to
+    /// (`dst_x`, `dst_y`). The rectangles may overlap.
+    pub fn copy_rect(
+        &mut self,
+        src_x: usize,
+        src_y: usize,
+        width: usize,
+        height: usize,
+        dst_x: usize,
+        dst_y: usize,
+    ) {
+        let max_x = self.width.saturating_sub(src_x.max(dst_x));
+        let max_y = self.height.saturating_sub(src_y.max(dst_y));
+        let (width, height) = (width.min(max_x), height.min(max_y));
+        if width == 0 {
+            return;
+        }
+        for i in 0..height {
+            // Copy in the direction that never overwrites unread pixels.
+            let row = if dst_y <= src_y { i } else { height - 1 - i };
+            unsafe {
+                copy_pixels(
+                    self.pixel_ptr(src_x, src_y + row),
+                    self.pixel_ptr(dst_x, dst_y + row),
+                    width,
+                );
+            }
+        }
+    }
+}
+
+/// Returns `true` if both `a` and `b` can be accessed two pixels at a time.
+fn word_aligned(a: *const u32, b: *const u32) -> bool {
+    (a as usize | b as usize) % 8 == 0
+}
+
+/// Sets the `len` pixels at `dst` to `value`.
+///
+/// The framebuffer is uncached, so every access is a bus transaction. Pixels
+/// are written two at a time where the alignment allows.
+unsafe fn fill_pixels(dst: *mut u32, len: usize, value: u32) {
+    let mut i = 0;
+    if dst as usize % 8 != 0 && len > 0 {
+        ptr::write_volatile(dst, value);
+        i = 1;
+    }
+    let word = (value as u64) << 32 | value as u64;
+    while i + 2 <= len {
+        ptr::write_volatile(dst.add(i) as *mut u64, word);
+        i += 2;
+    }
+    if i < len {
+        ptr::write_volatile(dst.add(i), value);
+    }
+}
+
+/// Copies the `len` pixels at `src` to `dst`, which may overlap.
+///
+/// Like `fill_pixels()`, pixels are moved two at a time where the alignment
+/// allows. `ptr::copy` is not used, as it may copy byte by byte.
+unsafe fn copy_pixels(src: *const u32, dst: *mut u32, len: usize) {
+    let words = word_aligned(src, dst);
+    if (dst as usize) <= (src as usize) {
+        let mut i = 0;
+        if words {
+            while i + 2 <= len {
+                let word = ptr::read_volatile(src.add(i) as *const u64);
+                ptr::write_volatile(dst.add(i) as *mut u64, word);
+                i += 2;
+            }
+        }
+        while i < len {
+            ptr::write_volatile(dst.add(i), ptr::read_volatile(src.add(i)));
+            i += 1;
+        }
+    } else {
+        let mut i = len;
+        if words {
+            if i % 2 == 1 {
+                i -= 1;
+                ptr::write_volatile(dst.add(i), ptr::read_volatile(src.add(i)));
+            }
+            while i >= 2 {
+                i -= 2;
+                let word = ptr::read_volatile(src.add(i) as *const u64);
+                ptr::write_volatile(dst.add(i) as *mut u64, word);
+            }
+        }
+        while i > 0 {
+            i -= 1;
+            ptr::write_volatile(dst.add(i), ptr::read_volatile(src.add(i)));
+        }
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrameBuffer")
+            .field("base", &format_args!("{:#x}", self.base))
+            .field("size", &self.size)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("pitch", &self.pitch)
+            .field("order", &self.order)
+            .finish()
+    }
+}
@@ -0,0 +1,141 @@
+use core::fmt;
+
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::{Color, FrameBuffer};
+
+/// Width of a character cell in pixels.
+const CELL_WIDTH: usize = GLYPH_WIDTH;
+/// Height of a character cell in pixels. Glyph rows are drawn twice so the
+/// text keeps the proportions of a VGA console.
+const CELL_HEIGHT: usize = GLYPH_HEIGHT * 2;
+
+/// Tab stops are every `TAB_WIDTH` columns.
+const TAB_WIDTH: usize = 8;
+
+/// A scrolling text console drawn on a framebuffer.
+///
+/// Printable ASCII is drawn with a built-in 8x8 font, `\n`, `\r`, backspace
+/// and `\t` move the cursor, and other control characters are ignored. Each
+/// multi-byte UTF-8 character is drawn as a single `?`.
+#[derive(Debug)]
+pub struct TextConsole {
+    fb: FrameBuffer,
+    cols: usize,
+    rows: usize,
+    x: usize,
+    y: usize,
+    fg: Color,
+    bg: Color,
+}
+
+impl TextConsole {
+    /// Returns a console drawing on `fb`, which is cleared, with the cursor
+    /// in the top left corner.
+    pub fn new(fb: FrameBuffer) -> TextConsole {
+        let mut console = TextConsole {
+            cols: fb.width() / CELL_WIDTH,
+            rows: fb.height() / CELL_HEIGHT,
+            fb,
+            x: 0,
+            y: 0,
+            fg: Color::GRAY,
+            bg: Color::BLACK,
+        };
+        console.fb.clear(console.bg);
+        console
+    }
+
+    /// Returns the number of columns and rows of the console.
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Returns the underlying framebuffer.
+    pub fn framebuffer(&mut self) -> &mut FrameBuffer {
+        &mut self.fb
+    }
+
+    /// Draws the characters written from now on in `fg` on `bg`.
+    pub fn set_colors(&mut self, fg: Color, bg: Color) {
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    /// Clears the screen with the background color and moves the cursor to
+    /// the top left corner.
+    pub fn clear(&mut self) {
+        self.fb.clear(self.bg);
+        self.x = 0;
+        self.y = 0;
+    }
+
+    /// Writes the byte `byte` at the cursor, scrolling if needed.
+    pub fn write_byte(&mut self, byte: u8) {
+        if self.cols == 0 || self.rows == 0 {
+            return;
+        }
+        match byte {
+            b'\n' => self.newline(),
+            b'\r' => self.x = 0,
+            // Backspace only moves the cursor; the console echoes "\u{8} \u{8}"
+            // to erase a character.
+            0x08 | 0x7f => self.x = self.x.saturating_sub(1),
+            b'\t' => {
+                let x = (self.x / TAB_WIDTH + 1) * TAB_WIDTH;
+                if x >= self.cols {
+                    self.newline();
+                } else {
+                    self.x = x;
+                }
+            }
+            // UTF-8 continuation bytes.
+            0x80..=0xbf => (),
+            // Printable ASCII and UTF-8 lead bytes, drawn as '?'.
+            0x20..=0x7e | 0xc0..=0xff => {
+                if self.x >= self.cols {
+                    self.newline();
+                }
+                self.draw(self.x, self.y, byte);
+                self.x += 1;
+            }
+            _ => (),
+        }
+    }
+
+    /// Draws the glyph of `byte` in the cell at column `col` of row `row`.
+    fn draw(&mut self, col: usize, row: usize, byte: u8) {
+        let glyph = font::glyph(byte);
+        let mut pixels = [self.bg; CELL_WIDTH * CELL_HEIGHT];
+        for (y, bits) in glyph.iter().enumerate() {
+            for x in (0..GLYPH_WIDTH).filter(|x| bits & (1 << x) != 0) {
+                pixels[2 * y * CELL_WIDTH + x] = self.fg;
+                pixels[(2 * y + 1) * CELL_WIDTH + x] = self.fg;
+            }
+        }
+        self.fb
+            .blit(col * CELL_WIDTH, row * CELL_HEIGHT, CELL_WIDTH, CELL_HEIGHT, &pixels);
+    }
+
+    /// Moves the cursor to the start of the next line, scrolling the screen
+    /// up by one line if the cursor is on the last one.
+    fn newline(&mut self) {
+        self.x = 0;
+        if self.y + 1 < self.rows {
+            self.y += 1;
+            return;
+        }
+        let width = self.cols * CELL_WIDTH;
+        let height = (self.rows - 1) * CELL_HEIGHT;
+        self.fb.copy_rect(0, CELL_HEIGHT, width, height, 0, 0);
+        self.fb.fill_rect(0, height, width, CELL_HEIGHT, self.bg);
+    }
+}
+
+impl fmt::Write for TextConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
@@ -17,6 +17,41 @@ pub enum Function {
     Alt5 = 0b010,
 }
 
+/// A pull-up/pull-down configuration of a GPIO pin.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pull {
+    Off = 0b00,
+    Down = 0b01,
+    Up = 0b10,
+}
+
+/// A condition detected on an input pin. A detected event sets the pin's bit
+/// in the event status register and raises the GPIO interrupts until it is
+/// cleared.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// A low to high transition, sampled with the system clock.
+    RisingEdge,
+    /// A high to low transition, sampled with the system clock.
+    FallingEdge,
+    /// The pin is high. Detected again as soon as the status is cleared
+    /// while the pin stays high.
+    High,
+    /// The pin is low. Detected again as soon as the status is cleared
+    /// while the pin stays low.
+    Low,
+    /// A low to high transition, not synchronized to the system clock, so
+    /// very short pulses are caught too.
+    AsyncRisingEdge,
+    /// A high to low transition, not synchronized to the system clock.
+    AsyncFallingEdge,
+}
+
+/// Number of cycles to wait for the pull-up/pull-down control signal to
+/// settle, from page 101 of the BCM2837 documentation.
+const PUD_SETUP_CYCLES: usize = 150;
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -65,7 +100,75 @@ pub struct Gpio<State> {
     _state: PhantomData<State>,
 }
 
+/// Returns the `(register_index, bit)` of `pin` in the two-word registers.
+#[inline(always)]
+fn bank(pin: u8) -> (usize, u32) {
+    (pin as usize / 32, 1 << (pin as u32 % 32))
+}
+
+fn registers() -> &'static mut Registers {
+    unsafe { &mut *(GPIO_BASE as *mut Registers) }
+}
+
+/// Returns a bit mask of all pins with a detected event. Bit `n` corresponds
+/// to pin `n`.
+pub fn pending_events() -> u64 {
+    let registers = registers();
+    registers.EDS[0].read() as u64 | (registers.EDS[1].read() as u64) << 32
+}
+
+/// Clears the detected events of the pins in the bit mask `pins`.
+pub fn clear_events(pins: u64) {
+    let registers = registers();
+    registers.EDS[0].write(pins as u32);
+    registers.EDS[1].write((pins >> 32) as u32);
+}
+
+/// Disables detection of all events on the pins in the bit mask `pins`,
+/// whatever their function.
+pub fn disable_events(pins: u64) {
+    let registers = registers();
+    for &(register_index, mask) in [(0, pins as u32), (1, (pins >> 32) as u32)].iter() {
+        registers.REN[register_index].and_mask(!mask);
+        registers.FEN[register_index].and_mask(!mask);
+        registers.HEN[register_index].and_mask(!mask);
+        registers.LEN[register_index].and_mask(!mask);
+        registers.AREN[register_index].and_mask(!mask);
+        registers.AFEN[register_index].and_mask(!mask);
+    }
+}
+
 impl<T> Gpio<T> {
+    /// Returns the pin number.
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// Reads the pin's value. Returns `true` if the level is high and `false`
+    /// if the level is low. The level can be read whatever the pin's function.
+    pub fn level(&mut self) -> bool {
+        let register_index = self.pin as usize / 32;
+        let shift = self.pin as u32 - (register_index as u32 * 32);
+        self.registers.LEV[register_index].has_mask(1 << shift)
+    }
+
+    /// Configures the pin's pull-up/pull-down resistor following the sequence
+    /// on page 101 of the BCM2837 documentation. The setting is kept across
+    /// function changes.
+    pub fn set_pull(&mut self, pull: Pull) {
+        let (register_index, bit) = bank(self.pin);
+        self.registers.PUD.write(pull as u32);
+        for _ in 0..PUD_SETUP_CYCLES {
+            aarch64::nop();
+        }
+        self.registers.PUDCLK[register_index].write(bit);
+        for _ in 0..PUD_SETUP_CYCLES {
+            aarch64::nop();
+        }
+        self.registers.PUD.write(0);
+        self.registers.PUDCLK[register_index].write(0);
+    }
+
     /// Transitions `self` to state `S`, consuming `self` and returning a new
     /// `Gpio` instance in state `S`. This method should _never_ be exposed to
     /// the public!
@@ -91,7 +194,7 @@ impl Gpio<Uninitialized> {
         }
 
         Gpio {
-            registers: unsafe { &mut *(GPIO_BASE as *mut Registers) },
+            registers: registers(),
             pin: pin,
             _state: PhantomData,
         }
@@ -140,12 +243,48 @@ impl Gpio<Output> {
     }
 }
 
+
 impl Gpio<Input> {
-    /// Reads the pin's value. Returns `true` if the level is high and `false`
-    /// if the level is low.
-    pub fn level(&mut self) -> bool {
-        let register_index = self.pin as usize / 32;
-        let shift = self.pin as u32 - (register_index as u32 * 32);
-        self.registers.LEV[register_index].has_mask(1 << shift)
+    /// Returns the detect-enable register for `event`.
+    fn event_register(&mut self, event: Event) -> &mut [Volatile<u32>; 2] {
+        match event {
+            Event::RisingEdge => &mut self.registers.REN,
+            Event::FallingEdge => &mut self.registers.FEN,
+            Event::High => &mut self.registers.HEN,
+            Event::Low => &mut self.registers.LEN,
+            Event::AsyncRisingEdge => &mut self.registers.AREN,
+            Event::AsyncFallingEdge => &mut self.registers.AFEN,
+        }
+    }
+
+    /// Enables detection of `event` on this pin. Several events can be
+    /// enabled at once.
+    pub fn enable_event(&mut self, event: Event) {
+        let (register_index, bit) = bank(self.pin);
+        self.event_register(event)[register_index].or_mask(bit);
+    }
+
+    /// Disables detection of `event` on this pin.
+    pub fn disable_event(&mut self, event: Event) {
+        let (register_index, bit) = bank(self.pin);
+        self.event_register(event)[register_index].and_mask(!bit);
+    }
+
+    /// Disables detection of all events on this pin.
+    pub fn disable_events(&mut self) {
+        disable_events(1 << self.pin);
+    }
+
+    /// Returns `true` if an enabled event was detected on this pin since the
+    /// status was last cleared.
+    pub fn event_detected(&mut self) -> bool {
+        let (register_index, bit) = bank(self.pin);
+        self.registers.EDS[register_index].has_mask(bit)
+    }
+
+    /// Clears the pin's event status.
+    pub fn clear_event(&mut self) {
+        let (register_index, bit) = bank(self.pin);
+        self.registers.EDS[register_index].write(bit);
     }
 }
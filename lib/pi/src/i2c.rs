@@ -0,0 +1,296 @@
+use core::time::Duration;
+
+use shim::const_assert_size;
+
+use volatile::prelude::*;
+use volatile::Volatile;
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio, Pull};
+use crate::mailbox::{self, Clock, GetClockRate};
+use crate::timer;
+
+/// Frequency of the core clock the BSC clock is divided from, used when the
+/// firmware cannot be asked for it.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Standard mode bus speed, used until `set_clock()` is called.
+pub const DEFAULT_CLOCK: u32 = 100_000;
+
+/// Longest time a transaction may take before it is aborted, unless changed
+/// with `set_timeout()`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Depth of the controller's FIFO in bytes.
+pub const FIFO_SIZE: usize = 16;
+
+/// Longest transfer the controller can do in one go.
+pub const MAX_TRANSFER_LEN: usize = 0xffff;
+
+/// Enum representing bit fields of the `C` register.
+#[repr(u32)]
+enum ControlBits {
+    Read = 1 << 0,
+    Clear = 0b11 << 4,
+    Start = 1 << 7,
+    Enable = 1 << 15,
+}
+
+/// Enum representing bit fields of the `S` register.
+#[repr(u32)]
+enum StatusBits {
+    Active = 1 << 0,
+    Done = 1 << 1,
+    TxData = 1 << 4,
+    RxData = 1 << 5,
+    Nack = 1 << 8,
+    ClockTimeout = 1 << 9,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    C: Volatile<u32>,
+    S: Volatile<u32>,
+    DLEN: Volatile<u32>,
+    A: Volatile<u32>,
+    FIFO: Volatile<u32>,
+    DIV: Volatile<u32>,
+    DEL: Volatile<u32>,
+    CLKT: Volatile<u32>,
+}
+
+const_assert_size!(Registers, 0x7e804020 - 0x7e804000);
+
+/// The BSC (I2C master) controllers routed to the GPIO header.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Bus {
+    /// BSC0 on GPIO pins 0 (SDA) and 1 (SCL), used for HAT ID EEPROMs.
+    Bsc0,
+    /// BSC1 on GPIO pins 2 (SDA) and 3 (SCL), the I2C bus of the header.
+    Bsc1,
+}
+
+impl Bus {
+    fn base(self) -> usize {
+        match self {
+            Bus::Bsc0 => IO_BASE + 0x20_5000,
+            Bus::Bsc1 => IO_BASE + 0x80_4000,
+        }
+    }
+
+    /// Returns the SDA and SCL pins of the bus.
+    pub fn pins(self) -> (u8, u8) {
+        match self {
+            Bus::Bsc0 => (0, 1),
+            Bus::Bsc1 => (2, 3),
+        }
+    }
+}
+
+/// Errors of an I2C transaction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The device did not acknowledge its address or a written byte.
+    Nack,
+    /// The device stretched the clock for too long.
+    ClockStretchTimeout,
+    /// The transaction did not complete within the timeout.
+    Timeout,
+    /// A buffer is empty or longer than the controller can transfer.
+    InvalidLength,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An I2C master on one of the BSC controllers. Addresses are 7 bits.
+pub struct I2c {
+    registers: &'static mut Registers,
+    bus: Bus,
+    timeout: Duration,
+}
+
+impl I2c {
+    /// Switches the pins of `bus` to the BSC function, with pull-ups, and
+    /// returns the controller running at `DEFAULT_CLOCK`.
+    pub fn new(bus: Bus) -> I2c {
+        let (sda, scl) = bus.pins();
+        for &pin in [sda, scl].iter() {
+            let mut gpio = Gpio::new(pin).into_alt(Function::Alt0);
+            gpio.set_pull(Pull::Up);
+        }
+        let mut i2c = I2c {
+            registers: unsafe { &mut *(bus.base() as *mut Registers) },
+            bus,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        i2c.set_clock(DEFAULT_CLOCK);
+        i2c
+    }
+
+    /// Returns the bus of the controller.
+    pub fn bus(&self) -> Bus {
+        self.bus
+    }
+
+    /// Sets the frequency of SCL to about `hz`. The core clock is asked for
+    /// every time, as the firmware may have changed it.
+    pub fn set_clock(&mut self, hz: u32) {
+        let core_clock = mailbox::request(GetClockRate(Clock::Core)).unwrap_or(DEFAULT_CORE_CLOCK);
+        // The divisor is always rounded down to an even number.
+        let divisor = (core_clock / hz.max(1)).max(2).min(0xfffe) & !1;
+        self.registers.DIV.write(divisor);
+    }
+
+    /// Returns the frequency of SCL.
+    pub fn clock(&self) -> u32 {
+        let core_clock = mailbox::request(GetClockRate(Clock::Core)).unwrap_or(DEFAULT_CORE_CLOCK);
+        match self.registers.DIV.read() {
+            // A divisor of 0 stands for 32768.
+            0 => core_clock / 32768,
+            divisor => core_clock / divisor,
+        }
+    }
+
+    /// Sets the longest time a transaction may take before it is aborted.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Writes `data` to the device at `addr`.
+    pub fn write(&mut self, addr: u8, data: &[u8]) -> Result<()> {
+        check_len(data.len())?;
+        let deadline = timer::current_time() + self.timeout;
+        self.start(addr, data.len(), false);
+        self.fill(data, deadline)?;
+        self.finish(deadline)
+    }
+
+    /// Reads `buf.len()` bytes from the device at `addr` into `buf`.
+    pub fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<()> {
+        check_len(buf.len())?;
+        let deadline = timer::current_time() + self.timeout;
+        self.start(addr, buf.len(), true);
+        self.drain(buf, deadline)?;
+        self.finish(deadline)
+    }
+
+    /// Writes `data` to the device at `addr`, then reads `buf.len()` bytes
+    /// into `buf` after a repeated start, e.g. to read a register. `data`
+    /// must fit in the FIFO.
+    pub fn write_read(&mut self, addr: u8, data: &[u8], buf: &mut [u8]) -> Result<()> {
+        check_len(buf.len())?;
+        if data.is_empty() || data.len() > FIFO_SIZE {
+            return Err(Error::InvalidLength);
+        }
+        let deadline = timer::current_time() + self.timeout;
+        self.clear();
+        self.registers.A.write(addr as u32 & 0x7f);
+        self.registers.DLEN.write(data.len() as u32);
+        for &byte in data {
+            self.registers.FIFO.write(byte as u32);
+        }
+        self.registers.C.write(ControlBits::Enable as u32 | ControlBits::Start as u32);
+        // The controller only sends a repeated start if the read is started
+        // while the write is still active.
+        while !self.registers.S.has_mask(StatusBits::Active as u32) {
+            self.check(deadline)?;
+        }
+        self.registers.DLEN.write(buf.len() as u32);
+        self.registers.C.write(
+            ControlBits::Enable as u32 | ControlBits::Start as u32 | ControlBits::Read as u32,
+        );
+        self.drain(buf, deadline)?;
+        self.finish(deadline)
+    }
+
+    /// Clears the FIFO and the status flags of a previous transaction.
+    fn clear(&mut self) {
+        self.registers.C.write(ControlBits::Clear as u32);
+        self.registers.S.write(
+            StatusBits::Done as u32 | StatusBits::Nack as u32 | StatusBits::ClockTimeout as u32,
+        );
+    }
+
+    /// Starts a transfer of `len` bytes to or from `addr`.
+    fn start(&mut self, addr: u8, len: usize, read: bool) {
+        self.clear();
+        self.registers.A.write(addr as u32 & 0x7f);
+        self.registers.DLEN.write(len as u32);
+        let mut control = ControlBits::Enable as u32 | ControlBits::Start as u32;
+        if read {
+            control |= ControlBits::Read as u32;
+        }
+        self.registers.C.write(control);
+    }
+
+    /// Returns the error the controller reported for the current
+    /// transaction, if any.
+    fn status_error(&self) -> Option<Error> {
+        let status = self.registers.S.read();
+        if status & StatusBits::Nack as u32 != 0 {
+            Some(Error::Nack)
+        } else if status & StatusBits::ClockTimeout as u32 != 0 {
+            Some(Error::ClockStretchTimeout)
+        } else {
+            None
+        }
+    }
+
+    /// Aborts the current transaction with `error`.
+    fn abort(&mut self, error: Error) -> Result<()> {
+        self.clear();
+        self.registers.C.write(0);
+        Err(error)
+    }
+
+    /// Aborts the current transaction if it failed or `deadline` passed.
+    fn check(&mut self, deadline: Duration) -> Result<()> {
+        match self.status_error() {
+            Some(error) => self.abort(error),
+            None if timer::current_time() > deadline => self.abort(Error::Timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// Feeds `data` to the FIFO as it drains.
+    fn fill(&mut self, data: &[u8], deadline: Duration) -> Result<()> {
+        for &byte in data {
+            while !self.registers.S.has_mask(StatusBits::TxData as u32) {
+                self.check(deadline)?;
+            }
+            self.registers.FIFO.write(byte as u32);
+        }
+        Ok(())
+    }
+
+    /// Reads bytes from the FIFO into `buf` as they arrive.
+    fn drain(&mut self, buf: &mut [u8], deadline: Duration) -> Result<()> {
+        for byte in buf.iter_mut() {
+            while !self.registers.S.has_mask(StatusBits::RxData as u32) {
+                self.check(deadline)?;
+            }
+            *byte = self.registers.FIFO.read() as u8;
+        }
+        Ok(())
+    }
+
+    /// Waits for the end of the current transaction.
+    fn finish(&mut self, deadline: Duration) -> Result<()> {
+        while !self.registers.S.has_mask(StatusBits::Done as u32) {
+            self.check(deadline)?;
+        }
+        if let Some(error) = self.status_error() {
+            return self.abort(error);
+        }
+        self.registers.S.write(StatusBits::Done as u32);
+        Ok(())
+    }
+}
+
+fn check_len(len: usize) -> Result<()> {
+    match len {
+        1..=MAX_TRANSFER_LEN => Ok(()),
+        _ => Err(Error::InvalidLength),
+    }
+}
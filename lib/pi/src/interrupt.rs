@@ -10,19 +10,21 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
     Gpio3 = 52,
+    Spi = 54,
     Uart = 57,
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 10;
 
     pub fn iter() -> impl Iterator<Item = Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
+        [Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Spi, Uart]
             .iter()
             .map(|int| *int)
     }
@@ -35,10 +37,12 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
             52 => Gpio3,
+            54 => Spi,
             57 => Uart,
             _ => panic!("Unknown irq: {}", irq),
         }
@@ -8,9 +8,14 @@
 
 pub mod atags;
 pub mod common;
+pub mod framebuffer;
 pub mod gpio;
+pub mod i2c;
 pub mod interrupt;
 pub mod local_interrupt;
+pub mod mailbox;
+pub mod pm;
 pub mod rng;
+pub mod spi;
 pub mod timer;
 pub mod uart;
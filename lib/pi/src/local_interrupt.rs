@@ -81,8 +81,10 @@ struct Registers {
     core_mailboxes_interrupt_control: [Volatile<u32>; 4],
     core_irq_source: [Volatile<u32>; 4],
     core_fiq_source: [Volatile<u32>; 4],
+    core_mailboxes_write_set: [[Volatile<u32>; 4]; 4],
+    core_mailboxes_read_clear: [[Volatile<u32>; 4]; 4],
 }
-const_assert_size!(Registers, 0x4000_0080 - 0x4000_0000);
+const_assert_size!(Registers, 0x4000_0100 - 0x4000_0000);
 
 
 pub struct LocalController {
@@ -107,6 +109,27 @@ impl LocalController {
         self.registers.core_timer_interrupt_control[self.core].write(1 << 1);
     }
 
+    /// Enables the IRQ of `mailbox` (0 to 3) of this core. The interrupt stays
+    /// pending while any bit of the mailbox is set. (QA7: 4.7)
+    pub fn enable_mailbox(&mut self, mailbox: usize) {
+        let control = &mut self.registers.core_mailboxes_interrupt_control[self.core];
+        let enabled = control.read();
+        control.write(enabled | (1 << mailbox));
+    }
+
+    /// Sets `bits` in `mailbox` of core `core`, interrupting that core if
+    /// it has the mailbox enabled.
+    pub fn send_mailbox(&mut self, core: usize, mailbox: usize, bits: u32) {
+        self.registers.core_mailboxes_write_set[core][mailbox].write(bits);
+    }
+
+    /// Clears `mailbox` of this core and returns the bits that were set.
+    pub fn take_mailbox(&mut self, mailbox: usize) -> u32 {
+        let bits = self.registers.core_mailboxes_read_clear[self.core][mailbox].read();
+        self.registers.core_mailboxes_read_clear[self.core][mailbox].write(bits);
+        bits
+    }
+
     pub fn is_pending(&self, int: LocalInterrupt) -> bool {
         let index = int as usize;
         self.registers.core_irq_source[self.core].has_mask(1 << index)
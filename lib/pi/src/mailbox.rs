@@ -0,0 +1,588 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use shim::const_assert_size;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+use crate::common::IO_BASE;
+
+/// The base address of the VideoCore mailbox 0 registers.
+const MAILBOX_BASE: usize = IO_BASE + 0xB880;
+
+/// The channel of the property-tag interface, ARM to VideoCore.
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// Size of a cache line, used to clean and invalidate the message buffer.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Number of 32-bit words in a message buffer.
+const BUFFER_WORDS: usize = 256;
+
+/// Request/response code of a message that has not been processed yet.
+const CODE_REQUEST: u32 = 0;
+/// Response code of a message processed successfully.
+const CODE_SUCCESS: u32 = 0x8000_0000;
+/// Bit set in a tag's request/response code once the VideoCore responded.
+const TAG_RESPONSE: u32 = 1 << 31;
+/// The tag ending a message.
+const TAG_END: u32 = 0;
+
+/// Enum representing bit fields of the mailbox `STATUS` register.
+#[repr(u32)]
+enum Status {
+    Full = 1 << 31,
+    Empty = 1 << 30,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 3],
+    PEEK: ReadVolatile<u32>,
+    SENDER: ReadVolatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONFIG: Volatile<u32>,
+    /// Mailbox 1, written by the ARM and read by the VideoCore.
+    WRITE: Volatile<u32>,
+    __r1: [Reserved<u32>; 5],
+    WRITE_STATUS: ReadVolatile<u32>,
+}
+
+const_assert_size!(Registers, 0x7e00b8bc - 0x7e00b880);
+
+/// Errors of the property-tag interface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The tags do not fit in the message buffer.
+    BufferFull,
+    /// The VideoCore did not process the message.
+    RequestFailed,
+    /// The VideoCore did not respond to the tag, e.g. because it does not
+    /// support it.
+    TagFailed,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A property tag: a request to the VideoCore with a typed response.
+pub trait Tag {
+    /// The tag identifier.
+    const ID: u32;
+    /// Size of the tag's value buffer in 32-bit words, large enough for both
+    /// the request and the response.
+    const WORDS: usize;
+    /// The decoded response.
+    type Response;
+
+    /// Writes the request values to `values`.
+    fn request(&self, _values: &mut [u32]) {}
+
+    /// Decodes the response values.
+    fn response(values: &[u32]) -> Self::Response;
+}
+
+/// Clocks whose rate can be queried and set.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Emmc2 = 12,
+}
+
+/// Devices whose power can be switched.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Device {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// A region of memory, as reported by `GetArmMemory` and `GetVcMemory`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+
+/// Gets the VideoCore firmware revision.
+pub struct GetFirmwareRevision;
+
+impl Tag for GetFirmwareRevision {
+    const ID: u32 = 0x0000_0001;
+    const WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+/// Gets the board revision code.
+pub struct GetBoardRevision;
+
+impl Tag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    const WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+/// Gets the MAC address of the on-board network adapter.
+pub struct GetMacAddress;
+
+impl Tag for GetMacAddress {
+    const ID: u32 = 0x0001_0003;
+    const WORDS: usize = 2;
+    type Response = [u8; 6];
+
+    fn response(values: &[u32]) -> [u8; 6] {
+        let low = values[0].to_le_bytes();
+        let high = values[1].to_le_bytes();
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+}
+
+/// Gets the board serial number.
+pub struct GetBoardSerial;
+
+impl Tag for GetBoardSerial {
+    const ID: u32 = 0x0001_0004;
+    const WORDS: usize = 2;
+    type Response = u64;
+
+    fn response(values: &[u32]) -> u64 {
+        values[0] as u64 | (values[1] as u64) << 32
+    }
+}
+
+/// Gets the memory assigned to the ARM cores.
+pub struct GetArmMemory;
+
+impl Tag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    const WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn response(values: &[u32]) -> MemoryRegion {
+        MemoryRegion { base: values[0], size: values[1] }
+    }
+}
+
+/// Gets the memory assigned to the VideoCore.
+pub struct GetVcMemory;
+
+impl Tag for GetVcMemory {
+    const ID: u32 = 0x0001_0006;
+    const WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn response(values: &[u32]) -> MemoryRegion {
+        MemoryRegion { base: values[0], size: values[1] }
+    }
+}
+
+/// Turns a device on or off. Responds with `true` if the device is on
+/// afterwards.
+pub struct SetPowerState {
+    pub device: Device,
+    pub on: bool,
+    /// Wait for the power to become stable before responding.
+    pub wait: bool,
+}
+
+impl Tag for SetPowerState {
+    const ID: u32 = 0x0002_8001;
+    const WORDS: usize = 2;
+    type Response = bool;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.device as u32;
+        values[1] = self.on as u32 | (self.wait as u32) << 1;
+    }
+
+    fn response(values: &[u32]) -> bool {
+        values[1] & 1 != 0
+    }
+}
+
+/// Gets the rate of a clock in Hz.
+pub struct GetClockRate(pub Clock);
+
+impl Tag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    const WORDS: usize = 2;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Gets the maximum rate of a clock in Hz.
+pub struct GetMaxClockRate(pub Clock);
+
+impl Tag for GetMaxClockRate {
+    const ID: u32 = 0x0003_0004;
+    const WORDS: usize = 2;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Sets the rate of a clock in Hz. Responds with the rate actually set.
+pub struct SetClockRate {
+    pub clock: Clock,
+    pub rate: u32,
+    /// Do not change the turbo setting along with the rate.
+    pub skip_turbo: bool,
+}
+
+impl Tag for SetClockRate {
+    const ID: u32 = 0x0003_8002;
+    const WORDS: usize = 3;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.clock as u32;
+        values[1] = self.rate;
+        values[2] = self.skip_turbo as u32;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Gets the SoC temperature in thousandths of a degree Celsius.
+pub struct GetTemperature;
+
+impl Tag for GetTemperature {
+    const ID: u32 = 0x0003_0006;
+    const WORDS: usize = 2;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Order of the color components of a pixel in memory.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelOrder {
+    Bgr = 0,
+    Rgb = 1,
+}
+
+/// Allocates the framebuffer with the given alignment in bytes. Responds with
+/// its bus address and size.
+pub struct AllocateBuffer {
+    pub alignment: u32,
+}
+
+impl Tag for AllocateBuffer {
+    const ID: u32 = 0x0004_0001;
+    const WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.alignment;
+    }
+
+    fn response(values: &[u32]) -> MemoryRegion {
+        MemoryRegion { base: values[0], size: values[1] }
+    }
+}
+
+/// Gets the number of bytes per framebuffer line.
+pub struct GetPitch;
+
+impl Tag for GetPitch {
+    const ID: u32 = 0x0004_0008;
+    const WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+/// Sets the size of the display in pixels. Responds with the size set.
+pub struct SetPhysicalSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tag for SetPhysicalSize {
+    const ID: u32 = 0x0004_8003;
+    const WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.width;
+        values[1] = self.height;
+    }
+
+    fn response(values: &[u32]) -> (u32, u32) {
+        (values[0], values[1])
+    }
+}
+
+/// Sets the size of the framebuffer in pixels, which may exceed the display.
+/// Responds with the size set.
+pub struct SetVirtualSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tag for SetVirtualSize {
+    const ID: u32 = 0x0004_8004;
+    const WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.width;
+        values[1] = self.height;
+    }
+
+    fn response(values: &[u32]) -> (u32, u32) {
+        (values[0], values[1])
+    }
+}
+
+/// Sets the number of bits per pixel. Responds with the depth set.
+pub struct SetDepth(pub u32);
+
+impl Tag for SetDepth {
+    const ID: u32 = 0x0004_8005;
+    const WORDS: usize = 1;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+/// Sets the order of the color components. Responds with the order set.
+pub struct SetPixelOrder(pub PixelOrder);
+
+impl Tag for SetPixelOrder {
+    const ID: u32 = 0x0004_8006;
+    const WORDS: usize = 1;
+    type Response = PixelOrder;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn response(values: &[u32]) -> PixelOrder {
+        match values[0] {
+            0 => PixelOrder::Bgr,
+            _ => PixelOrder::Rgb,
+        }
+    }
+}
+
+/// Sets the position of the display within the framebuffer.
+pub struct SetVirtualOffset {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Tag for SetVirtualOffset {
+    const ID: u32 = 0x0004_8009;
+    const WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.x;
+        values[1] = self.y;
+    }
+
+    fn response(values: &[u32]) -> (u32, u32) {
+        (values[0], values[1])
+    }
+}
+
+/// Where a tag was placed in a `Message`, used to get its response.
+pub struct Slot<T: Tag> {
+    /// Index of the tag's identifier in the message buffer.
+    index: usize,
+    _tag: PhantomData<T>,
+}
+
+/// The message buffer. The VideoCore requires 16-byte alignment, since the
+/// low 4 bits of the address carry the channel.
+#[repr(C, align(16))]
+struct Buffer([u32; BUFFER_WORDS]);
+
+/// A property-tag message: tags are pushed, the message is sent, and each
+/// tag's response is read back through the `Slot` returned by `push()`.
+///
+/// ```rust,ignore
+/// let mut message = Message::new();
+/// let revision = message.push(GetBoardRevision)?;
+/// let memory = message.push(GetArmMemory)?;
+/// message.send()?;
+/// let revision = message.get(&revision)?;
+/// ```
+pub struct Message {
+    buffer: Buffer,
+    /// Number of words used by the header and the tags pushed so far.
+    len: usize,
+}
+
+impl Message {
+    /// Returns an empty message.
+    pub fn new() -> Message {
+        Message {
+            buffer: Buffer([0; BUFFER_WORDS]),
+            len: 2,
+        }
+    }
+
+    /// Appends `tag` to the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BufferFull` if the tag does not fit.
+    pub fn push<T: Tag>(&mut self, tag: T) -> Result<Slot<T>> {
+        let index = self.len;
+        // The end tag needs one more word.
+        if index + 3 + T::WORDS + 1 > BUFFER_WORDS {
+            return Err(Error::BufferFull);
+        }
+        let buf = &mut self.buffer.0;
+        buf[index] = T::ID;
+        buf[index + 1] = (T::WORDS * 4) as u32;
+        buf[index + 2] = CODE_REQUEST;
+        tag.request(&mut buf[index + 3..index + 3 + T::WORDS]);
+        self.len += 3 + T::WORDS;
+        Ok(Slot { index, _tag: PhantomData })
+    }
+
+    /// Sends the message to the VideoCore and waits for the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::RequestFailed` if the VideoCore did not process the
+    /// message.
+    pub fn send(&mut self) -> Result<()> {
+        let len = self.len;
+        let buf = &mut self.buffer.0;
+        buf[len] = TAG_END;
+        buf[0] = ((len + 1) * 4) as u32;
+        buf[1] = CODE_REQUEST;
+
+        let address = buf.as_ptr() as usize;
+        clean_and_invalidate(address, (len + 1) * 4);
+        call(PROPERTY_CHANNEL, address as u32);
+        clean_and_invalidate(address, (len + 1) * 4);
+
+        match buf[1] {
+            CODE_SUCCESS => Ok(()),
+            _ => Err(Error::RequestFailed),
+        }
+    }
+
+    /// Returns the response to the tag in `slot`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::TagFailed` if the VideoCore did not respond to the tag.
+    pub fn get<T: Tag>(&self, slot: &Slot<T>) -> Result<T::Response> {
+        let buf = &self.buffer.0;
+        let code = buf[slot.index + 2];
+        if code & TAG_RESPONSE == 0 {
+            return Err(Error::TagFailed);
+        }
+        let values = &buf[slot.index + 3..slot.index + 3 + T::WORDS];
+        Ok(T::response(values))
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.buffer.0[..self.len].iter()).finish()
+    }
+}
+
+/// Sends a message consisting of `tag` alone and returns its response.
+pub fn request<T: Tag>(tag: T) -> Result<T::Response> {
+    let mut message = Message::new();
+    let slot = message.push(tag)?;
+    message.send()?;
+    message.get(&slot)
+}
+
+/// Held by the core in `call()`. A message and its response must not be
+/// interleaved with another core's, which could otherwise take the response
+/// meant for this one off the mailbox.
+static CALL_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Writes `data` to mailbox `channel` and waits for the response on the same
+/// channel. `data` must be 16-byte aligned. Must be called with the MMU on,
+/// since the lock uses exclusive accesses.
+fn call(channel: u32, data: u32) {
+    while CALL_LOCK.compare_and_swap(false, true, Ordering::Acquire) {
+        continue
+    }
+    let registers = unsafe { &mut *(MAILBOX_BASE as *mut Registers) };
+    while registers.WRITE_STATUS.has_mask(Status::Full as u32) {
+        continue
+    }
+    registers.WRITE.write(data | channel);
+    loop {
+        while registers.STATUS.has_mask(Status::Empty as u32) {
+            continue
+        }
+        if registers.READ.read() & 0xf == channel {
+            break;
+        }
+    }
+    CALL_LOCK.store(false, Ordering::Release);
+}
+
+/// Writes the cache lines covering `len` bytes at `address` back to memory
+/// and invalidates them, so the VideoCore and the cores see the same data.
+fn clean_and_invalidate(address: usize, len: usize) {
+    let start = address & !(CACHE_LINE_SIZE - 1);
+    for line in (start..address + len).step_by(CACHE_LINE_SIZE) {
+        unsafe { asm!("dc civac, $0" :: "r"(line) :: "volatile") };
+    }
+    unsafe { asm!("dsb sy" :::: "volatile") };
+}
@@ -0,0 +1,113 @@
+use core::time::Duration;
+
+use crate::common::IO_BASE;
+use shim::const_assert_size;
+
+use volatile::prelude::*;
+use volatile::{Reserved, Volatile};
+
+/// The base address of the power management (PM) registers.
+const PM_REG_BASE: usize = IO_BASE + 0x10_0000;
+
+/// Every write to a PM register must carry this password in its top byte.
+const PASSWORD: u32 = 0x5a00_0000;
+
+/// `RSTC` bits selecting the kind of reset done when the watchdog expires.
+const RSTC_WRCFG_MASK: u32 = 0b11 << 4;
+const RSTC_WRCFG_FULL_RESET: u32 = 0b10 << 4;
+const RSTC_RESET: u32 = 0x0000_0102;
+
+/// `RSTS` bits holding the partition the firmware boots from. Partition 63
+/// asks the firmware to halt instead of booting.
+const RSTS_PARTITION_MASK: u32 = 0x555;
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+/// The watchdog counts down in ticks of 1/65536 of a second.
+const WDOG_TICKS_PER_SEC: u64 = 1 << 16;
+const WDOG_TIME_MASK: u32 = 0x000f_ffff;
+
+/// The longest timeout the watchdog can be started with, a little under 16
+/// seconds.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    __r0: [Reserved<u32>; 7],
+    RSTC: Volatile<u32>,
+    RSTS: Volatile<u32>,
+    WDOG: Volatile<u32>,
+}
+
+const_assert_size!(Registers, 0x28);
+
+/// The watchdog of the power management block. Once started, it resets the
+/// board unless it is restarted or stopped before its timeout expires.
+pub struct Watchdog {
+    registers: &'static mut Registers,
+}
+
+impl Watchdog {
+    /// Returns a new instance of `Watchdog`.
+    pub fn new() -> Watchdog {
+        Watchdog {
+            registers: unsafe { &mut *(PM_REG_BASE as *mut Registers) },
+        }
+    }
+
+    /// Starts the watchdog, or restarts it if it is running, so that it
+    /// resets the board after `timeout`. Timeouts longer than `MAX_TIMEOUT`
+    /// are shortened to `MAX_TIMEOUT`.
+    pub fn start(&mut self, timeout: Duration) {
+        let timeout = timeout.min(MAX_TIMEOUT);
+        let ticks = (timeout.as_micros() as u64 * WDOG_TICKS_PER_SEC / 1_000_000) as u32;
+        let rstc = self.registers.RSTC.read() & !RSTC_WRCFG_MASK;
+        self.registers.WDOG.write(PASSWORD | (ticks & WDOG_TIME_MASK));
+        self.registers.RSTC.write(PASSWORD | rstc | RSTC_WRCFG_FULL_RESET);
+    }
+
+    /// Stops the watchdog.
+    pub fn stop(&mut self) {
+        self.registers.RSTC.write(PASSWORD | RSTC_RESET);
+    }
+
+    /// Returns `true` if the watchdog is running.
+    pub fn is_running(&self) -> bool {
+        self.registers.RSTC.read() & RSTC_WRCFG_MASK == RSTC_WRCFG_FULL_RESET
+    }
+
+    /// Returns the time left before the watchdog resets the board.
+    pub fn time_left(&self) -> Duration {
+        let ticks = (self.registers.WDOG.read() & WDOG_TIME_MASK) as u64;
+        Duration::from_micros(ticks * 1_000_000 / WDOG_TICKS_PER_SEC)
+    }
+
+    /// Sets the partition the firmware boots from after the next reset.
+    fn set_boot_partition(&mut self, partition: u32) {
+        let rsts = self.registers.RSTS.read() & !RSTS_PARTITION_MASK;
+        self.registers.RSTS.write(PASSWORD | rsts | partition);
+    }
+}
+
+/// Resets the board right away.
+pub fn reset() -> ! {
+    let mut watchdog = Watchdog::new();
+    watchdog.set_boot_partition(0);
+    restart(watchdog)
+}
+
+/// Stops the board: it is reset, and the firmware halts instead of booting
+/// the kernel again. The board boots again once its power is cycled.
+pub fn halt() -> ! {
+    let mut watchdog = Watchdog::new();
+    watchdog.set_boot_partition(RSTS_PARTITION_HALT);
+    restart(watchdog)
+}
+
+/// Starts `watchdog` with a very short timeout and waits for the reset.
+fn restart(mut watchdog: Watchdog) -> ! {
+    watchdog.start(Duration::from_micros(150));
+    loop {
+        aarch64::wfe();
+    }
+}
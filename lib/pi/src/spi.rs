@@ -0,0 +1,233 @@
+use shim::const_assert_size;
+
+use volatile::prelude::*;
+use volatile::Volatile;
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::interrupt::Interrupt;
+use crate::mailbox::{self, Clock, GetClockRate};
+
+/// The base address for the `SPI0` registers.
+const SPI0_REG_BASE: usize = IO_BASE + 0x20_4000;
+
+/// Frequency of the core clock the SPI clock is divided from, used when the
+/// firmware cannot be asked for it.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Clock used until `set_clock()` is called.
+pub const DEFAULT_CLOCK: u32 = 1_000_000;
+
+/// Depth of each of the controller's FIFOs in bytes.
+pub const FIFO_SIZE: usize = 64;
+
+/// The interrupt raised by the controller.
+pub const INTERRUPT: Interrupt = Interrupt::Spi;
+
+/// GPIO pins of SPI0: CE1, CE0, MISO, MOSI and SCLK.
+pub const PINS: [u8; 5] = [7, 8, 9, 10, 11];
+
+/// Enum representing bit fields of the `CS` register.
+#[repr(u32)]
+enum CsBits {
+    ChipSelect = 0b11,
+    Cpha = 1 << 2,
+    Cpol = 1 << 3,
+    Clear = 0b11 << 4,
+    Active = 1 << 7,
+    IntDone = 1 << 9,
+    IntRx = 1 << 10,
+    Done = 1 << 16,
+    RxData = 1 << 17,
+    TxData = 1 << 18,
+}
+
+/// Position of the active-high flag of chip select line 0 in `CS`. The flag
+/// of line 1 follows it.
+const CSPOL_SHIFT: u32 = 21;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CS: Volatile<u32>,
+    FIFO: Volatile<u32>,
+    CLK: Volatile<u32>,
+    DLEN: Volatile<u32>,
+    LTOH: Volatile<u32>,
+    DC: Volatile<u32>,
+}
+
+const_assert_size!(Registers, 0x7e204018 - 0x7e204000);
+
+/// Clock polarity and phase.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// Clock idles low, data is sampled on the rising edge.
+    Mode0,
+    /// Clock idles low, data is sampled on the falling edge.
+    Mode1,
+    /// Clock idles high, data is sampled on the falling edge.
+    Mode2,
+    /// Clock idles high, data is sampled on the rising edge.
+    Mode3,
+}
+
+/// The chip select line asserted during transfers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChipSelect {
+    /// CE0 on GPIO pin 8.
+    Ce0 = 0,
+    /// CE1 on GPIO pin 7.
+    Ce1 = 1,
+    /// No line is asserted, e.g. because the device's chip select is driven
+    /// with a GPIO pin.
+    None = 3,
+}
+
+/// Progress of a full-duplex transfer driven by `Spi::poll_transfer()`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Progress {
+    written: usize,
+    read: usize,
+}
+
+impl Progress {
+    /// Returns the number of bytes exchanged so far.
+    pub fn done(&self) -> usize {
+        self.read
+    }
+}
+
+/// The SPI0 master.
+///
+/// Transfers are full-duplex: a byte is received for every byte sent. They
+/// can be polled to completion with `transfer()`, or driven from the
+/// controller's interrupt with `start_transfer()` and `poll_transfer()`.
+pub struct Spi {
+    registers: &'static mut Registers,
+}
+
+impl Spi {
+    /// Switches the SPI0 pins to their SPI function and returns the master
+    /// in mode 0 at `DEFAULT_CLOCK`, asserting CE0.
+    pub fn new() -> Spi {
+        for &pin in PINS.iter() {
+            Gpio::new(pin).into_alt(Function::Alt0);
+        }
+        let mut spi = Spi {
+            registers: unsafe { &mut *(SPI0_REG_BASE as *mut Registers) },
+        };
+        spi.registers.CS.write(CsBits::Clear as u32);
+        spi.set_clock(DEFAULT_CLOCK);
+        spi
+    }
+
+    /// Sets the clock polarity and phase.
+    pub fn set_mode(&mut self, mode: Mode) {
+        let bits = match mode {
+            Mode::Mode0 => 0,
+            Mode::Mode1 => CsBits::Cpha as u32,
+            Mode::Mode2 => CsBits::Cpol as u32,
+            Mode::Mode3 => CsBits::Cpol as u32 | CsBits::Cpha as u32,
+        };
+        let cs = self.registers.CS.read() & !(CsBits::Cpol as u32 | CsBits::Cpha as u32);
+        self.registers.CS.write(cs | bits);
+    }
+
+    /// Selects the chip select line asserted during transfers and whether it
+    /// is active high.
+    pub fn set_chip_select(&mut self, chip_select: ChipSelect, active_high: bool) {
+        let mut cs = self.registers.CS.read() & !(CsBits::ChipSelect as u32);
+        cs |= chip_select as u32;
+        if chip_select != ChipSelect::None {
+            let polarity = 1 << (CSPOL_SHIFT + chip_select as u32);
+            match active_high {
+                true => cs |= polarity,
+                false => cs &= !polarity,
+            }
+        }
+        self.registers.CS.write(cs);
+    }
+
+    /// Sets the clock to at most `hz`. The core clock is asked for every
+    /// time, as the firmware may have changed it.
+    pub fn set_clock(&mut self, hz: u32) {
+        let core_clock = mailbox::request(GetClockRate(Clock::Core)).unwrap_or(DEFAULT_CORE_CLOCK);
+        // Round the divisor up to an even number so the clock is never
+        // faster than asked for.
+        let divisor = (core_clock + hz.max(1) - 1) / hz.max(1);
+        let divisor = ((divisor + 1) & !1).max(2).min(0xfffe);
+        self.registers.CLK.write(divisor);
+    }
+
+    /// Returns the frequency of the clock.
+    pub fn clock(&self) -> u32 {
+        let core_clock = mailbox::request(GetClockRate(Clock::Core)).unwrap_or(DEFAULT_CORE_CLOCK);
+        match self.registers.CLK.read() {
+            // A divisor of 0 stands for 65536.
+            0 => core_clock / 65536,
+            divisor => core_clock / divisor,
+        }
+    }
+
+    /// Sends `tx` while receiving `rx.len()` bytes into `rx`, polling until
+    /// done. `tx` is padded with zeros if it is shorter than `rx`, and the
+    /// bytes received past the end of `rx` are dropped.
+    pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) {
+        let mut progress = Progress::default();
+        self.start_transfer(false);
+        while !self.poll_transfer(tx, rx, &mut progress) {
+            continue;
+        }
+    }
+
+    /// Asserts the chip select line and starts a transfer, clearing the
+    /// FIFOs. If `interrupt` is `true`, the controller raises `INTERRUPT`
+    /// whenever `poll_transfer()` should be called.
+    pub fn start_transfer(&mut self, interrupt: bool) {
+        let mut cs = self.registers.CS.read() & !(CsBits::IntDone as u32 | CsBits::IntRx as u32);
+        cs |= CsBits::Clear as u32 | CsBits::Active as u32;
+        if interrupt {
+            cs |= CsBits::IntDone as u32 | CsBits::IntRx as u32;
+        }
+        self.registers.CS.write(cs);
+    }
+
+    /// Moves as many bytes as the FIFOs allow for the transfer started with
+    /// `start_transfer()` of `tx` and `rx` (see `transfer()`), recording how
+    /// far it got in `progress`. Returns `true` once the transfer is
+    /// complete, after deasserting the chip select line and turning the
+    /// interrupt off.
+    pub fn poll_transfer(&mut self, tx: &[u8], rx: &mut [u8], progress: &mut Progress) -> bool {
+        let len = tx.len().max(rx.len());
+        while progress.read < len && self.registers.CS.has_mask(CsBits::RxData as u32) {
+            let byte = self.registers.FIFO.read() as u8;
+            if let Some(slot) = rx.get_mut(progress.read) {
+                *slot = byte;
+            }
+            progress.read += 1;
+        }
+        // Never have more bytes in flight than the receive FIFO holds.
+        while progress.written < len
+            && progress.written - progress.read < FIFO_SIZE
+            && self.registers.CS.has_mask(CsBits::TxData as u32)
+        {
+            let byte = tx.get(progress.written).cloned().unwrap_or(0);
+            self.registers.FIFO.write(byte as u32);
+            progress.written += 1;
+        }
+        if progress.read < len || !self.registers.CS.has_mask(CsBits::Done as u32) {
+            return false;
+        }
+        self.end_transfer();
+        true
+    }
+
+    /// Deasserts the chip select line and turns the interrupt off, aborting
+    /// the transfer in progress if any.
+    pub fn end_transfer(&mut self) {
+        let cs = self.registers.CS.read()
+            & !(CsBits::Active as u32 | CsBits::IntDone as u32 | CsBits::IntRx as u32);
+        self.registers.CS.write(cs | CsBits::Clear as u32);
+    }
+}
@@ -9,8 +9,13 @@ use volatile::{ReadVolatile, Reserved, Volatile};
 
 use crate::common::IO_BASE;
 use crate::gpio::{Function, Gpio};
+use crate::interrupt::Interrupt;
 use crate::timer;
 
+mod pl011;
+
+pub use self::pl011::{Config, FifoLevel, LineErrors, Parity, Pl011, StopBits};
+
 /// The base address for the `MU` registers.
 const MU_REG_BASE: usize = IO_BASE + 0x215040;
 
@@ -24,6 +29,16 @@ enum LsrStatus {
     TxAvailable = 1 << 5,
 }
 
+/// Enum representing bit fields of the `AUX_MU_IER_REG` register. The BCM2837
+/// documentation has the receive and transmit bits swapped (see the errata).
+/// Bits 2 and 3 have to be set for the mini UART to raise interrupts at all.
+#[repr(u8)]
+enum IerBits {
+    RxInterrupt = 1,
+    TxInterrupt = 1 << 1,
+    Required = 0b11 << 2,
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -69,13 +84,126 @@ struct Registers {
 
 const_assert_size!(Registers, 0x7e21506c - 0x7e215040);
 
+/// Operations shared by the Raspberry Pi's UARTs, so that the console and the
+/// bootloader can use either the `MiniUart` or the `Pl011`.
+pub trait Uart: io::Read + io::Write + fmt::Write {
+    /// The interrupt the UART raises.
+    const INTERRUPT: Interrupt;
+
+    /// Initializes the UART for 8N1 at ~115200 baud and routes it to GPIO
+    /// pins 14 and 15.
+    fn new() -> Self
+    where
+        Self: Sized;
+
+    /// Set the read timeout to `t` duration.
+    fn set_read_timeout(&mut self, t: Duration);
+
+    /// Returns the read timeout, if any.
+    fn read_timeout(&self) -> Option<Duration>;
+
+    /// Write the byte `byte`. This method blocks until there is space available
+    /// in the output FIFO.
+    fn write_byte(&mut self, byte: u8);
+
+    /// Returns `true` if there is space in the output FIFO. If this method
+    /// returns `true`, a subsequent call to `write_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    fn can_write(&self) -> bool;
+
+    /// Returns `true` if there is at least one byte ready to be read. If this
+    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    fn has_byte(&self) -> bool;
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    fn read_byte(&mut self) -> u8;
+
+    /// Enables or disables the interrupt raised while received bytes are
+    /// waiting in the input FIFO.
+    fn set_rx_interrupt(&mut self, enable: bool);
+
+    /// Enables or disables the interrupt raised when the output FIFO has
+    /// drained.
+    fn set_tx_interrupt(&mut self, enable: bool);
+
+    /// Blocks until there is a byte ready to read. If a read timeout is set,
+    /// this method blocks for at most that amount of time. Otherwise, this
+    /// method blocks indefinitely until there is a byte to read.
+    ///
+    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
+    /// timeout expired while waiting for a byte to be ready. If this method
+    /// returns `Ok(())`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately.
+    fn wait_for_byte(&self) -> Result<(), ()> {
+        match self.read_timeout() {
+            Some(timeout) => {
+                let timeout_time = timer::current_time() + timeout;
+                while timer::current_time() < timeout_time {
+                    if self.has_byte() {
+                        return Ok(())
+                    }
+                }
+                Err(())
+            }
+            None => {
+                if self.has_byte() {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            }
+        }
+    }
+}
+
+/// `fmt::Write::write_str()` for a `Uart`, translating `\n` to `\r\n`.
+fn write_str<U: Uart>(uart: &mut U, s: &str) -> fmt::Result {
+    for &byte in s.as_bytes() {
+        if byte == b'\n' {
+            uart.write_byte(b'\r');
+        }
+        uart.write_byte(byte);
+    }
+    Ok(())
+}
+
+/// `io::Read::read()` for a `Uart`: waits for the first byte, then reads
+/// every byte already received.
+fn read<U: Uart>(uart: &mut U, buf: &mut [u8]) -> io::Result<usize> {
+    match uart.wait_for_byte() {
+        Ok(_) => {
+            let mut byte_count = 0;
+            while uart.has_byte() && byte_count < buf.len() {
+                buf[byte_count] = uart.read_byte();
+                byte_count += 1;
+            }
+            Ok(byte_count)
+        }
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "UART read timed out",
+        ))
+    }
+}
+
+/// `io::Write::write()` for a `Uart`.
+fn write<U: Uart>(uart: &mut U, buf: &[u8]) -> io::Result<usize> {
+    for &byte in buf {
+        uart.write_byte(byte);
+    }
+    Ok(buf.len())
+}
+
 /// The Raspberry Pi's "mini UART".
 pub struct MiniUart {
     registers: &'static mut Registers,
     timeout: Option<Duration>,
 }
 
-impl MiniUart {
+impl Uart for MiniUart {
+    const INTERRUPT: Interrupt = Interrupt::Aux;
+
     /// Initializes the mini UART by enabling it as an auxiliary peripheral,
     /// setting the data size to 8 bits, setting the BAUD rate to ~115200 (baud
     /// divider of 270), setting GPIO pins 14 and 15 to alternative function 5
@@ -83,7 +211,7 @@ impl MiniUart {
     ///
     /// By default, reads will never time out. To set a read timeout, use
     /// `set_read_timeout()`.
-    pub fn new() -> MiniUart {
+    fn new() -> MiniUart {
         let registers = unsafe {
             // Enable the mini UART as an auxiliary device.
             (*AUX_ENABLES).or_mask(1);
@@ -103,125 +231,78 @@ impl MiniUart {
         }
     }
 
-    /// Set the read timeout to `t` duration.
-    pub fn set_read_timeout(&mut self, t: Duration) {
+    fn set_read_timeout(&mut self, t: Duration) {
         self.timeout = Some(t);
     }
 
-    /// Write the byte `byte`. This method blocks until there is space available
-    /// in the output FIFO.
-    pub fn write_byte(&mut self, byte: u8) {
-        while !self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8) {
+    fn read_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while !self.can_write() {
             continue
         }
         self.registers.IO.write(byte);
     }
 
-    /// Returns `true` if there is at least one byte ready to be read. If this
-    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
-    /// return immediately. This method does not block.
-    pub fn has_byte(&self) -> bool {
-        self.registers.LSR.has_mask(LsrStatus::DataReady as u8)
+    fn can_write(&self) -> bool {
+        self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8)
     }
 
-    /// Blocks until there is a byte ready to read. If a read timeout is set,
-    /// this method blocks for at most that amount of time. Otherwise, this
-    /// method blocks indefinitely until there is a byte to read.
-    ///
-    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
-    /// timeout expired while waiting for a byte to be ready. If this method
-    /// returns `Ok(())`, a subsequent call to `read_byte` is guaranteed to
-    /// return immediately.
-    pub fn wait_for_byte(&self) -> Result<(), ()> {
-        match self.timeout {
-            Some(timeout) => {
-                let timeout_time = timer::current_time() + timeout;
-                while timer::current_time() < timeout_time {
-                    if self.has_byte() {
-                        return Ok(())
-                    }
-                }
-                Err(())
-            }
-            None => {
-                if self.has_byte() {
-                    Ok(())
-                } else {
-                    Err(())
-                }
-            }
-        }
+    fn has_byte(&self) -> bool {
+        self.registers.LSR.has_mask(LsrStatus::DataReady as u8)
     }
 
-    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
-    pub fn read_byte(&mut self) -> u8 {
+    fn read_byte(&mut self) -> u8 {
         while !self.has_byte() {
             continue
         }
         self.registers.IO.read()
     }
+
+    /// Enables or disables the interrupt raised while the input FIFO holds at
+    /// least one byte.
+    fn set_rx_interrupt(&mut self, enable: bool) {
+        self.set_interrupt(IerBits::RxInterrupt, enable);
+    }
+
+    /// Enables or disables the interrupt raised while the output FIFO is
+    /// empty.
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        self.set_interrupt(IerBits::TxInterrupt, enable);
+    }
+}
+
+impl MiniUart {
+    fn set_interrupt(&mut self, bit: IerBits, enable: bool) {
+        let ier = self.registers.IER.read();
+        let ier = match enable {
+            true => ier | bit as u8 | IerBits::Required as u8,
+            false => ier & !(bit as u8),
+        };
+        self.registers.IER.write(ier);
+    }
 }
 
 impl fmt::Write for MiniUart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for &byte in s.as_bytes() {
-            if byte == b'\n' {
-                self.write_byte(b'\r');
-            }
-            self.write_byte(byte);
-        }
-        Ok(())
+        write_str(self, s)
     }
+}
 
-    fn write_char(&mut self, c: char) -> fmt::Result {
-        let mut buf = [0; 4];
-        for &byte in c.encode_utf8(&mut buf).as_bytes() {
-            if byte == b'\n' {
-                self.write_byte(b'\r');
-            }
-            self.write_byte(byte);
-        }
-        Ok(())
+impl io::Read for MiniUart {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read(self, buf)
     }
 }
 
-mod uart_io {
-    use super::io;
-    use super::MiniUart;
-    use volatile::prelude::*;
-
-    impl io::Read for MiniUart {
-        fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-            let wait = self.wait_for_byte();
-            match wait {
-                Ok(_) => {
-                    let mut byte_count = 0;
-                    while self.has_byte() && byte_count < buf.len() {
-                        buf[byte_count] = self.read_byte();
-                        byte_count += 1;
-                    }
-                    Ok(byte_count)
-                }
-                Err(_) => Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    "MiniUart read timed out",
-                ))
-            }
-        }
+impl io::Write for MiniUart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write(self, buf)
     }
 
-    impl io::Write for MiniUart {
-        fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-            let mut byte_count= 0;
-            for byte in buf {
-                self.write_byte(*byte);
-                byte_count += 1;
-            }
-            Ok(byte_count)
-        }
-
-        fn flush(&mut self) -> Result<(), io::Error> {
-            Ok(())
-        }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
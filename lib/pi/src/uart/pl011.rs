@@ -0,0 +1,347 @@
+use core::fmt;
+use core::time::Duration;
+
+use shim::const_assert_size;
+use shim::io;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::interrupt::Interrupt;
+
+use super::Uart;
+
+/// The base address for the `PL011` registers.
+const PL011_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// Default frequency of `UARTCLK` set by the firmware on the Raspberry Pi 3.
+pub const DEFAULT_CLOCK: u32 = 48_000_000;
+
+/// Enum representing bit fields of the `UARTFR` register.
+#[repr(u32)]
+enum FrBits {
+    Busy = 1 << 3,
+    RxEmpty = 1 << 4,
+    TxFull = 1 << 5,
+}
+
+/// Enum representing the error bits returned alongside each byte by the
+/// `UARTDR` register.
+#[repr(u32)]
+enum DrBits {
+    Framing = 1 << 8,
+    Parity = 1 << 9,
+    Break = 1 << 10,
+    Overrun = 1 << 11,
+}
+
+/// Enum representing bit fields of the `UARTLCR_H` register.
+#[repr(u32)]
+enum LcrhBits {
+    ParityEnable = 1 << 1,
+    EvenParity = 1 << 2,
+    TwoStopBits = 1 << 3,
+    FifoEnable = 1 << 4,
+    WordLength8 = 0b11 << 5,
+}
+
+/// Enum representing bit fields of the `UARTCR` register.
+#[repr(u32)]
+enum CrBits {
+    Enable = 1,
+    TxEnable = 1 << 8,
+    RxEnable = 1 << 9,
+    RtsEnable = 1 << 14,
+    CtsEnable = 1 << 15,
+}
+
+/// Enum representing bit fields of the `UARTIMSC`, `UARTMIS` and `UARTICR`
+/// registers.
+#[repr(u32)]
+enum IntBits {
+    Rx = 1 << 4,
+    Tx = 1 << 5,
+    RxTimeout = 1 << 6,
+    All = 0x7ff,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>,
+    RSRECR: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    ILPR: Reserved<u32>,
+    IBRD: Volatile<u32>,
+    FBRD: Volatile<u32>,
+    LCRH: Volatile<u32>,
+    CR: Volatile<u32>,
+    IFLS: Volatile<u32>,
+    IMSC: Volatile<u32>,
+    RIS: ReadVolatile<u32>,
+    MIS: ReadVolatile<u32>,
+    ICR: WriteVolatile<u32>,
+    DMACR: Volatile<u32>,
+}
+
+const_assert_size!(Registers, 0x7e20104c - 0x7e201000);
+
+/// Parity bit sent with every character.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits sent after every character.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// FIFO fill level at which the receive or transmit interrupt is raised. The
+/// FIFOs are 16 bytes deep.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FifoLevel {
+    OneEighth = 0,
+    OneQuarter = 1,
+    Half = 2,
+    ThreeQuarters = 3,
+    SevenEighths = 4,
+}
+
+/// Line settings of a `Pl011`.
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    pub baud_rate: u32,
+    /// Frequency of `UARTCLK` in Hz, which the baud rate is derived from.
+    pub clock: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// Use the RTS/CTS lines on GPIO pins 17 and 16 for hardware flow
+    /// control.
+    pub flow_control: bool,
+    /// The receive interrupt is raised once the input FIFO is filled to this
+    /// level, or when bytes sit in it for 32 bit periods.
+    pub rx_fifo_level: FifoLevel,
+    /// The transmit interrupt is raised once the output FIFO drains to this
+    /// level.
+    pub tx_fifo_level: FifoLevel,
+}
+
+impl Default for Config {
+    /// 115200 baud, 8N1, no flow control.
+    fn default() -> Config {
+        Config {
+            baud_rate: 115200,
+            clock: DEFAULT_CLOCK,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: false,
+            rx_fifo_level: FifoLevel::OneEighth,
+            tx_fifo_level: FifoLevel::OneEighth,
+        }
+    }
+}
+
+/// Receive errors reported by the UART since they were last taken.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LineErrors {
+    /// A byte was received without a valid stop bit.
+    pub framing: bool,
+    /// A byte was received with the wrong parity.
+    pub parity: bool,
+    /// The input was held low for longer than a full character.
+    pub break_condition: bool,
+    /// A byte was received while the input FIFO was full and got lost.
+    pub overrun: bool,
+}
+
+impl LineErrors {
+    /// Returns `true` if no error was reported.
+    pub fn is_empty(&self) -> bool {
+        !(self.framing || self.parity || self.break_condition || self.overrun)
+    }
+}
+
+/// The Raspberry Pi's PL011 UART.
+///
+/// On the Raspberry Pi 3 the PL011 is wired to the Bluetooth module by
+/// default. `dtoverlay=disable-bt` (or `miniuart-bt`) in `config.txt` routes it
+/// to GPIO pins 14 and 15 instead. QEMU's `raspi3` machine connects it to the
+/// first serial port.
+pub struct Pl011 {
+    registers: &'static mut Registers,
+    timeout: Option<Duration>,
+    errors: LineErrors,
+}
+
+impl Pl011 {
+    /// Initializes the PL011 with `config`: the UART is disabled while the
+    /// baud rate divisor, line settings and FIFO levels are programmed, GPIO
+    /// pins 14 and 15 (and 16 and 17 with flow control) are switched to the
+    /// UART, and finally the transmitter and receiver are enabled.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `set_read_timeout()`.
+    pub fn with_config(config: Config) -> Pl011 {
+        let registers = unsafe { &mut *(PL011_REG_BASE as *mut Registers) };
+
+        registers.CR.write(0);
+        while registers.FR.has_mask(FrBits::Busy as u32) {
+            continue
+        }
+        // Flushes the FIFOs.
+        registers.LCRH.write(0);
+
+        Gpio::new(14).into_alt(Function::Alt0);
+        Gpio::new(15).into_alt(Function::Alt0);
+        if config.flow_control {
+            Gpio::new(16).into_alt(Function::Alt3);
+            Gpio::new(17).into_alt(Function::Alt3);
+        }
+
+        registers.ICR.write(IntBits::All as u32);
+        registers.IMSC.write(0);
+
+        // The divisor is UARTCLK / (16 * baud), with a 6-bit fraction.
+        let baud = config.baud_rate as u64;
+        let divisor = (config.clock as u64 * 4 + baud / 2) / baud;
+        registers.IBRD.write((divisor >> 6) as u32);
+        registers.FBRD.write((divisor & 0x3f) as u32);
+
+        let mut lcrh = LcrhBits::WordLength8 as u32 | LcrhBits::FifoEnable as u32;
+        lcrh |= match config.parity {
+            Parity::None => 0,
+            Parity::Even => LcrhBits::ParityEnable as u32 | LcrhBits::EvenParity as u32,
+            Parity::Odd => LcrhBits::ParityEnable as u32,
+        };
+        if config.stop_bits == StopBits::Two {
+            lcrh |= LcrhBits::TwoStopBits as u32;
+        }
+        registers.LCRH.write(lcrh);
+        registers.IFLS.write((config.rx_fifo_level as u32) << 3 | config.tx_fifo_level as u32);
+
+        let mut cr = CrBits::Enable as u32 | CrBits::TxEnable as u32 | CrBits::RxEnable as u32;
+        if config.flow_control {
+            cr |= CrBits::RtsEnable as u32 | CrBits::CtsEnable as u32;
+        }
+        registers.CR.write(cr);
+
+        Pl011 {
+            registers,
+            timeout: None,
+            errors: LineErrors::default(),
+        }
+    }
+
+    /// Returns the receive errors reported since the last call and clears
+    /// them.
+    pub fn take_errors(&mut self) -> LineErrors {
+        core::mem::replace(&mut self.errors, LineErrors::default())
+    }
+
+    fn set_interrupt(&mut self, bits: u32, enable: bool) {
+        let imsc = self.registers.IMSC.read();
+        let imsc = match enable {
+            true => imsc | bits,
+            false => imsc & !bits,
+        };
+        self.registers.IMSC.write(imsc);
+    }
+}
+
+impl Uart for Pl011 {
+    const INTERRUPT: Interrupt = Interrupt::Uart;
+
+    /// Initializes the PL011 with the default `Config`.
+    fn new() -> Pl011 {
+        Pl011::with_config(Config::default())
+    }
+
+    fn set_read_timeout(&mut self, t: Duration) {
+        self.timeout = Some(t);
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while !self.can_write() {
+            continue
+        }
+        self.registers.DR.write(byte as u32);
+    }
+
+    fn can_write(&self) -> bool {
+        !self.registers.FR.has_mask(FrBits::TxFull as u32)
+    }
+
+    fn has_byte(&self) -> bool {
+        !self.registers.FR.has_mask(FrBits::RxEmpty as u32)
+    }
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    /// Errors flagged with the byte are recorded for `take_errors()`.
+    fn read_byte(&mut self) -> u8 {
+        while !self.has_byte() {
+            continue
+        }
+        let data = self.registers.DR.read();
+        if data & DrBits::Framing as u32 != 0 {
+            self.errors.framing = true;
+        }
+        if data & DrBits::Parity as u32 != 0 {
+            self.errors.parity = true;
+        }
+        if data & DrBits::Break as u32 != 0 {
+            self.errors.break_condition = true;
+        }
+        if data & DrBits::Overrun as u32 != 0 {
+            self.errors.overrun = true;
+        }
+        data as u8
+    }
+
+    /// Enables or disables the interrupts raised when the input FIFO reaches
+    /// its fill level and when received bytes are left sitting in it.
+    fn set_rx_interrupt(&mut self, enable: bool) {
+        self.set_interrupt(IntBits::Rx as u32 | IntBits::RxTimeout as u32, enable);
+    }
+
+    /// Enables or disables the interrupt raised when the output FIFO drains
+    /// to its fill level. It is only raised on the transition, so the FIFO
+    /// should be filled before enabling it.
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        self.set_interrupt(IntBits::Tx as u32, enable);
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        super::write_str(self, s)
+    }
+}
+
+impl io::Read for Pl011 {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        super::read(self, buf)
+    }
+}
+
+impl io::Write for Pl011 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        super::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
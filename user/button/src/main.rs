@@ -0,0 +1,55 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use core::time::Duration;
+
+use kernel_api::syscall::{gpio_claim, gpio_configure, gpio_wait, gpio_write};
+use kernel_api::{println, OsError, OsResult};
+use kernel_api::{GPIO_FALLING, GPIO_INPUT, GPIO_OUTPUT, GPIO_PULL_OFF, GPIO_PULL_UP};
+use bw_allocator::Allocator;
+
+#[global_allocator]
+pub static A: Allocator = Allocator::new();
+
+/// Pin driving the LED.
+const LED_PIN: u64 = 16;
+/// Pin connected to a push button that shorts it to ground.
+const BUTTON_PIN: u64 = 20;
+/// The program exits when the button is not pressed for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    if let Err(error) = main_inner() {
+        println!("button: {:?}", error);
+    }
+}
+
+/// Toggles the LED on every press of the button. The pins are released by
+/// the kernel when the program exits.
+fn main_inner() -> OsResult<()> {
+    gpio_claim(LED_PIN)?;
+    gpio_claim(BUTTON_PIN)?;
+    gpio_configure(LED_PIN, GPIO_OUTPUT, GPIO_PULL_OFF)?;
+    gpio_configure(BUTTON_PIN, GPIO_INPUT, GPIO_PULL_UP)?;
+
+    println!("Press the button on GPIO {} to toggle the LED on GPIO {}.", BUTTON_PIN, LED_PIN);
+    let mut on = false;
+    let mut presses = 0;
+    loop {
+        match gpio_wait(BUTTON_PIN, GPIO_FALLING, Some(IDLE_TIMEOUT)) {
+            Ok(_) => (),
+            Err(OsError::IoErrorTimedOut) => break,
+            Err(e) => return Err(e),
+        }
+        on = !on;
+        presses += 1;
+        gpio_write(LED_PIN, on)?;
+    }
+    println!("{} presses, bye.", presses);
+    Ok(())
+}
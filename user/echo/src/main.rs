@@ -10,7 +10,7 @@ mod cr0;
 use core::time::Duration;
 
 use kernel_api::syscall::*;
-use kernel_api::{print, println, OsResult, SocketStatus, OsError};
+use kernel_api::{print, println, OsResult, SocketStatus, OsError, PollFd, POLLOUT};
 use bw_allocator::Allocator;
 
 #[global_allocator]
@@ -32,21 +32,20 @@ fn main() {
 fn main_inner() -> OsResult<!> {
     let socket = sock_create();
     sock_listen(socket, 80)?;
-    let mut status = sock_status(socket)?;
-    while !status.can_send {
+    let mut fds = [PollFd::new(socket.raw(), POLLOUT)];
+    while poll(&mut fds, Some(Duration::from_secs(10)))? == 0 {
+        let status = sock_status(socket)?;
         let mut s = String::new();
         write!(s, "Waiting for {:?}: {:?} to be able to send.\r\n", socket, status);
         print!("{}", s);
-        let _sleep_duration = sleep(Duration::from_secs(1))?;
-        status = sock_status(socket)?;
     }
     let message = "Welcome to Echo server hosted on RustOS!\r\n";
     let _bytes_sent = sock_send(socket, message.as_bytes())?;
     loop {
         let mut buf = [0u8; 1024];
-        let _bytes_recvd = sock_recv(socket, &mut buf)?;
-        let in_message = core::str::from_utf8(&buf).map_err(|_| OsError::IoErrorInvalidData)?;
+        let bytes_recvd = sock_recv(socket, &mut buf)?;
+        let in_message = core::str::from_utf8(&buf[..bytes_recvd]).map_err(|_| OsError::IoErrorInvalidData)?;
         print!("{}", in_message);
-        let _bytes_sent = sock_send(socket, &buf)?;
+        let _bytes_sent = sock_send(socket, &buf[..bytes_recvd])?;
     }
 }
@@ -0,0 +1,78 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use kernel_api::syscall::{console_mode, console_release, rand, read};
+use kernel_api::{print, println, OsResult, CONSOLE_DEFAULT, STDIN};
+use bw_allocator::Allocator;
+
+#[global_allocator]
+pub static A: Allocator = Allocator::new();
+
+/// Smallest number to guess.
+const MIN: u32 = 1;
+/// Largest number to guess.
+const MAX: u32 = 100;
+
+fn main() {
+    if let Err(error) = main_inner() {
+        println!("guess: {:?}", error);
+    }
+    console_release();
+}
+
+fn main_inner() -> OsResult<()> {
+    loop {
+        play()?;
+
+        // Raw mode without echo: a single key press answers.
+        print!("Play again? [y/n] ");
+        console_mode(0)?;
+        let mut key = [0u8; 1];
+        let answer = read(STDIN, &mut key);
+        console_mode(CONSOLE_DEFAULT)?;
+        println!("");
+        match answer? {
+            1 if key[0] == b'y' || key[0] == b'Y' => continue,
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Plays one round, reading a line for each guess.
+fn play() -> OsResult<()> {
+    let secret = rand(MIN, MAX + 1);
+    let mut tries = 0;
+    println!("I picked a number between {} and {}.", MIN, MAX);
+    loop {
+        print!("Your guess: ");
+        let mut line = [0u8; 32];
+        let len = read(STDIN, &mut line)?;
+        if len == 0 {
+            println!("");
+            return Ok(());
+        }
+        let guess = match core::str::from_utf8(&line[..len]).ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+        {
+            Some(guess) => guess,
+            None => {
+                println!("Please enter a number.");
+                continue;
+            }
+        };
+        tries += 1;
+        if guess < secret {
+            println!("Higher.");
+        } else if guess > secret {
+            println!("Lower.");
+        } else {
+            println!("Got it in {} tries!", tries);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,77 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use core::time::Duration;
+
+use kernel_api::syscall::{ping, sleep, time};
+use kernel_api::{println, OsError};
+use bw_allocator::Allocator;
+
+#[global_allocator]
+pub static A: Allocator = Allocator::new();
+
+/// The host to ping.
+const TARGET: (u8, u8, u8, u8) = (192, 168, 254, 1);
+/// Number of echo requests to send.
+const COUNT: u16 = 4;
+/// Time between two echo requests.
+const INTERVAL: Duration = Duration::from_secs(1);
+/// Time to wait for each echo reply.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn main() {
+    let (a, b, c, d) = TARGET;
+    println!("PING {}.{}.{}.{}", a, b, c, d);
+
+    let mut received = 0u16;
+    let mut rtt_min = Duration::from_secs(u64::max_value());
+    let mut rtt_max = Duration::from_secs(0);
+    let mut rtt_sum = Duration::from_secs(0);
+    let beg = time();
+
+    for seq_no in 1..=COUNT {
+        match ping(TARGET, seq_no, TIMEOUT) {
+            Ok(reply) => {
+                received += 1;
+                rtt_min = rtt_min.min(reply.rtt);
+                rtt_max = rtt_max.max(reply.rtt);
+                rtt_sum += reply.rtt;
+                println!(
+                    "reply from {}.{}.{}.{}: icmp_seq={} ttl={} time={}.{:03} ms",
+                    a, b, c, d,
+                    seq_no,
+                    reply.ttl,
+                    reply.rtt.as_micros() / 1000,
+                    reply.rtt.as_micros() % 1000
+                );
+            }
+            Err(OsError::IoErrorTimedOut) => println!("request timeout for icmp_seq={}", seq_no),
+            Err(e) => println!("ping: icmp_seq={}: {:?}", seq_no, e),
+        }
+        if seq_no < COUNT {
+            let _ = sleep(INTERVAL);
+        }
+    }
+
+    let elapsed = time() - beg;
+    let loss = (COUNT - received) as u32 * 100 / COUNT as u32;
+    println!("--- {}.{}.{}.{} ping statistics ---", a, b, c, d);
+    println!(
+        "{} packets transmitted, {} received, {}% packet loss, time {} ms",
+        COUNT, received, loss, elapsed.as_millis()
+    );
+    if received > 0 {
+        let rtt_avg = rtt_sum / received as u32;
+        println!(
+            "rtt min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms",
+            rtt_min.as_micros() / 1000, rtt_min.as_micros() % 1000,
+            rtt_avg.as_micros() / 1000, rtt_avg.as_micros() % 1000,
+            rtt_max.as_micros() / 1000, rtt_max.as_micros() % 1000
+        );
+    }
+}